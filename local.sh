@@ -7,4 +7,8 @@ export MONGODB_NOTE_COLLECTION=notes
 export POSTGRES_USER=postgres
 export POSTGRES_PASSWORD=postgres
 export POSTGRES_DB=postgres
-export POSTGRES_URL=localhost
\ No newline at end of file
+export POSTGRES_URL=localhost
+export JWT_SECRET=change-me
+export ADMIN_USERNAME=admin
+export ADMIN_PASSWORD=change-me
+export ADMIN_SCOPE="admin customers:read customers:write customers:delete orders:read orders:write orders:delete products:read products:write products:delete tenants:*"
\ No newline at end of file
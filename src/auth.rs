@@ -0,0 +1,548 @@
+use crate::helper::Config;
+use crate::store::DynUserStore;
+use crate::{Error, Result};
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, State};
+use axum::http::header::{AUTHORIZATION, COOKIE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Cookie holding the access token, sent on every `/api` request.
+pub const ACCESS_TOKEN_COOKIE: &str = "access_token";
+/// Cookie holding the refresh token, only sent to `/api/auth`.
+pub const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+
+/// Header machine clients send their API key in.
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// Start of every API key, so that leaked keys are easy to search for.
+const API_KEY_PREFIX: &str = "crk_";
+/// Characters of a key shown when keys are listed.
+const API_KEY_SHOWN_LENGTH: usize = API_KEY_PREFIX.len() + 8;
+
+const DEFAULT_ACCESS_TOKEN_TTL: u64 = 15 * 60;
+const DEFAULT_REFRESH_TOKEN_TTL: u64 = 14 * 24 * 60 * 60;
+
+/// The authenticated caller of a request, `require_auth` puts it into the
+/// request extensions and handlers extract it like any other argument.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ctx {
+    subject: String,
+    scopes: Vec<String>,
+    tenant: Option<String>,
+}
+
+impl Ctx {
+    pub fn new(subject: String, scopes: Vec<String>) -> Self {
+        Self {
+            subject,
+            scopes,
+            tenant: None,
+        }
+    }
+
+    /// Binds the caller to `tenant`, it can then act on no other.
+    pub fn with_tenant(mut self, tenant: Option<String>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|own| own == scope)
+    }
+
+    /// The tenant the credentials are bound to, `None` for callers of the
+    /// default tenant and for operators holding `tenants:*`.
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Ctx
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        parts
+            .extensions
+            .get::<Ctx>()
+            .cloned()
+            .ok_or(Error::AuthFailCtxNotInRequestExt)
+    }
+}
+
+/// Claims read from an access token, `scope` holds the scopes separated by
+/// spaces like an OAuth 2.0 scope parameter, and `tenant` binds the bearer to
+/// a tenant.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    #[serde(default)]
+    pub scope: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+/// Verifies the signature and expiry of bearer tokens, either HS256 with a
+/// shared secret or RS256 with the public key of the issuer.
+#[derive(Clone)]
+pub struct TokenVerifier {
+    key: Arc<DecodingKey>,
+    validation: Arc<Validation>,
+}
+
+impl std::fmt::Debug for TokenVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenVerifier")
+            .field("algorithms", &self.validation.algorithms)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reads `JWT_ALGORITHM`, HS256 when unset.
+fn algorithm_from_config(config: &Config) -> Result<Algorithm> {
+    let algorithm = config
+        .get_optional_config("JWT_ALGORITHM")
+        .map(|algorithm| algorithm.trim().to_uppercase());
+
+    match algorithm.as_deref() {
+        None | Some("HS256") => Ok(Algorithm::HS256),
+        Some("RS256") => Ok(Algorithm::RS256),
+        Some(other) => Err(Error::ConfigInvalid {
+            name: "JWT_ALGORITHM".to_string(),
+            value: other.to_string(),
+        }),
+    }
+}
+
+/// Reads a number of seconds, `default` when unset.
+fn ttl_from_config(config: &Config, name: &str, default: u64) -> Result<u64> {
+    match config.get_optional_config(name) {
+        Some(value) => {
+            value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|ttl| *ttl > 0)
+                .ok_or(Error::ConfigInvalid {
+                    name: name.to_string(),
+                    value,
+                })
+        }
+        None => Ok(default),
+    }
+}
+
+fn now() -> u64 {
+    jsonwebtoken::get_current_timestamp()
+}
+
+impl TokenVerifier {
+    fn new(key: DecodingKey, algorithm: Algorithm) -> Self {
+        let mut validation = Validation::new(algorithm);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        // the audience is only checked when one is configured, see `with_audience`
+        validation.validate_aud = false;
+
+        Self {
+            key: Arc::new(key),
+            validation: Arc::new(validation),
+        }
+    }
+
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self::new(DecodingKey::from_secret(secret), Algorithm::HS256)
+    }
+
+    /// `pem` is the PEM encoded RSA public key of the token issuer.
+    pub fn rs256(pem: &[u8]) -> Result<Self> {
+        let key = DecodingKey::from_rsa_pem(pem).map_err(|_| Error::ConfigInvalid {
+            name: "JWT_PUBLIC_KEY".to_string(),
+            value: "<not an RSA public key in PEM format>".to_string(),
+        })?;
+
+        Ok(Self::new(key, Algorithm::RS256))
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        Arc::make_mut(&mut self.validation).set_issuer(&[issuer]);
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        let validation = Arc::make_mut(&mut self.validation);
+        validation.set_audience(&[audience]);
+        validation.validate_aud = true;
+        self
+    }
+
+    /// Reads `JWT_ALGORITHM` (`HS256` by default), then `JWT_SECRET` for HS256
+    /// or `JWT_PUBLIC_KEY` for RS256, and the optional `JWT_ISSUER` and
+    /// `JWT_AUDIENCE` the tokens must carry.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut verifier = match algorithm_from_config(config)? {
+            Algorithm::RS256 => Self::rs256(config.get_config("JWT_PUBLIC_KEY")?.as_bytes())?,
+            _ => Self::hs256(config.get_config("JWT_SECRET")?.trim().as_bytes()),
+        };
+        if let Some(issuer) = config.get_optional_config("JWT_ISSUER") {
+            verifier = verifier.with_issuer(issuer.trim());
+        }
+        if let Some(audience) = config.get_optional_config("JWT_AUDIENCE") {
+            verifier = verifier.with_audience(audience.trim());
+        }
+
+        Ok(verifier)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Ctx> {
+        let claims = decode::<Claims>(token, &self.key, &self.validation)
+            .map_err(|e| {
+                tracing::info!("Rejected access token: {}", e);
+                Error::AuthFailTokenWrongFormat
+            })?
+            .claims;
+
+        Ok(Ctx::new(
+            claims.sub,
+            claims
+                .scope
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+        )
+        .with_tenant(claims.tenant))
+    }
+}
+
+/// Claims written into the access tokens this service issues.
+#[derive(Serialize)]
+struct IssuedClaims<'a> {
+    sub: &'a str,
+    iat: u64,
+    exp: u64,
+    scope: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iss: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aud: Option<&'a str>,
+}
+
+/// Signs the access tokens handed out by `/api/auth/login`, with the same
+/// algorithm, issuer and audience the `TokenVerifier` checks.
+#[derive(Clone)]
+pub struct TokenIssuer {
+    key: Arc<EncodingKey>,
+    header: Header,
+    issuer: Option<String>,
+    audience: Option<String>,
+    ttl: u64,
+}
+
+impl std::fmt::Debug for TokenIssuer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenIssuer")
+            .field("algorithm", &self.header.alg)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenIssuer {
+    fn new(key: EncodingKey, algorithm: Algorithm) -> Self {
+        Self {
+            key: Arc::new(key),
+            header: Header::new(algorithm),
+            issuer: None,
+            audience: None,
+            ttl: DEFAULT_ACCESS_TOKEN_TTL,
+        }
+    }
+
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self::new(EncodingKey::from_secret(secret), Algorithm::HS256)
+    }
+
+    /// `pem` is the PEM encoded RSA private key matching `JWT_PUBLIC_KEY`.
+    pub fn rs256(pem: &[u8]) -> Result<Self> {
+        let key = EncodingKey::from_rsa_pem(pem).map_err(|_| Error::ConfigInvalid {
+            name: "JWT_PRIVATE_KEY".to_string(),
+            value: "<not an RSA private key in PEM format>".to_string(),
+        })?;
+
+        Ok(Self::new(key, Algorithm::RS256))
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: u64) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Seconds an issued access token stays valid.
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
+
+    /// Reads the same settings as `TokenVerifier::from_config`, with
+    /// `JWT_PRIVATE_KEY` in place of `JWT_PUBLIC_KEY` for RS256, and
+    /// `ACCESS_TOKEN_TTL` in seconds (15 minutes by default).
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut issuer = match algorithm_from_config(config)? {
+            Algorithm::RS256 => Self::rs256(config.get_config("JWT_PRIVATE_KEY")?.as_bytes())?,
+            _ => Self::hs256(config.get_config("JWT_SECRET")?.trim().as_bytes()),
+        };
+        if let Some(name) = config.get_optional_config("JWT_ISSUER") {
+            issuer = issuer.with_issuer(name.trim());
+        }
+        if let Some(audience) = config.get_optional_config("JWT_AUDIENCE") {
+            issuer = issuer.with_audience(audience.trim());
+        }
+
+        Ok(issuer.with_ttl(ttl_from_config(
+            config,
+            "ACCESS_TOKEN_TTL",
+            DEFAULT_ACCESS_TOKEN_TTL,
+        )?))
+    }
+
+    pub fn issue(&self, subject: &str, scope: &str, tenant: Option<&str>) -> Result<String> {
+        let iat = now();
+        let claims = IssuedClaims {
+            sub: subject,
+            iat,
+            exp: iat + self.ttl,
+            scope,
+            tenant,
+            iss: self.issuer.as_deref(),
+            aud: self.audience.as_deref(),
+        };
+
+        encode(&self.header, &claims, &self.key).map_err(|e| {
+            tracing::error!("Failed to sign access token: {}", e);
+            Error::HandlerError
+        })
+    }
+}
+
+/// Everything needed to verify and hand out tokens.
+#[derive(Clone, Debug)]
+pub struct Tokens {
+    pub verifier: TokenVerifier,
+    pub issuer: TokenIssuer,
+    /// Seconds a refresh token stays valid.
+    pub refresh_ttl: u64,
+}
+
+impl Tokens {
+    /// HS256 tokens with the default lifetimes.
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self {
+            verifier: TokenVerifier::hs256(secret),
+            issuer: TokenIssuer::hs256(secret),
+            refresh_ttl: DEFAULT_REFRESH_TOKEN_TTL,
+        }
+    }
+
+    /// See `TokenVerifier::from_config` and `TokenIssuer::from_config`,
+    /// `REFRESH_TOKEN_TTL` is in seconds (14 days by default).
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            verifier: TokenVerifier::from_config(config)?,
+            issuer: TokenIssuer::from_config(config)?,
+            refresh_ttl: ttl_from_config(config, "REFRESH_TOKEN_TTL", DEFAULT_REFRESH_TOKEN_TTL)?,
+        })
+    }
+}
+
+/// Hashes a password with Argon2id into a PHC string, on the blocking pool
+/// since a hash takes tens of milliseconds on purpose.
+pub async fn hash_password(password: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::encode_b64(&rand::thread_rng().gen::<[u8; 16]>())
+            .map_err(|_| Error::HandlerError)?;
+
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| {
+                tracing::error!("Failed to hash password: {}", e);
+                Error::HandlerError
+            })
+    })
+    .await
+    .map_err(|_| Error::HandlerError)?
+}
+
+/// Checks a password against a PHC string written by `hash_password`.
+pub async fn verify_password(password: String, hash: String) -> Result<bool> {
+    tokio::task::spawn_blocking(move || {
+        let hash = PasswordHash::new(&hash).map_err(|e| {
+            tracing::error!("Stored password hash is invalid: {}", e);
+            Error::HandlerError
+        })?;
+
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    })
+    .await
+    .map_err(|_| Error::HandlerError)?
+}
+
+/// A new opaque refresh token, only its hash is stored.
+pub fn generate_refresh_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// A new API key and the prefix of it that is shown when keys are listed, only
+/// its hash is stored.
+pub fn generate_api_key() -> (String, String) {
+    let key = format!(
+        "{}{}",
+        API_KEY_PREFIX,
+        URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>())
+    );
+    let prefix = key[..API_KEY_SHOWN_LENGTH].to_string();
+    (key, prefix)
+}
+
+/// Refresh tokens and API keys carry 256 random bits, a plain SHA-256 is
+/// enough to keep them from being read back out of the database.
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// The value of the `name` cookie sent with the request.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+/// A `Set-Cookie` value the browser keeps away from scripts and other sites,
+/// a `max_age` of 0 removes the cookie.
+pub fn set_cookie(name: &str, value: &str, path: &str, max_age: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}={}; HttpOnly; Secure; SameSite=Strict; Path={}; Max-Age={}",
+        name, value, path, max_age
+    ))
+    .expect("cookie values are base64url or JWTs")
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+fn bearer_token<B>(request: &Request<B>) -> Result<Option<&str>> {
+    let header = match request.headers().get(AUTHORIZATION) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    header
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(Some)
+        .ok_or(Error::AuthFailTokenWrongFormat)
+}
+
+/// The value of an `X-Api-Key` header.
+fn api_key<B>(request: &Request<B>) -> Result<Option<&str>> {
+    match request
+        .headers()
+        .get(HeaderName::from_static(API_KEY_HEADER))
+    {
+        Some(header) => header
+            .to_str()
+            .ok()
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(Some)
+            .ok_or(Error::AuthFailTokenWrongFormat),
+        None => Ok(None),
+    }
+}
+
+/// The owner of a live API key, acting with the scopes of the key on the
+/// tenant it was minted for.
+async fn authenticate_api_key(users: &DynUserStore, key: &str) -> Result<Ctx> {
+    let key = users.use_api_key(&hash_token(key)).await?.ok_or_else(|| {
+        tracing::info!("Rejected an unknown, expired or revoked API key");
+        Error::AuthFailTokenWrongFormat
+    })?;
+    tracing::info!("Authenticated with the API key {}", key.name);
+
+    Ok(Ctx::new(
+        key.owner,
+        key.scope.split_whitespace().map(str::to_string).collect(),
+    )
+    .with_tenant(Some(key.tenant_id)))
+}
+
+/// Rejects requests without valid credentials: an access token sent as a
+/// bearer token or in the `access_token` cookie set by `/api/auth/login`, or
+/// an API key in the `X-Api-Key` header. The caller is put into the request
+/// extensions as a `Ctx`.
+pub async fn require_auth<B>(
+    State(tokens): State<Tokens>,
+    State(users): State<DynUserStore>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response> {
+    let ctx = if let Some(token) = bearer_token(&request)? {
+        tokens.verifier.verify(token)?
+    } else if let Some(key) = api_key(&request)? {
+        authenticate_api_key(&users, key).await?
+    } else {
+        let token = cookie(request.headers(), ACCESS_TOKEN_COOKIE)
+            .ok_or(Error::AuthFailNoAuthTokenCookie)?;
+        tokens.verifier.verify(token)?
+    };
+    tracing::info!(
+        "Authenticated {} with scopes {:?}",
+        ctx.subject(),
+        ctx.scopes()
+    );
+
+    request.extensions_mut().insert(ctx);
+    Ok(next.run(request).await)
+}
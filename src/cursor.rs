@@ -0,0 +1,155 @@
+use crate::{Error, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::Arc;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Sort key of a list that supports cursors. `LIST` is signed together with
+/// the key, so a cursor only works on the list that issued it.
+pub trait CursorKey: Serialize + DeserializeOwned {
+    const LIST: &'static str;
+}
+
+/// Signs and verifies the opaque `cursor` of keyset pagination.
+///
+/// A cursor is the base64 JSON of the sort key of the last item on a page,
+/// followed by its HMAC-SHA256, so clients can neither read nor forge one.
+#[derive(Clone)]
+pub struct CursorSigner {
+    secret: Arc<[u8]>,
+}
+
+impl std::fmt::Debug for CursorSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CursorSigner").finish_non_exhaustive()
+    }
+}
+
+impl CursorSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    /// Signer with a per-process secret, cursors become invalid on restart.
+    pub fn random() -> Self {
+        Self::new(&rand::thread_rng().gen::<[u8; 32]>())
+    }
+
+    fn mac<K: CursorKey>(&self) -> HmacSha256 {
+        // unwrap() is allowed as HMAC accepts keys of any length
+        let mut mac = HmacSha256::new_from_slice(&self.secret).unwrap();
+        mac.update(K::LIST.as_bytes());
+        mac.update(b".");
+        mac
+    }
+
+    pub fn encode<K: CursorKey>(&self, key: &K) -> Result<String> {
+        let payload = serde_json::to_vec(key).map_err(|_| Error::HandlerError)?;
+
+        let mut mac = self.mac::<K>();
+        mac.update(&payload);
+        let signature = mac.finalize().into_bytes();
+
+        Ok(format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    /// Verifies and decodes a cursor, an empty cursor starts from the first item.
+    pub fn decode<K: CursorKey>(&self, cursor: &str) -> Result<Option<K>> {
+        if cursor.is_empty() {
+            return Ok(None);
+        }
+
+        let invalid = || Error::invalid_param("cursor", "is not a cursor issued by this API");
+        let (payload, signature) = cursor.split_once('.').ok_or_else(invalid)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+
+        let mut mac = self.mac::<K>();
+        mac.update(&payload);
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+
+        serde_json::from_slice(&payload)
+            .map(Some)
+            .map_err(|_| invalid())
+    }
+
+    /// Trims `items`, fetched with `limit + 1`, down to `limit` and returns the
+    /// cursor of the last item when there is a next page.
+    pub fn next_cursor<T, K, F>(
+        &self,
+        items: &mut Vec<T>,
+        limit: i64,
+        key: F,
+    ) -> Result<Option<String>>
+    where
+        K: CursorKey,
+        F: Fn(&T) -> K,
+    {
+        if items.len() as i64 <= limit {
+            return Ok(None);
+        }
+
+        items.truncate(limit as usize);
+        items.last().map(|item| self.encode(&key(item))).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{CustomerCursor, OrderCursor};
+
+    #[test]
+    fn round_trip() {
+        let signer = CursorSigner::new(b"secret");
+        let key = OrderCursor {
+            id: "64b1f0c2a1b2c3d4e5f60718".to_string(),
+        };
+
+        let cursor = signer.encode(&key).unwrap();
+        let decoded: Option<OrderCursor> = signer.decode(&cursor).unwrap();
+        assert_eq!(decoded, Some(key));
+
+        let empty: Option<OrderCursor> = signer.decode("").unwrap();
+        assert_eq!(empty, None);
+    }
+
+    #[test]
+    fn rejects_tampered_cursor() {
+        let signer = CursorSigner::new(b"secret");
+        let cursor = signer
+            .encode(&OrderCursor {
+                id: "64b1f0c2a1b2c3d4e5f60718".to_string(),
+            })
+            .unwrap();
+        let (_, signature) = cursor.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(br#"{"id":"64b1f0c2a1b2c3d4e5f60719"}"#),
+            signature
+        );
+
+        for cursor in [forged.as_str(), "garbage", "a.b"] {
+            assert!(matches!(
+                signer.decode::<OrderCursor>(cursor),
+                Err(Error::InvalidParam { .. })
+            ));
+        }
+        assert!(CursorSigner::new(b"other")
+            .decode::<OrderCursor>(&cursor)
+            .is_err());
+        assert!(signer.decode::<CustomerCursor>(&cursor).is_err());
+    }
+}
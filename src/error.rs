@@ -2,6 +2,8 @@ use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use serde::Serialize;
 
+use crate::validation::FieldError;
+
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Clone, Debug, Serialize, strum_macros::AsRefStr)]
@@ -13,23 +15,107 @@ pub enum Error {
     AuthFailNoAuthTokenCookie,
     AuthFailTokenWrongFormat,
     AuthFailCtxNotInRequestExt,
+    AuthFailMissingScope {
+        scopes: Vec<String>,
+    },
+    AuthFailWrongTenant {
+        tenant: String,
+    },
+    TenantNotInRequestExt,
     CustomerError,
     HandlerError,
 
+    // -- Request errors.
+    JsonRejection {
+        status: u16,
+        e: String,
+    },
+    InvalidParam {
+        field: String,
+        reason: String,
+    },
+    ValidationError {
+        errors: Vec<FieldError>,
+    },
+
+    // -- Config errors.
+    ConfigMissing {
+        name: String,
+    },
+    ConfigInvalid {
+        name: String,
+        value: String,
+    },
+
     // DB Errors
-    PGError { e: String },
-    SqlxUuid { e: String },
-    MongoParsingError { e: String },
-    MongoConnectionError { e: String },
-    MongoQueryError { e: String },
-    MongoInvalidIDError { e: String },
-    MongoSerializeBsonError { e: String },
+    PGConnectionError {
+        e: String,
+    },
+    PGError {
+        e: String,
+    },
+    SqlxUuid {
+        e: String,
+    },
+    MongoParsingError {
+        e: String,
+    },
+    MongoConnectionError {
+        e: String,
+    },
+    MongoQueryError {
+        e: String,
+    },
+    MongoInvalidIDError {
+        e: String,
+    },
+    MongoSerializeBsonError {
+        e: String,
+    },
 
     MongoSerializeError,
     MongoError,
 
+    // -- Lookup errors.
+    NotFound {
+        resource: String,
+        id: String,
+    },
+    Conflict {
+        resource: String,
+        key: String,
+    },
+    PreconditionFailed {
+        resource: String,
+        id: String,
+    },
+    ResourceInUse {
+        resource: String,
+        id: String,
+        dependent: String,
+        count: u64,
+    },
+    InvalidTransition {
+        resource: String,
+        id: String,
+        from: String,
+        to: String,
+    },
+    ItemsFinal {
+        resource: String,
+        id: String,
+        status: String,
+    },
+    InsufficientStock {
+        sku: String,
+        requested: u32,
+        available: i32,
+    },
+
     // -- Model errors.
-    TicketDeleteFailIdNotFound { id: u64 },
+    TicketDeleteFailIdNotFound {
+        id: u64,
+    },
 }
 
 // region:    --- Error Boilerplate
@@ -57,15 +143,66 @@ impl IntoResponse for Error {
 }
 
 impl Error {
+    pub fn not_found(resource: &str, id: &str) -> Self {
+        Self::NotFound {
+            resource: resource.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    pub fn invalid_param(field: &str, reason: impl Into<String>) -> Self {
+        Self::InvalidParam {
+            field: field.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn precondition_failed(resource: &str, id: &str) -> Self {
+        Self::PreconditionFailed {
+            resource: resource.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    pub fn invalid_transition(resource: &str, id: &str, from: &str, to: &str) -> Self {
+        Self::InvalidTransition {
+            resource: resource.to_string(),
+            id: id.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    pub fn items_final(resource: &str, id: &str, status: &str) -> Self {
+        Self::ItemsFinal {
+            resource: resource.to_string(),
+            id: id.to_string(),
+            status: status.to_string(),
+        }
+    }
+
     pub fn client_status_and_error(&self) -> (StatusCode, ClientError) {
         #[allow(unreachable_patterns)]
         match self {
-            Self::LoginFail => (StatusCode::FORBIDDEN, ClientError::LOGIN_FAIL),
+            Self::LoginFail => (StatusCode::UNAUTHORIZED, ClientError::LOGIN_FAIL),
 
             // -- Auth.
-            Self::AuthFailNoAuthTokenCookie
-            | Self::AuthFailTokenWrongFormat
-            | Self::AuthFailCtxNotInRequestExt => (StatusCode::FORBIDDEN, ClientError::NO_AUTH),
+            Self::AuthFailNoAuthTokenCookie | Self::AuthFailTokenWrongFormat => {
+                (StatusCode::UNAUTHORIZED, ClientError::NO_AUTH)
+            }
+            Self::AuthFailCtxNotInRequestExt => (StatusCode::FORBIDDEN, ClientError::NO_AUTH),
+            Self::AuthFailMissingScope { scopes } => (
+                StatusCode::FORBIDDEN,
+                ClientError::INSUFFICIENT_SCOPE {
+                    missing: scopes.clone(),
+                },
+            ),
+            Self::AuthFailWrongTenant { tenant } => (
+                StatusCode::FORBIDDEN,
+                ClientError::WRONG_TENANT {
+                    tenant: tenant.clone(),
+                },
+            ),
 
             // -- Model.
             Self::CustomerError => {
@@ -73,6 +210,15 @@ impl Error {
                 (StatusCode::BAD_REQUEST, ClientError::DATABASE_ERROR)
             }
 
+            // -- Model.
+            Self::PGConnectionError { e } => {
+                tracing::error!("DB Connection Error {}", e);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    ClientError::SERVICE_UNAVAILABLE,
+                )
+            }
+
             // -- Model.
             Self::PGError { e } => {
                 tracing::error!("DB Error {}", e);
@@ -87,8 +233,14 @@ impl Error {
 
             // -- Model.
             Self::SqlxUuid { e } => {
-                tracing::error!("DB Error {}", e);
-                (StatusCode::BAD_REQUEST, ClientError::DATABASE_ERROR)
+                tracing::error!("Invalid Id {}", e);
+                (
+                    StatusCode::BAD_REQUEST,
+                    ClientError::INVALID_PARAMS {
+                        field: "id".to_string(),
+                        reason: None,
+                    },
+                )
             }
 
             // -- Model.
@@ -99,8 +251,11 @@ impl Error {
 
             // -- Model.
             Self::MongoConnectionError { e } => {
-                tracing::error!("DB Error {}", e);
-                (StatusCode::BAD_REQUEST, ClientError::DATABASE_ERROR)
+                tracing::error!("DB Connection Error {}", e);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    ClientError::SERVICE_UNAVAILABLE,
+                )
             }
 
             // -- Model.
@@ -111,8 +266,14 @@ impl Error {
 
             // -- Model.
             Self::MongoInvalidIDError { e } => {
-                tracing::error!("DB Error {}", e);
-                (StatusCode::BAD_REQUEST, ClientError::DATABASE_ERROR)
+                tracing::error!("Invalid Id {}", e);
+                (
+                    StatusCode::BAD_REQUEST,
+                    ClientError::INVALID_PARAMS {
+                        field: "id".to_string(),
+                        reason: None,
+                    },
+                )
             }
 
             // -- Model.
@@ -133,11 +294,136 @@ impl Error {
                 (StatusCode::BAD_REQUEST, ClientError::DATABASE_ERROR)
             }
 
-            // -- Model.
-            Self::TicketDeleteFailIdNotFound { .. } => {
-                (StatusCode::BAD_REQUEST, ClientError::INVALID_PARAMS)
+            // -- Request.
+            Self::JsonRejection { status, e } => (
+                StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_REQUEST),
+                ClientError::INVALID_BODY {
+                    reason: e.to_string(),
+                },
+            ),
+
+            Self::InvalidParam { field, reason } => (
+                StatusCode::BAD_REQUEST,
+                ClientError::INVALID_PARAMS {
+                    field: field.to_string(),
+                    reason: Some(reason.to_string()),
+                },
+            ),
+
+            Self::ValidationError { errors } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ClientError::VALIDATION_FAILED {
+                    errors: errors.to_vec(),
+                },
+            ),
+
+            // -- Lookup.
+            Self::NotFound { resource, id } => (
+                StatusCode::NOT_FOUND,
+                ClientError::RESOURCE_NOT_FOUND {
+                    resource: resource.to_string(),
+                    id: id.to_string(),
+                },
+            ),
+
+            Self::Conflict { resource, key } => (
+                StatusCode::CONFLICT,
+                ClientError::RESOURCE_CONFLICT {
+                    resource: resource.to_string(),
+                    key: key.to_string(),
+                },
+            ),
+
+            Self::PreconditionFailed { resource, id } => (
+                StatusCode::PRECONDITION_FAILED,
+                ClientError::PRECONDITION_FAILED {
+                    resource: resource.to_string(),
+                    id: id.to_string(),
+                },
+            ),
+
+            Self::ResourceInUse {
+                resource,
+                id,
+                dependent,
+                count,
+            } => (
+                StatusCode::CONFLICT,
+                ClientError::RESOURCE_IN_USE {
+                    resource: resource.to_string(),
+                    id: id.to_string(),
+                    dependent: dependent.to_string(),
+                    count: *count,
+                },
+            ),
+
+            Self::InvalidTransition {
+                resource,
+                id,
+                from,
+                to,
+            } => (
+                StatusCode::CONFLICT,
+                ClientError::INVALID_TRANSITION {
+                    resource: resource.to_string(),
+                    id: id.to_string(),
+                    from: from.to_string(),
+                    to: to.to_string(),
+                },
+            ),
+
+            Self::ItemsFinal {
+                resource,
+                id,
+                status,
+            } => (
+                StatusCode::CONFLICT,
+                ClientError::ITEMS_FINAL {
+                    resource: resource.to_string(),
+                    id: id.to_string(),
+                    status: status.to_string(),
+                },
+            ),
+
+            Self::InsufficientStock {
+                sku,
+                requested,
+                available,
+            } => (
+                StatusCode::CONFLICT,
+                ClientError::INSUFFICIENT_STOCK {
+                    sku: sku.to_string(),
+                    requested: *requested,
+                    available: *available,
+                },
+            ),
+
+            // -- Config.
+            Self::ConfigMissing { name } => {
+                tracing::error!("Config Error, {} is not set", name);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    ClientError::SERVICE_ERROR,
+                )
+            }
+
+            Self::ConfigInvalid { name, value } => {
+                tracing::error!("Config Error, {} has the invalid value '{}'", name, value);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    ClientError::SERVICE_ERROR,
+                )
             }
 
+            // -- Model.
+            Self::TicketDeleteFailIdNotFound { .. } => (
+                StatusCode::BAD_REQUEST,
+                ClientError::INVALID_PARAMS {
+                    field: "id".to_string(),
+                    reason: None,
+                },
+            ),
+
             // -- Fallback.
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -147,12 +433,180 @@ impl Error {
     }
 }
 
-#[derive(Debug, strum_macros::AsRefStr)]
+/// Error sent back to the client. The variant name is the stable error code,
+/// its fields are serialized next to it as problem details extension members.
+#[derive(Debug, Serialize, strum_macros::AsRefStr)]
+#[serde(tag = "code")]
 #[allow(non_camel_case_types)]
 pub enum ClientError {
     LOGIN_FAIL,
     NO_AUTH,
-    INVALID_PARAMS,
+    INSUFFICIENT_SCOPE {
+        missing: Vec<String>,
+    },
+    WRONG_TENANT {
+        tenant: String,
+    },
+    INVALID_PARAMS {
+        field: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    INVALID_BODY {
+        reason: String,
+    },
+    VALIDATION_FAILED {
+        errors: Vec<FieldError>,
+    },
+    RESOURCE_NOT_FOUND {
+        resource: String,
+        id: String,
+    },
+    RESOURCE_CONFLICT {
+        resource: String,
+        key: String,
+    },
+    PRECONDITION_FAILED {
+        resource: String,
+        id: String,
+    },
+    RESOURCE_IN_USE {
+        resource: String,
+        id: String,
+        dependent: String,
+        count: u64,
+    },
+    INVALID_TRANSITION {
+        resource: String,
+        id: String,
+        from: String,
+        to: String,
+    },
+    ITEMS_FINAL {
+        resource: String,
+        id: String,
+        status: String,
+    },
+    INSUFFICIENT_STOCK {
+        sku: String,
+        requested: u32,
+        available: i32,
+    },
     DATABASE_ERROR,
     SERVICE_ERROR,
+    SERVICE_UNAVAILABLE,
+}
+
+impl ClientError {
+    pub fn detail(&self) -> String {
+        match self {
+            Self::LOGIN_FAIL => "Login failed".to_string(),
+            Self::NO_AUTH => "Missing or invalid authentication".to_string(),
+            Self::INSUFFICIENT_SCOPE { missing } if missing.is_empty() => {
+                "The request is not permitted".to_string()
+            }
+            Self::INSUFFICIENT_SCOPE { missing } => {
+                format!("Missing the scope(s) {}", missing.join(", "))
+            }
+            Self::WRONG_TENANT { tenant } => {
+                format!("The credentials may not act on the tenant '{}'", tenant)
+            }
+            Self::INVALID_PARAMS {
+                field,
+                reason: None,
+            } => format!("Invalid value for '{}'", field),
+            Self::INVALID_PARAMS {
+                field,
+                reason: Some(reason),
+            } => format!("Invalid value for '{}': {}", field, reason),
+            Self::INVALID_BODY { reason } => reason.to_string(),
+            Self::VALIDATION_FAILED { errors } => {
+                format!("{} field(s) failed validation", errors.len())
+            }
+            Self::RESOURCE_NOT_FOUND { resource, id } => {
+                format!("No {} found with id '{}'", resource, id)
+            }
+            Self::DATABASE_ERROR => {
+                "The request could not be processed by the database".to_string()
+            }
+            Self::RESOURCE_CONFLICT { resource, key } => {
+                format!("A {} with key {} already exists", resource, key)
+            }
+            Self::PRECONDITION_FAILED { resource, id } => format!(
+                "The {} with id '{}' was modified, fetch it again before retrying",
+                resource, id
+            ),
+            Self::RESOURCE_IN_USE {
+                resource,
+                id,
+                dependent,
+                count,
+            } => format!(
+                "The {} with id '{}' is still referenced by {} {}(s)",
+                resource, id, count, dependent
+            ),
+            Self::INVALID_TRANSITION {
+                resource,
+                id,
+                from,
+                to,
+            } => format!(
+                "The {} with id '{}' cannot move from '{}' to '{}'",
+                resource, id, from, to
+            ),
+            Self::ITEMS_FINAL {
+                resource,
+                id,
+                status,
+            } => format!(
+                "The items of the {} with id '{}' are final once it is '{}'",
+                resource, id, status
+            ),
+            Self::INSUFFICIENT_STOCK {
+                sku,
+                requested,
+                available,
+            } => format!(
+                "Only {} of '{}' in stock, {} requested",
+                available, sku, requested
+            ),
+            Self::SERVICE_ERROR => "Internal service error".to_string(),
+            Self::SERVICE_UNAVAILABLE => {
+                "The service is temporarily unavailable, retry later".to_string()
+            }
+        }
+    }
+}
+
+/// RFC 7807 `application/problem+json` response body.
+#[derive(Debug, Serialize)]
+pub struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    pub instance: String,
+    pub request_id: String,
+    #[serde(flatten)]
+    pub error: &'a ClientError,
+}
+
+impl<'a> ProblemDetails<'a> {
+    pub fn new(
+        status_code: StatusCode,
+        error: &'a ClientError,
+        instance: String,
+        request_id: String,
+    ) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title: status_code.canonical_reason().unwrap_or("Unknown Error"),
+            status: status_code.as_u16(),
+            detail: error.detail(),
+            instance,
+            request_id,
+            error,
+        }
+    }
 }
@@ -0,0 +1,49 @@
+use axum::http::header::{ETAG, IF_MATCH, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderName};
+
+/// Entity tag of a resource, derived from its version.
+pub fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+pub fn etag_header(version: i32) -> [(HeaderName, String); 1] {
+    [(ETAG, etag(version))]
+}
+
+/// Versions listed in the `If-Match` header. `None` means the write is
+/// unconditional, either because the header is missing or because it is `*`.
+/// Weak or malformed tags never match, as required for `If-Match`.
+pub fn if_match(headers: &HeaderMap) -> Option<Vec<i32>> {
+    let value = headers.get(IF_MATCH)?.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return None;
+    }
+
+    Some(
+        value
+            .split(',')
+            .filter_map(|tag| {
+                tag.trim()
+                    .strip_prefix('"')?
+                    .strip_suffix('"')?
+                    .parse()
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+/// Whether the `If-None-Match` header matches the current version, in which
+/// case a GET answers `304 Not Modified`. Uses the weak comparison.
+pub fn if_none_match(headers: &HeaderMap, version: i32) -> bool {
+    let value = match headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        Some(value) => value.trim(),
+        None => return false,
+    };
+
+    value == "*"
+        || value
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag(version))
+}
@@ -1,22 +1,53 @@
 use crate::{
-    mongo::MONGO,
-    pg::PG,
+    auth::{
+        cookie, generate_api_key, generate_refresh_token, hash_password, hash_token, set_cookie,
+        verify_password, Ctx, Tokens, ACCESS_TOKEN_COOKIE, REFRESH_TOKEN_COOKIE,
+    },
+    cursor::CursorSigner,
+    etag,
+    model::{ApiKeyModel, OrderStatus, RefreshTokenModel, UserModel},
     response::{
-        CustomerListResponse, DeleteOrderResponse, GenericResponse, OrderListResponse,
-        SingleCustomerResponse, SingleOrderResponse,
+        ApiKeyListResponse, CreatedApiKeyResponse, CustomerListResponse, DeleteOrderResponse,
+        GenericResponse, OrderListResponse, Pagination, ProductListResponse, SearchHit,
+        SearchResource, SearchResponse, SingleApiKeyResponse, SingleCustomerResponse,
+        SingleProductResponse, TokenResponse,
+    },
+    schema::{
+        CreateApiKeySchema, CreateCustomerSchema, CreateOrderSchema, CreateProductSchema,
+        CustomerCursor, CustomerFilterOptions, DeleteOptions, ExpandOptions, FilterOptions,
+        LoginSchema, OrderCursor, OrderFilterOptions, PageRequest, RefreshSchema, SearchOptions,
+        Sort, SortOptions, TransitionOrderSchema, UpdateCustomerSchema, UpdateOrderSchema,
+        UpdateProductSchema, CUSTOMER_EXPANSIONS, CUSTOMER_SORT_FIELDS, MAX_SEARCH_DEPTH,
+        ORDER_SORT_FIELDS,
+    },
+    store::{
+        ensure_customer_exists, CustomerDeletePolicy, DynCustomerStore, DynOrderStore,
+        DynProductStore, DynUserStore,
     },
-    schema::{CreateCustomerSchema, CreateOrderSchema, FilterOptions},
+    tenant::Tenant,
+    validation::{ValidJson, ValidQuery},
     Error, Result,
 };
 use autometrics::autometrics;
 use tracing::instrument;
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{Path, State},
+    http::{header::SET_COOKIE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+/// Damps the weight of the top ranks in the rank fusion of `search_handler`.
+const SEARCH_RANK_CONSTANT: f64 = 60.0;
+
+/// Orders embedded by `?expand=orders`, the full list is at `/api/pg/:id/orders`.
+const EXPANDED_ORDERS: PageRequest = PageRequest {
+    page: 1,
+    limit: 100,
+};
 
 #[instrument]
 #[autometrics]
@@ -34,66 +65,370 @@ pub async fn health_checker_handler() -> Result<impl IntoResponse> {
 #[instrument]
 #[autometrics]
 pub async fn create_customer_handler(
-    State(db): State<PG>,
-    Json(body): Json<CreateCustomerSchema>,
+    tenant: Tenant,
+    State(db): State<DynCustomerStore>,
+    ValidJson(body): ValidJson<CreateCustomerSchema>,
 ) -> Result<impl IntoResponse> {
-    let result = db.create_customer(&body).await?;
+    let result = db.create_customer(&tenant, &body).await?;
 
-    Ok((StatusCode::CREATED, Json(result)))
+    Ok((
+        StatusCode::CREATED,
+        etag::etag_header(result.version),
+        Json(result),
+    ))
 }
 
 pub async fn handler_404() -> impl IntoResponse {
     (StatusCode::FORBIDDEN, "nothing to see here")
 }
 
+/// Cursors hold the key of the default sort, so they only walk lists in that order.
+fn reject_cursor_sort(sort: &SortOptions) -> Result<()> {
+    match sort.sort {
+        Some(_) => Err(Error::invalid_param(
+            "sort",
+            "cannot be combined with cursor",
+        )),
+        None => Ok(()),
+    }
+}
+
 // GET /api/pg
 #[instrument]
 #[autometrics]
 pub async fn list_customer_handler(
-    opts: Option<Query<FilterOptions>>,
-    State(db): State<PG>,
+    tenant: Tenant,
+    ValidQuery(opts): ValidQuery<FilterOptions>,
+    ValidQuery(filter): ValidQuery<CustomerFilterOptions>,
+    ValidQuery(sort): ValidQuery<SortOptions>,
+    State(db): State<DynCustomerStore>,
+    State(cursors): State<CursorSigner>,
 ) -> Result<Json<CustomerListResponse>> {
-    let Query(opts) = opts.unwrap_or_default();
-    let limit = opts.limit.unwrap_or(10) as i64;
-    let offset = opts.page.unwrap_or(0) as i64;
-    let result = db.list_customers(limit, offset).await?;
+    let filter = filter.filter()?;
+    if let Some((cursor, limit)) = opts.cursor_request()? {
+        reject_cursor_sort(&sort)?;
+        let after: Option<CustomerCursor> = cursors.decode(cursor)?;
+        let mut data = db
+            .list_customers_after(&tenant, &filter, after.as_ref(), limit + 1)
+            .await?;
+        let next_cursor = cursors.next_cursor(&mut data, limit, |customer| CustomerCursor {
+            name: customer.name.to_owned(),
+            id: customer.id.to_owned(),
+        })?;
+
+        return Ok(Json(CustomerListResponse {
+            status: "success".to_string(),
+            data,
+            pagination: Pagination::Cursor { limit, next_cursor },
+        }));
+    }
+
+    let sort = Sort::parse(
+        sort.sort.as_deref(),
+        CUSTOMER_SORT_FIELDS,
+        Sort::by("customer_name"),
+    )?;
+    let result = db
+        .list_customers(&tenant, &filter, opts.page_request()?, &sort)
+        .await?;
 
-    Ok(Json(result.ok_or(Error::HandlerError)?))
+    Ok(Json(result))
 }
 
 // GET /api/pg/<customer-name>
 #[instrument]
 #[autometrics]
 pub async fn get_customer_handler(
+    tenant: Tenant,
     id: Path<String>,
-    State(db): State<PG>,
-) -> Result<Json<SingleCustomerResponse>> {
-    let result = db.get_customer(&id).await?;
+    ValidQuery(opts): ValidQuery<ExpandOptions>,
+    headers: HeaderMap,
+    State(db): State<DynCustomerStore>,
+    State(orders): State<DynOrderStore>,
+) -> Result<Response> {
+    let expand_orders = opts.includes("orders", CUSTOMER_EXPANSIONS)?;
+    let mut result = db.get_customer(&tenant, &id).await?;
+
+    // the ETag only covers the customer, so an expanded response is never conditional
+    if expand_orders {
+        let customer_orders = orders
+            .fetch_customer_orders(&tenant, &id, EXPANDED_ORDERS, &Sort::default())
+            .await?;
+        result.orders = Some(customer_orders.orders);
+
+        return Ok(Json(result).into_response());
+    }
+
+    if etag::if_none_match(&headers, result.version) {
+        return Ok((StatusCode::NOT_MODIFIED, etag::etag_header(result.version)).into_response());
+    }
+
+    Ok((etag::etag_header(result.version), Json(result)).into_response())
+}
+
+// GET /api/pg/<customer-name>/orders
+#[instrument]
+#[autometrics]
+pub async fn list_customer_orders_handler(
+    tenant: Tenant,
+    id: Path<String>,
+    ValidQuery(opts): ValidQuery<FilterOptions>,
+    ValidQuery(sort): ValidQuery<SortOptions>,
+    State(db): State<DynCustomerStore>,
+    State(orders): State<DynOrderStore>,
+) -> Result<Json<OrderListResponse>> {
+    if opts.cursor.is_some() {
+        return Err(Error::invalid_param(
+            "cursor",
+            "is not supported on this list",
+        ));
+    }
+    let page = opts.page_request()?;
+    let sort = Sort::parse(sort.sort.as_deref(), ORDER_SORT_FIELDS, Sort::default())?;
+
+    // get_customer fails with NotFound for an unknown customer, so the
+    // client gets a 404 rather than an empty list
+    db.get_customer(&tenant, &id).await?;
+    let result = orders
+        .fetch_customer_orders(&tenant, &id, page, &sort)
+        .await?;
 
-    Ok(Json(result.ok_or(Error::HandlerError)?))
+    Ok(Json(result))
 }
 
 // DELETE /api/pg/<customer-name>
 #[instrument]
 #[autometrics]
 pub async fn delete_customer_handler(
+    tenant: Tenant,
     id: Path<String>,
-    State(db): State<PG>,
+    headers: HeaderMap,
+    State(db): State<DynCustomerStore>,
+    State(orders): State<DynOrderStore>,
+    State(products): State<DynProductStore>,
+    State(policy): State<CustomerDeletePolicy>,
 ) -> Result<Json<SingleCustomerResponse>> {
-    let result = db.delete_customer(&id).await?;
+    // customers and orders live in different databases, so the check and the
+    // delete below are not atomic, orders created in between are swept after
+    // the delete
+    if policy == CustomerDeletePolicy::Reject {
+        let count = orders.count_customer_orders(&tenant, &id).await?;
+        if count > 0 {
+            return Err(Error::ResourceInUse {
+                resource: "customer".to_string(),
+                id: id.to_string(),
+                dependent: "order".to_string(),
+                count,
+            });
+        }
+    }
+
+    let if_match = etag::if_match(&headers);
+
+    // the orders go first so that a failure leaves the customer in place and
+    // the delete can be retried, the customer is checked beforehand so that
+    // a missing customer or a stale `If-Match` does not take its orders
+    if policy == CustomerDeletePolicy::Cascade {
+        let customer = db.get_customer(&tenant, &id).await?;
+        if if_match
+            .as_ref()
+            .is_some_and(|versions| !versions.contains(&customer.version))
+        {
+            return Err(Error::precondition_failed("customer", &id));
+        }
+
+        let deleted = orders.delete_customer_orders(&tenant, &id).await?;
+        release_orders_stock(&products, &deleted).await;
+        tracing::info!(
+            "Deleted {} order(s) of customer {}",
+            deleted.len(),
+            id.as_str()
+        );
+    }
+
+    let result = db
+        .delete_customer(&tenant, &id, if_match.as_deref())
+        .await?;
+
+    // an order created after the orders were checked or deleted above points
+    // at a customer that is gone now, `create_order_handler` takes back the
+    // ones written after this
+    if policy != CustomerDeletePolicy::Orphan {
+        if let Err(e) = sweep_orphaned_orders(&tenant, &id, &orders, &products).await {
+            tracing::error!(
+                "Failed to delete the orders of deleted customer {}: {:?}",
+                id.as_str(),
+                e
+            );
+        }
+    }
+
+    Ok(Json(result))
+}
+
+/// Deletes the live orders still pointing at the deleted customer
+/// `customer_id` and puts their stock back.
+async fn sweep_orphaned_orders(
+    tenant: &Tenant,
+    customer_id: &str,
+    orders: &DynOrderStore,
+    products: &DynProductStore,
+) -> Result<()> {
+    if orders.count_customer_orders(tenant, customer_id).await? == 0 {
+        return Ok(());
+    }
+
+    let deleted = orders.delete_customer_orders(tenant, customer_id).await?;
+    release_orders_stock(products, &deleted).await;
+    tracing::warn!(
+        "Deleted {} order(s) created while customer {} was deleted",
+        deleted.len(),
+        customer_id
+    );
+
+    Ok(())
+}
 
-    Ok(Json(result.ok_or(Error::HandlerError)?))
+/// Puts back the stock reserved for each of `order_ids`. Releasing is
+/// idempotent, so a failure is logged with the order to retry and the
+/// remaining orders are still released.
+async fn release_orders_stock(products: &DynProductStore, order_ids: &[String]) {
+    for order_id in order_ids {
+        if let Err(e) = products.release_stock(order_id).await {
+            tracing::error!("Failed to release the stock of order {}: {:?}", order_id, e);
+        }
+    }
 }
 
-// UPDATE /api/pg/<customer-name>
+// PUT /api/pg/<customer-name>
 #[instrument]
 #[autometrics]
 pub async fn update_customer_handler(
+    tenant: Tenant,
     id: Path<String>,
-    State(db): State<PG>,
-    Json(body): Json<CreateCustomerSchema>,
-) -> Result<Json<SingleCustomerResponse>> {
-    let result = db.update_customer(&id, &body).await?;
+    headers: HeaderMap,
+    State(db): State<DynCustomerStore>,
+    ValidJson(body): ValidJson<CreateCustomerSchema>,
+) -> Result<impl IntoResponse> {
+    let result = db
+        .update_customer(&tenant, &id, &body, etag::if_match(&headers).as_deref())
+        .await?;
+
+    Ok((etag::etag_header(result.version), Json(result)))
+}
+
+// PATCH /api/pg/<customer-name>
+#[instrument]
+#[autometrics]
+pub async fn patch_customer_handler(
+    tenant: Tenant,
+    id: Path<String>,
+    headers: HeaderMap,
+    State(db): State<DynCustomerStore>,
+    ValidJson(body): ValidJson<UpdateCustomerSchema>,
+) -> Result<impl IntoResponse> {
+    let result = db
+        .patch_customer(&tenant, &id, &body, etag::if_match(&headers).as_deref())
+        .await?;
+
+    Ok((etag::etag_header(result.version), Json(result)))
+}
+
+// POST /api/products
+#[instrument]
+#[autometrics]
+pub async fn create_product_handler(
+    State(products): State<DynProductStore>,
+    ValidJson(body): ValidJson<CreateProductSchema>,
+) -> Result<impl IntoResponse> {
+    let result = products.create_product(&body).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        etag::etag_header(result.product.version),
+        Json(result),
+    ))
+}
+
+// GET /api/products
+#[instrument]
+#[autometrics]
+pub async fn list_product_handler(
+    ValidQuery(opts): ValidQuery<FilterOptions>,
+    State(products): State<DynProductStore>,
+) -> Result<Json<ProductListResponse>> {
+    if opts.cursor.is_some() {
+        return Err(Error::invalid_param(
+            "cursor",
+            "is not supported on this list",
+        ));
+    }
+    let result = products.list_products(opts.page_request()?).await?;
+
+    Ok(Json(result))
+}
+
+// GET /api/products/:id
+#[instrument]
+#[autometrics]
+pub async fn get_product_handler(
+    id: Path<String>,
+    headers: HeaderMap,
+    State(products): State<DynProductStore>,
+) -> Result<Response> {
+    let result = products.get_product(&id).await?;
+    let version = result.product.version;
+
+    if etag::if_none_match(&headers, version) {
+        return Ok((StatusCode::NOT_MODIFIED, etag::etag_header(version)).into_response());
+    }
+
+    Ok((etag::etag_header(version), Json(result)).into_response())
+}
+
+// PUT /api/products/:id
+#[instrument]
+#[autometrics]
+pub async fn update_product_handler(
+    id: Path<String>,
+    headers: HeaderMap,
+    State(products): State<DynProductStore>,
+    ValidJson(body): ValidJson<CreateProductSchema>,
+) -> Result<impl IntoResponse> {
+    let result = products
+        .update_product(&id, &body, etag::if_match(&headers).as_deref())
+        .await?;
+
+    Ok((etag::etag_header(result.product.version), Json(result)))
+}
+
+// PATCH /api/products/:id
+#[instrument]
+#[autometrics]
+pub async fn patch_product_handler(
+    id: Path<String>,
+    headers: HeaderMap,
+    State(products): State<DynProductStore>,
+    ValidJson(body): ValidJson<UpdateProductSchema>,
+) -> Result<impl IntoResponse> {
+    let result = products
+        .patch_product(&id, &body, etag::if_match(&headers).as_deref())
+        .await?;
+
+    Ok((etag::etag_header(result.product.version), Json(result)))
+}
+
+// DELETE /api/products/:id
+#[instrument]
+#[autometrics]
+pub async fn delete_product_handler(
+    id: Path<String>,
+    headers: HeaderMap,
+    State(products): State<DynProductStore>,
+) -> Result<Json<SingleProductResponse>> {
+    let result = products
+        .delete_product(&id, etag::if_match(&headers).as_deref())
+        .await?;
 
     Ok(Json(result))
 }
@@ -102,25 +437,69 @@ pub async fn update_customer_handler(
 #[instrument]
 #[autometrics]
 pub async fn create_order_handler(
-    State(mongo): State<MONGO>,
-    Json(body): Json<CreateOrderSchema>,
-) -> Result<Json<SingleOrderResponse>> {
-    let result = mongo.create_order(&body).await?;
+    tenant: Tenant,
+    State(mongo): State<DynOrderStore>,
+    State(customers): State<DynCustomerStore>,
+    State(products): State<DynProductStore>,
+    State(policy): State<CustomerDeletePolicy>,
+    ValidJson(body): ValidJson<CreateOrderSchema>,
+) -> Result<impl IntoResponse> {
+    let result = mongo.create_order(&tenant, &body).await?;
 
-    Ok(Json(result))
+    // the customer may have been deleted between the check of the store and
+    // the write, `delete_customer_handler` only sweeps the orders written
+    // before its delete so the ones after are taken back here
+    if policy != CustomerDeletePolicy::Orphan {
+        if let Err(e) = ensure_customer_exists(customers.as_ref(), &tenant, &body.customer_id).await
+        {
+            let order_id = &result.data.order.id;
+            mongo.delete_order(&tenant, order_id, false, None).await?;
+            release_orders_stock(&products, std::slice::from_ref(order_id)).await;
+            return Err(e);
+        }
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        etag::etag_header(result.data.order.version),
+        Json(result),
+    ))
 }
 
 // GET /api/mongo
 #[instrument]
 #[autometrics]
 pub async fn list_order_handler(
-    opts: Option<Query<FilterOptions>>,
-    State(mongo): State<MONGO>,
+    tenant: Tenant,
+    ValidQuery(opts): ValidQuery<FilterOptions>,
+    ValidQuery(filter): ValidQuery<OrderFilterOptions>,
+    ValidQuery(sort): ValidQuery<SortOptions>,
+    State(mongo): State<DynOrderStore>,
+    State(cursors): State<CursorSigner>,
 ) -> Result<Json<OrderListResponse>> {
-    let Query(opts) = opts.unwrap_or_default();
-    let limit = opts.limit.unwrap_or(10) as i64;
-    let offset = opts.page.unwrap_or(1) as i64;
-    let result = mongo.fetch_orders(limit, offset).await?;
+    let filter = filter.filter()?;
+    if let Some((cursor, limit)) = opts.cursor_request()? {
+        reject_cursor_sort(&sort)?;
+        let after: Option<OrderCursor> = cursors.decode(cursor)?;
+        let mut orders = mongo
+            .fetch_orders_after(&tenant, &filter, after.as_ref(), limit + 1)
+            .await?;
+        let next_cursor = cursors.next_cursor(&mut orders, limit, |order| OrderCursor {
+            id: order.id.to_owned(),
+        })?;
+
+        return Ok(Json(OrderListResponse {
+            status: "success".to_string(),
+            results: orders.len(),
+            orders,
+            pagination: Pagination::Cursor { limit, next_cursor },
+        }));
+    }
+
+    let sort = Sort::parse(sort.sort.as_deref(), ORDER_SORT_FIELDS, Sort::default())?;
+    let result = mongo
+        .fetch_orders(&tenant, &filter, opts.page_request()?, &sort)
+        .await?;
 
     Ok(Json(result))
 }
@@ -129,35 +508,411 @@ pub async fn list_order_handler(
 #[instrument]
 #[autometrics]
 pub async fn get_order_handler(
+    tenant: Tenant,
     id: Path<String>,
-    State(mongo): State<MONGO>,
-) -> Result<Json<SingleOrderResponse>> {
-    let result = mongo.get_order(&id).await?;
+    headers: HeaderMap,
+    State(mongo): State<DynOrderStore>,
+) -> Result<Response> {
+    let result = mongo.get_order(&tenant, &id).await?;
+    let version = result.data.order.version;
 
-    Ok(Json(result))
+    if etag::if_none_match(&headers, version) {
+        return Ok((StatusCode::NOT_MODIFIED, etag::etag_header(version)).into_response());
+    }
+
+    Ok((etag::etag_header(version), Json(result)).into_response())
 }
 
-// PATCH /api/mongo/:id
+// PUT /api/mongo/:id
 #[instrument]
 #[autometrics]
 pub async fn update_order_handler(
+    tenant: Tenant,
     id: Path<String>,
-    State(mongo): State<MONGO>,
-    Json(body): Json<CreateOrderSchema>,
-) -> Result<Json<SingleOrderResponse>> {
-    let result = mongo.edit_order(&id, &body).await?;
+    headers: HeaderMap,
+    State(mongo): State<DynOrderStore>,
+    ValidJson(body): ValidJson<CreateOrderSchema>,
+) -> Result<impl IntoResponse> {
+    let result = mongo
+        .edit_order(&tenant, &id, &body, etag::if_match(&headers).as_deref())
+        .await?;
 
-    Ok(Json(result))
+    Ok((etag::etag_header(result.data.order.version), Json(result)))
+}
+
+// PATCH /api/mongo/:id
+#[instrument]
+#[autometrics]
+pub async fn patch_order_handler(
+    tenant: Tenant,
+    id: Path<String>,
+    headers: HeaderMap,
+    State(mongo): State<DynOrderStore>,
+    ValidJson(body): ValidJson<UpdateOrderSchema>,
+) -> Result<impl IntoResponse> {
+    let result = mongo
+        .patch_order(&tenant, &id, &body, etag::if_match(&headers).as_deref())
+        .await?;
+
+    Ok((etag::etag_header(result.data.order.version), Json(result)))
+}
+
+// POST /api/mongo/:id/transition
+#[instrument]
+#[autometrics]
+pub async fn transition_order_handler(
+    tenant: Tenant,
+    id: Path<String>,
+    headers: HeaderMap,
+    State(mongo): State<DynOrderStore>,
+    State(products): State<DynProductStore>,
+    ValidJson(body): ValidJson<TransitionOrderSchema>,
+) -> Result<impl IntoResponse> {
+    let result = mongo
+        .transition_order(
+            &tenant,
+            &id,
+            body.status,
+            etag::if_match(&headers).as_deref(),
+        )
+        .await?;
+
+    // the stock of a cancelled order is available again, a shipped one keeps
+    // it; the order has moved already, so a failed stock write is logged to
+    // be retried rather than failing the request, both writes are idempotent
+    let order_id = &result.data.order.id;
+    match body.status {
+        OrderStatus::Cancelled => {
+            release_orders_stock(&products, std::slice::from_ref(order_id)).await;
+        }
+        OrderStatus::Shipped => {
+            if let Err(e) = products.settle_stock(order_id).await {
+                tracing::error!("Failed to settle the stock of order {}: {:?}", order_id, e);
+            }
+        }
+        _ => {}
+    }
+
+    Ok((etag::etag_header(result.data.order.version), Json(result)))
 }
 
 // DELETE /api/mongo/:id
 #[instrument]
 #[autometrics]
 pub async fn delete_order_handler(
+    tenant: Tenant,
     id: Path<String>,
-    State(mongo): State<MONGO>,
+    ValidQuery(opts): ValidQuery<DeleteOptions>,
+    headers: HeaderMap,
+    State(mongo): State<DynOrderStore>,
+    State(products): State<DynProductStore>,
 ) -> Result<Json<DeleteOrderResponse>> {
-    let result = mongo.delete_order(&id).await?;
+    let soft = opts.soft.unwrap_or(false);
+    let result = mongo
+        .delete_order(&tenant, &id, soft, etag::if_match(&headers).as_deref())
+        .await?;
+    // orders live in MongoDB and stock in Postgres, so this is a separate
+    // write that cannot undo the delete, a failure is only logged
+    release_orders_stock(&products, std::slice::from_ref(&result.data.order.id)).await;
 
     Ok(Json(result))
 }
+
+// GET /api/search
+// Customers and orders are ranked by different engines whose scores do not
+// compare, so the two rankings are merged with reciprocal rank fusion.
+#[instrument]
+#[autometrics]
+pub async fn search_handler(
+    tenant: Tenant,
+    ValidQuery(opts): ValidQuery<FilterOptions>,
+    ValidQuery(search): ValidQuery<SearchOptions>,
+    State(customers): State<DynCustomerStore>,
+    State(orders): State<DynOrderStore>,
+) -> Result<Json<SearchResponse>> {
+    if opts.cursor.is_some() {
+        return Err(Error::invalid_param(
+            "cursor",
+            "is not supported on this list",
+        ));
+    }
+    let query = search.query()?;
+    let page = opts.page_request()?;
+    if page.offset() >= MAX_SEARCH_DEPTH {
+        return Err(Error::invalid_param(
+            "page",
+            format!("search results stop after {} hits", MAX_SEARCH_DEPTH),
+        ));
+    }
+    // the last page may be cut short by the depth limit
+    let depth = page
+        .offset()
+        .saturating_add(page.limit)
+        .min(MAX_SEARCH_DEPTH);
+
+    let ((customers, customer_count), (orders, order_count)) = futures::try_join!(
+        customers.search_customers(&tenant, query, depth),
+        orders.search_orders(&tenant, query, depth)
+    )?;
+
+    let score = |rank: usize| 1.0 / (SEARCH_RANK_CONSTANT + rank as f64 + 1.0);
+    let mut hits: Vec<SearchHit> = customers
+        .into_iter()
+        .map(SearchResource::Customer)
+        .enumerate()
+        .chain(orders.into_iter().map(SearchResource::Order).enumerate())
+        .map(|(rank, resource)| SearchHit {
+            resource,
+            score: score(rank),
+        })
+        .collect();
+    // the sort is stable, so customers stay ahead of orders of the same rank
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    Ok(Json(SearchResponse {
+        status: "success".to_string(),
+        results: hits
+            .into_iter()
+            .skip(page.offset() as usize)
+            .take((depth - page.offset()) as usize)
+            .collect(),
+        // pages past the depth limit are not advertised as they cannot be read
+        pagination: Pagination::new(
+            page,
+            (customer_count + order_count).min(MAX_SEARCH_DEPTH as u64),
+        ),
+    }))
+}
+
+/// Issues an access token and a refresh token of `family_id` for `user`, both
+/// in the body and as HttpOnly cookies.
+async fn issue_tokens(
+    users: &DynUserStore,
+    tokens: &Tokens,
+    user: &UserModel,
+    family_id: Uuid,
+) -> Result<Response> {
+    let access_token = tokens.issuer.issue(
+        &user.user_id.to_string(),
+        &user.scope,
+        user.tenant_id.as_deref(),
+    )?;
+    let refresh_token = generate_refresh_token();
+    let now = Utc::now();
+    users
+        .insert_refresh_token(&RefreshTokenModel {
+            token_hash: hash_token(&refresh_token),
+            user_id: user.user_id,
+            family_id,
+            expires_at: now + Duration::seconds(tokens.refresh_ttl as i64),
+            revoked_at: None,
+            created_at: now,
+        })
+        .await?;
+
+    let mut headers = HeaderMap::new();
+    headers.append(
+        SET_COOKIE,
+        set_cookie(
+            ACCESS_TOKEN_COOKIE,
+            &access_token,
+            "/api",
+            tokens.issuer.ttl(),
+        ),
+    );
+    headers.append(
+        SET_COOKIE,
+        set_cookie(
+            REFRESH_TOKEN_COOKIE,
+            &refresh_token,
+            "/api/auth",
+            tokens.refresh_ttl,
+        ),
+    );
+
+    Ok((
+        headers,
+        Json(TokenResponse {
+            status: "success".to_string(),
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: tokens.issuer.ttl(),
+            refresh_token,
+            refresh_expires_in: tokens.refresh_ttl,
+        }),
+    )
+        .into_response())
+}
+
+/// The refresh token from the body, or from the `refresh_token` cookie.
+fn presented_refresh_token(
+    headers: &HeaderMap,
+    body: Option<Json<RefreshSchema>>,
+) -> Option<String> {
+    body.and_then(|Json(body)| body.refresh_token)
+        .filter(|token| !token.trim().is_empty())
+        .or_else(|| cookie(headers, REFRESH_TOKEN_COOKIE).map(str::to_string))
+}
+
+// POST /api/auth/login
+#[instrument(skip(users))]
+#[autometrics]
+pub async fn login_handler(
+    State(users): State<DynUserStore>,
+    State(tokens): State<Tokens>,
+    ValidJson(body): ValidJson<LoginSchema>,
+) -> Result<Response> {
+    let user = users.get_user_by_username(body.username.trim()).await?;
+    let user = match user {
+        Some(user) => {
+            if !verify_password(body.password, user.password_hash.clone()).await? {
+                return Err(Error::LoginFail);
+            }
+            user
+        }
+        None => {
+            // hash anyway so that unknown usernames take as long as wrong passwords
+            hash_password(body.password).await?;
+            return Err(Error::LoginFail);
+        }
+    };
+
+    issue_tokens(&users, &tokens, &user, Uuid::new_v4()).await
+}
+
+// POST /api/auth/refresh
+// Each refresh token is used once and replaced by a new one of the same
+// family, reusing an old one revokes the whole family.
+#[instrument(skip_all)]
+#[autometrics]
+pub async fn refresh_handler(
+    State(users): State<DynUserStore>,
+    State(tokens): State<Tokens>,
+    headers: HeaderMap,
+    body: Option<Json<RefreshSchema>>,
+) -> Result<Response> {
+    let token = presented_refresh_token(&headers, body).ok_or(Error::AuthFailNoAuthTokenCookie)?;
+    let consumed = users
+        .consume_refresh_token(&hash_token(&token))
+        .await?
+        .ok_or(Error::AuthFailTokenWrongFormat)?;
+    // the scope is read again so that changes apply from the next refresh
+    let user = users
+        .get_user(consumed.user_id)
+        .await?
+        .ok_or(Error::AuthFailTokenWrongFormat)?;
+
+    issue_tokens(&users, &tokens, &user, consumed.family_id).await
+}
+
+// POST /api/auth/logout
+#[instrument(skip_all)]
+#[autometrics]
+pub async fn logout_handler(
+    State(users): State<DynUserStore>,
+    headers: HeaderMap,
+    body: Option<Json<RefreshSchema>>,
+) -> Result<impl IntoResponse> {
+    if let Some(token) = presented_refresh_token(&headers, body) {
+        let revoked = users.revoke_refresh_family(&hash_token(&token)).await?;
+        tracing::info!("Revoked {} refresh tokens", revoked);
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.append(SET_COOKIE, set_cookie(ACCESS_TOKEN_COOKIE, "", "/api", 0));
+    headers.append(
+        SET_COOKIE,
+        set_cookie(REFRESH_TOKEN_COOKIE, "", "/api/auth", 0),
+    );
+
+    Ok((
+        headers,
+        Json(GenericResponse {
+            status: "success".to_string(),
+            message: "Logged out".to_string(),
+        }),
+    ))
+}
+
+// POST /api/admin/api-keys
+#[instrument]
+#[autometrics]
+pub async fn create_api_key_handler(
+    ctx: Ctx,
+    tenant: Tenant,
+    State(users): State<DynUserStore>,
+    ValidJson(body): ValidJson<CreateApiKeySchema>,
+) -> Result<impl IntoResponse> {
+    // a key cannot do more than the caller minting it
+    let missing: Vec<String> = body
+        .scopes
+        .iter()
+        .filter(|scope| !ctx.has_scope(scope))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::AuthFailMissingScope { scopes: missing });
+    }
+
+    let (key, prefix) = generate_api_key();
+    let api_key = ApiKeyModel {
+        api_key_id: Uuid::new_v4(),
+        name: body.name.trim().to_string(),
+        owner: body
+            .owner
+            .as_deref()
+            .unwrap_or(ctx.subject())
+            .trim()
+            .to_string(),
+        key_hash: hash_token(&key),
+        prefix,
+        scope: body.scopes.join(" "),
+        // the key only ever acts on the tenant it is minted in
+        tenant_id: tenant.as_str().to_string(),
+        expires_at: body.expires_at,
+        last_used_at: None,
+        revoked_at: None,
+        created_at: Utc::now(),
+    };
+    users.insert_api_key(&api_key).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKeyResponse {
+            status: "success".to_string(),
+            key,
+            api_key: api_key.into(),
+        }),
+    ))
+}
+
+// GET /api/admin/api-keys
+#[instrument]
+#[autometrics]
+pub async fn list_api_key_handler(
+    tenant: Tenant,
+    State(users): State<DynUserStore>,
+) -> Result<Json<ApiKeyListResponse>> {
+    let keys = users.list_api_keys(&tenant).await?;
+
+    Ok(Json(ApiKeyListResponse {
+        status: "success".to_string(),
+        data: keys.into_iter().map(Into::into).collect(),
+    }))
+}
+
+// DELETE /api/admin/api-keys/:id
+#[instrument]
+#[autometrics]
+pub async fn revoke_api_key_handler(
+    tenant: Tenant,
+    Path(id): Path<String>,
+    State(users): State<DynUserStore>,
+) -> Result<Json<SingleApiKeyResponse>> {
+    let key = users.revoke_api_key(&tenant, &id).await?;
+
+    Ok(Json(SingleApiKeyResponse {
+        status: "success".to_string(),
+        api_key: key.into(),
+    }))
+}
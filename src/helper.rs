@@ -1,5 +1,5 @@
+use crate::{Error, Result};
 use std::fs::File;
-use std::io::Error;
 use std::io::Read;
 
 pub struct Config {
@@ -19,20 +19,21 @@ impl Config {
         }
     }
 
-    pub fn get_config_from_env_var(&self, name: &str) -> String {
+    pub fn get_config_from_env_var(&self, name: &str) -> Result<String> {
         tracing::info!("Retrieving value from environment variable {}", name);
-        let env_var: String = match std::env::var(&name) {
-            Ok(env_var) => env_var,
+        match std::env::var(name) {
+            Ok(env_var) => Ok(env_var),
             Err(err) => {
                 tracing::error!("Environment variable {} not found", name);
                 tracing::error!("Error {}", err);
-                std::process::exit(1);
+                Err(Error::ConfigMissing {
+                    name: name.to_string(),
+                })
             }
-        };
-        return env_var;
+        }
     }
 
-    fn get_config_from_file(&self, name: &str) -> Result<String, Error> {
+    fn get_config_from_file(&self, name: &str) -> std::io::Result<String> {
         let filepath = format!("{}{}", self.filepath, name);
         tracing::info!("Reading variable from: {}", filepath);
 
@@ -53,13 +54,24 @@ impl Config {
         Ok(content)
     }
 
-    pub fn get_config(&self, name: &str) -> String {
+    /// Like `get_config`, for settings that fall back to a default when unset.
+    pub fn get_optional_config(&self, name: &str) -> Option<String> {
+        if !self.filepath.is_empty() {
+            if let Ok(value) = self.get_config_from_file(name) {
+                return Some(value);
+            }
+        }
+
+        std::env::var(name).ok()
+    }
+
+    pub fn get_config(&self, name: &str) -> Result<String> {
         if self.filepath.is_empty() {
             return self.get_config_from_env_var(name);
         }
 
-        let value = match self.get_config_from_file(name) {
-            Ok(value) => value,
+        match self.get_config_from_file(name) {
+            Ok(value) => Ok(value),
             Err(_) => {
                 tracing::info!(
                     "Unable to read {} from file, trying environment variable",
@@ -67,7 +79,6 @@ impl Config {
                 );
                 self.get_config_from_env_var(name)
             }
-        };
-        return value;
+        }
     }
 }
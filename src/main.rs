@@ -1,26 +1,40 @@
+mod auth;
+mod cursor;
 mod error;
+mod etag;
 mod handler;
 mod helper;
+mod memory;
 mod model;
 mod mongo;
+mod permission;
 mod pg;
 mod response;
 mod route;
 mod schema;
+mod store;
+mod tenant;
+mod validation;
 
 pub use self::error::{Error, Result};
 
+use auth::Tokens;
 use autometrics::prometheus_exporter;
+use cursor::CursorSigner;
 // use dotenvy::dotenv;
 use helper::Config;
+use memory::MemoryStore;
 use mongo::MONGO;
 use pg::PG;
 use route::create_router;
+use std::sync::Arc;
+use store::{CustomerDeletePolicy, UserStore};
+use tenant::{Tenant, TenantResolver};
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{layer::SubscriberExt, Registry};
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
     prometheus_exporter::init();
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", "app=info,tower_http=trace");
@@ -43,34 +57,115 @@ async fn main() {
     tracing::info!("Initializing config...");
     let config = Config::init();
 
-    tracing::info!("Retrieving Configuration Variables ...");
-    let pg_username: String = config.get_config("POSTGRES_USER");
-    let pg_passwd: String = config.get_config("POSTGRES_PASSWORD");
-    let pg_url: String = config.get_config("POSTGRES_URL");
-    let pg_db: String = config.get_config("POSTGRES_DB");
-    let mongodb_username: String = config.get_config("ME_CONFIG_MONGODB_ADMINUSERNAME");
-    let mongodb_passwd: String = config.get_config("ME_CONFIG_MONGODB_ADMINPASSWORD");
-    let mongodb_server: String = config.get_config("ME_CONFIG_MONGODB_SERVER");
-
-    tracing::info!("Setting up connection to Postgresql...");
-    let pg = PG::init(pg_username, pg_passwd, pg_url, pg_db)
-        .await
-        .unwrap();
-    tracing::info!("Setting up connection to MongoDB...");
-    let mongo = MONGO::init(mongodb_username, mongodb_passwd, mongodb_server)
-        .await
-        .unwrap();
+    let on_customer_delete = match config.get_optional_config("CUSTOMER_DELETE_POLICY") {
+        Some(value) => value.parse()?,
+        None => CustomerDeletePolicy::default(),
+    };
+    tracing::info!("Deleting a customer with orders: {:?}", on_customer_delete);
+
+    let cursors = match config.get_optional_config("CURSOR_SECRET") {
+        Some(secret) => CursorSigner::new(secret.trim().as_bytes()),
+        None => {
+            tracing::warn!("CURSOR_SECRET is not set, cursors will not survive a restart");
+            CursorSigner::random()
+        }
+    };
 
-    let app = create_router(pg.clone(), mongo.clone());
+    let tokens = Tokens::from_config(&config)?;
+    tracing::info!("Verifying access tokens with {:?}", tokens.verifier);
+    let tenants = TenantResolver::from_config(&config);
+    tracing::info!("Resolving tenants with {:?}", tenants);
+
+    let app = if std::env::var("STORAGE_BACKEND").as_deref() == Ok("memory") {
+        tracing::warn!("Using the in-memory storage backend, data will not be persisted");
+        let store = MemoryStore::default();
+        bootstrap_admin(&store, &config).await?;
+        create_router(
+            store.clone(),
+            store.clone(),
+            store.clone(),
+            store,
+            on_customer_delete,
+            cursors,
+            tokens,
+            tenants,
+        )
+    } else {
+        tracing::info!("Retrieving Configuration Variables ...");
+        let pg_username: String = config.get_config("POSTGRES_USER")?;
+        let pg_passwd: String = config.get_config("POSTGRES_PASSWORD")?;
+        let pg_url: String = config.get_config("POSTGRES_URL")?;
+        let pg_db: String = config.get_config("POSTGRES_DB")?;
+        let mongodb_username: String = config.get_config("ME_CONFIG_MONGODB_ADMINUSERNAME")?;
+        let mongodb_passwd: String = config.get_config("ME_CONFIG_MONGODB_ADMINPASSWORD")?;
+        let mongodb_server: String = config.get_config("ME_CONFIG_MONGODB_SERVER")?;
+
+        tracing::info!("Setting up connection to Postgresql...");
+        let pg = PG::init(pg_username, pg_passwd, pg_url, pg_db).await?;
+        tracing::info!("Setting up connection to MongoDB...");
+        let mongo = MONGO::init(
+            mongodb_username,
+            mongodb_passwd,
+            mongodb_server,
+            Arc::new(pg.clone()),
+            Arc::new(pg.clone()),
+        )
+        .await?;
+
+        bootstrap_admin(&pg, &config).await?;
+
+        create_router(
+            pg.clone(),
+            mongo.clone(),
+            pg.clone(),
+            pg,
+            on_customer_delete,
+            cursors,
+            tokens,
+            tenants,
+        )
+    };
 
     tracing::info!("🚀 Server started successfully");
     axum::Server::bind(&"0.0.0.0:8000".parse().unwrap())
         .serve(app.into_make_service())
         .await
         .unwrap();
+
+    Ok(())
+}
+
+/// Creates the user named by `ADMIN_USERNAME` with `ADMIN_PASSWORD` and the
+/// scopes of `ADMIN_SCOPE` unless it exists, so that a fresh deployment has
+/// someone who can log in.
+async fn bootstrap_admin<U: UserStore>(users: &U, config: &Config) -> Result<()> {
+    let username = match config.get_optional_config("ADMIN_USERNAME") {
+        Some(username) => username.trim().to_string(),
+        None => return Ok(()),
+    };
+    if users.get_user_by_username(&username).await?.is_some() {
+        return Ok(());
+    }
+
+    let password = config.get_config("ADMIN_PASSWORD")?.trim().to_string();
+    let scope = config
+        .get_optional_config("ADMIN_SCOPE")
+        .unwrap_or_default();
+    // an admin bound to no tenant needs `tenants:*` to act on any but the default
+    let tenant = config
+        .get_optional_config("ADMIN_TENANT")
+        .map(|tenant| Tenant::parse(tenant.trim()))
+        .transpose()?;
+    let password_hash = auth::hash_password(password).await?;
+    users
+        .create_user(tenant.as_ref(), &username, &password_hash, scope.trim())
+        .await?;
+    tracing::info!("Created the admin user {}", username);
+
+    Ok(())
 }
 
-fn string_to_level_filter(level: &String) -> Option<LevelFilter> {
+fn string_to_level_filter(level: &str) -> Option<LevelFilter> {
     match level.to_lowercase().as_str() {
         "error" => Some(LevelFilter::ERROR),
         "warn" => Some(LevelFilter::WARN),
@@ -84,6 +179,8 @@ fn string_to_level_filter(level: &String) -> Option<LevelFilter> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::{TokenIssuer, TokenVerifier};
+    use crate::store::ProductStore;
     use crate::{response::*, schema::*};
     use axum::http::Method;
     use axum::{
@@ -91,68 +188,752 @@ mod tests {
         http::{self, Request, StatusCode},
         Router,
     };
-    use serde_json::json;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::{json, Value};
     use tower::ServiceExt; // for `oneshot` and `ready`
 
-    async fn init() -> Router {
-        let config = Config::init();
+    const TEST_JWT_SECRET: &[u8] = b"test";
+    const ALL_SCOPES: &str = "customers:read customers:write customers:delete orders:read \
+         orders:write orders:delete products:read products:write products:delete";
 
-        // retrieve configuration variables
-        let pg_username: String = config.get_config("POSTGRES_USER");
-        let pg_passwd: String = config.get_config("POSTGRES_PASSWORD");
-        let pg_url: String = config.get_config("POSTGRES_URL");
-        let pg_db: String = config.get_config("POSTGRES_DB");
-        let mongodb_username: String = config.get_config("ME_CONFIG_MONGODB_ADMINUSERNAME");
-        let mongodb_passwd: String = config.get_config("ME_CONFIG_MONGODB_ADMINPASSWORD");
-        let mongodb_server: String = config.get_config("ME_CONFIG_MONGODB_SERVER");
+    const ORDER_PRODUCTS: &[&str] = &[
+        "apple",
+        "banana",
+        "cherry",
+        "groceries",
+        "pear",
+        "Pineapple",
+        "Paul's pears",
+    ];
 
-        let pg = PG::init(pg_username, pg_passwd, pg_url, pg_db)
-            .await
-            .unwrap();
-        let mongo = MONGO::init(mongodb_username, mongodb_passwd, mongodb_server)
-            .await
-            .unwrap();
+    fn init() -> Router {
+        init_with_policy(CustomerDeletePolicy::default())
+    }
+
+    fn init_with_policy(on_customer_delete: CustomerDeletePolicy) -> Router {
+        let store = MemoryStore::default();
+
+        create_router(
+            store.clone(),
+            store.clone(),
+            store.clone(),
+            store,
+            on_customer_delete,
+            CursorSigner::new(b"test"),
+            Tokens::hs256(TEST_JWT_SECRET),
+            TenantResolver::default(),
+        )
+    }
+
+    /// A catalog whose stock can be reserved but never put back or settled.
+    #[derive(Debug)]
+    struct StuckStock(MemoryStore);
+
+    #[async_trait::async_trait]
+    impl ProductStore for StuckStock {
+        async fn create_product(
+            &self,
+            body: &CreateProductSchema,
+        ) -> Result<SingleProductResponse> {
+            self.0.create_product(body).await
+        }
+
+        async fn list_products(&self, page: PageRequest) -> Result<ProductListResponse> {
+            self.0.list_products(page).await
+        }
+
+        async fn get_product(&self, id: &str) -> Result<SingleProductResponse> {
+            self.0.get_product(id).await
+        }
+
+        async fn get_product_by_sku(&self, sku: &str) -> Result<ProductResponse> {
+            self.0.get_product_by_sku(sku).await
+        }
+
+        async fn update_product(
+            &self,
+            id: &str,
+            body: &CreateProductSchema,
+            if_match: Option<&[i32]>,
+        ) -> Result<SingleProductResponse> {
+            self.0.update_product(id, body, if_match).await
+        }
+
+        async fn patch_product(
+            &self,
+            id: &str,
+            body: &UpdateProductSchema,
+            if_match: Option<&[i32]>,
+        ) -> Result<SingleProductResponse> {
+            self.0.patch_product(id, body, if_match).await
+        }
+
+        async fn delete_product(
+            &self,
+            id: &str,
+            if_match: Option<&[i32]>,
+        ) -> Result<SingleProductResponse> {
+            self.0.delete_product(id, if_match).await
+        }
+
+        async fn reserve_stock(
+            &self,
+            order_id: &str,
+            items: &[OrderItemSchema],
+        ) -> Result<Vec<model::LineItem>> {
+            self.0.reserve_stock(order_id, items).await
+        }
+
+        async fn release_stock(&self, _order_id: &str) -> Result<u64> {
+            Err(Error::HandlerError)
+        }
+
+        async fn settle_stock(&self, _order_id: &str) -> Result<u64> {
+            Err(Error::HandlerError)
+        }
+    }
+
+    fn init_with_stuck_stock(on_customer_delete: CustomerDeletePolicy) -> Router {
+        let store = MemoryStore::default();
+
+        create_router(
+            store.clone(),
+            store.clone(),
+            StuckStock(store.clone()),
+            store,
+            on_customer_delete,
+            CursorSigner::new(b"test"),
+            Tokens::hs256(TEST_JWT_SECRET),
+            TenantResolver::default(),
+        )
+    }
+
+    /// An access token for `subject` that expires `ttl` seconds from now.
+    fn sign_token(
+        key: &EncodingKey,
+        header: &Header,
+        subject: &str,
+        scope: &str,
+        ttl: i64,
+    ) -> String {
+        let claims = auth::Claims {
+            sub: subject.to_string(),
+            exp: (chrono::Utc::now().timestamp() + ttl) as u64,
+            scope: scope.to_string(),
+            tenant: None,
+        };
+        encode(header, &claims, key).unwrap()
+    }
+
+    /// An operator token that may act on any tenant.
+    fn operator_token() -> String {
+        format!(
+            "Bearer {}",
+            sign_token(
+                &EncodingKey::from_secret(TEST_JWT_SECRET),
+                &Header::default(),
+                "ops",
+                &format!("admin {} {}", ALL_SCOPES, tenant::ANY_TENANT_SCOPE),
+                3600,
+            )
+        )
+    }
 
-        create_router(pg.clone(), mongo.clone())
+    fn test_token() -> String {
+        sign_token(
+            &EncodingKey::from_secret(TEST_JWT_SECRET),
+            &Header::default(),
+            "test",
+            ALL_SCOPES,
+            3600,
+        )
     }
 
     fn get_customer_model(name: &str, surname: &str) -> CreateCustomerSchema {
         CreateCustomerSchema {
             customer_name: name.to_string(),
             customer_surname: surname.to_string(),
+            email: None,
+            phone: None,
+            addresses: Vec::new(),
         }
     }
 
-    fn get_order_schema(customer_name: &str, product_name: &str) -> CreateOrderSchema {
+    /// An order of one `product_name`, one of the `ORDER_PRODUCTS`.
+    fn get_order_schema(customer_id: &str, product_name: &str) -> CreateOrderSchema {
         CreateOrderSchema {
-            customer_name: customer_name.to_string(),
-            product_name: product_name.to_string(),
+            customer_id: customer_id.to_string(),
+            items: vec![OrderItemSchema {
+                sku: order_sku(product_name),
+                quantity: 1,
+            }],
+            currency: "EUR".to_string(),
+        }
+    }
+
+    fn order_sku(product_name: &str) -> String {
+        let name: String = product_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        format!("ORDER-{}", name.to_uppercase())
+    }
+
+    /// Orders must reference an existing customer, returns the id of a new one.
+    async fn create_test_customer(app: &Router) -> String {
+        create_named_customer(app, "paul", "doe").await
+    }
+
+    async fn create_named_customer(app: &Router, name: &str, surname: &str) -> String {
+        let (status_code, response) = api_call(
+            app,
+            http::Method::POST,
+            "/api/pg",
+            Body::from(serde_json::to_vec(&json!(get_customer_model(name, surname))).unwrap()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+
+        response["id"].as_str().unwrap().to_string()
+    }
+
+    async fn api_call(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Body,
+    ) -> (StatusCode, serde_json::Value) {
+        let (status, _, response) = api_call_with_headers(app, method, uri, &[], body).await;
+        (status, response)
+    }
+
+    async fn api_call_with_headers(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        headers: &[(http::HeaderName, &str)],
+        body: Body,
+    ) -> (StatusCode, http::HeaderMap, serde_json::Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        // every call is authenticated unless the test sends its own credentials
+        if !headers.iter().any(|(name, _)| {
+            name == http::header::AUTHORIZATION
+                || name == http::header::COOKIE
+                || name == auth::API_KEY_HEADER
+        }) {
+            request = request.header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", test_token()),
+            );
         }
+
+        let response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let response_headers = response.headers().clone();
+
+        let response_body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        // a 304 Not Modified has no body
+        let response_json = if response_body.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&response_body).unwrap()
+        };
+        (status, response_headers, response_json)
     }
+    #[tokio::test]
+    async fn access_tokens() {
+        let app = init();
+        let key = EncodingKey::from_secret(TEST_JWT_SECRET);
 
-    async fn api_call(method: Method, uri: &str, body: Body) -> (StatusCode, serde_json::Value) {
-        // dotenv().expect(".env file not found");
-        let app = init().await;
+        // the health check stays public
         let response = app
+            .clone()
             .oneshot(
-                Request::builder()
-                    .method(method)
-                    .uri(uri)
-                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
-                    .body(body)
+                Request::get("/api/healthchecker")
+                    .body(Body::empty())
                     .unwrap(),
             )
             .await
             .unwrap();
-        let status = response.status();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let response_body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        (status, serde_json::from_slice(&response_body).unwrap())
+        let response = app
+            .clone()
+            .oneshot(Request::get("/api/pg").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[http::header::WWW_AUTHENTICATE], "Bearer");
+
+        for (authorization, expected_status) in [
+            ("Basic dGVzdDp0ZXN0".to_string(), StatusCode::UNAUTHORIZED),
+            ("Bearer ".to_string(), StatusCode::UNAUTHORIZED),
+            ("Bearer not-a-jwt".to_string(), StatusCode::UNAUTHORIZED),
+            (
+                format!(
+                    "Bearer {}",
+                    sign_token(
+                        &EncodingKey::from_secret(b"other"),
+                        &Header::default(),
+                        "test",
+                        "",
+                        3600
+                    )
+                ),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                format!(
+                    "Bearer {}",
+                    sign_token(&key, &Header::default(), "test", "", -3600)
+                ),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                format!(
+                    "Bearer {}",
+                    sign_token(&key, &Header::default(), "test", ALL_SCOPES, 3600)
+                ),
+                StatusCode::OK,
+            ),
+        ] {
+            for uri in ["/api/pg", "/api/mongo", "/api/search?q=paul"] {
+                let (status_code, _, response) = api_call_with_headers(
+                    &app,
+                    http::Method::GET,
+                    uri,
+                    &[(http::header::AUTHORIZATION, &authorization)],
+                    Body::empty(),
+                )
+                .await;
+                println!("{:?}", response);
+                assert_eq!(status_code, expected_status);
+                if status_code == StatusCode::UNAUTHORIZED {
+                    assert_eq!(response["code"], "NO_AUTH");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn scope_permissions() {
+        let app = init();
+        let key = EncodingKey::from_secret(TEST_JWT_SECRET);
+        let customer_id = uuid::Uuid::new_v4();
+
+        for (scope, method, uri, missing) in [
+            ("orders:read", Method::GET, "/api/mongo".to_string(), None),
+            (
+                "orders:read",
+                Method::POST,
+                "/api/mongo".to_string(),
+                Some(vec!["orders:write"]),
+            ),
+            (
+                "orders:read orders:write",
+                Method::DELETE,
+                "/api/mongo/650f2e3e7d3c4b1a2b3c4d5e".to_string(),
+                Some(vec!["orders:delete"]),
+            ),
+            (
+                "orders:read",
+                Method::GET,
+                "/api/pg".to_string(),
+                Some(vec!["customers:read"]),
+            ),
+            (
+                "customers:read",
+                Method::GET,
+                format!("/api/pg/{}/orders", customer_id),
+                Some(vec!["orders:read"]),
+            ),
+            (
+                "customers:read",
+                Method::GET,
+                "/api/search?q=paul".to_string(),
+                Some(vec!["orders:read"]),
+            ),
+            (
+                "customers:read",
+                Method::PATCH,
+                format!("/api/pg/{}", customer_id),
+                Some(vec!["customers:write"]),
+            ),
+            (
+                "customers:delete",
+                Method::DELETE,
+                format!("/api/pg/{}", customer_id),
+                None,
+            ),
+        ] {
+            let token = sign_token(&key, &Header::default(), "test", scope, 3600);
+            let (status_code, _, response) = api_call_with_headers(
+                &app,
+                method.clone(),
+                &uri,
+                &[(http::header::AUTHORIZATION, &format!("Bearer {}", token))],
+                Body::from("{}"),
+            )
+            .await;
+            println!("{} {} {:?}", method, uri, response);
+            match missing {
+                Some(missing) => {
+                    assert_eq!(status_code, StatusCode::FORBIDDEN);
+                    assert_eq!(response["code"], "INSUFFICIENT_SCOPE");
+                    assert_eq!(response["missing"], json!(missing));
+                }
+                // permitted requests reach the handler, which may still fail
+                None => assert_ne!(status_code, StatusCode::FORBIDDEN),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn api_keys() {
+        let app = init();
+        let admin_token = sign_token(
+            &EncodingKey::from_secret(TEST_JWT_SECRET),
+            &Header::default(),
+            "ops",
+            "admin orders:read",
+            3600,
+        );
+        let admin = format!("Bearer {}", admin_token);
+        let api_key_header = http::HeaderName::from_static(auth::API_KEY_HEADER);
+        let mint = |body: Value| {
+            let app = app.clone();
+            let admin = admin.clone();
+            async move {
+                api_call_with_headers(
+                    &app,
+                    http::Method::POST,
+                    "/api/admin/api-keys",
+                    &[(http::header::AUTHORIZATION, &admin)],
+                    Body::from(body.to_string()),
+                )
+                .await
+            }
+        };
+
+        // minting keys takes the admin scope
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/admin/api-keys",
+            Body::from(json!({"name": "nightly export"}).to_string()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+        assert_eq!(response["missing"], json!(["admin"]));
+
+        // a key cannot be granted scopes its minter lacks
+        let (status_code, _, response) =
+            mint(json!({"name": "nightly export", "scopes": ["orders:write"]})).await;
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+        assert_eq!(response["missing"], json!(["orders:write"]));
+
+        let (status_code, _, response) = mint(json!({
+            "name": "nightly export",
+            "scopes": ["orders:read"],
+            "expires_at": "2000-01-01T00:00:00Z"
+        }))
+        .await;
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["errors"][0]["field"], "expires_at");
+
+        let (status_code, _, response) =
+            mint(json!({"name": "nightly export", "scopes": ["orders:read"]})).await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        let key = response["key"].as_str().unwrap().to_string();
+        let id = response["id"].as_str().unwrap().to_string();
+        assert!(key.starts_with(response["prefix"].as_str().unwrap()));
+        assert_eq!(response["owner"], "ops");
+        assert_eq!(response["scopes"], json!(["orders:read"]));
+
+        // the key acts with its own scopes
+        for (method, uri, expected_status) in [
+            (Method::GET, "/api/mongo", StatusCode::OK),
+            (Method::POST, "/api/mongo", StatusCode::FORBIDDEN),
+            (Method::GET, "/api/pg", StatusCode::FORBIDDEN),
+        ] {
+            let (status_code, _, _) = api_call_with_headers(
+                &app,
+                method,
+                uri,
+                &[(api_key_header.clone(), &key)],
+                Body::from("{}"),
+            )
+            .await;
+            assert_eq!(status_code, expected_status);
+        }
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            Method::GET,
+            "/api/mongo",
+            &[(api_key_header.clone(), "crk_not-a-key")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+
+        // listing never shows the key again
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            Method::GET,
+            "/api/admin/api-keys",
+            &[(http::header::AUTHORIZATION, &admin)],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let listed = &response["data"][0];
+        assert_eq!(listed["id"], id.as_str());
+        assert!(!response.to_string().contains(&key));
+        assert!(listed["last_used_at"].is_string());
+        assert!(listed["revoked_at"].is_null());
+
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            Method::DELETE,
+            &format!("/api/admin/api-keys/{}", id),
+            &[(http::header::AUTHORIZATION, &admin)],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(response["revoked_at"].is_string());
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            Method::GET,
+            "/api/mongo",
+            &[(api_key_header.clone(), &key)],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            Method::DELETE,
+            &format!("/api/admin/api-keys/{}", uuid::Uuid::new_v4()),
+            &[(http::header::AUTHORIZATION, &admin)],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn rs256_access_tokens() {
+        let private_key = include_bytes!("../testdata/jwt_rs256_private.pem");
+        let app = create_router(
+            MemoryStore::default(),
+            MemoryStore::default(),
+            MemoryStore::default(),
+            MemoryStore::default(),
+            CustomerDeletePolicy::default(),
+            CursorSigner::new(b"test"),
+            Tokens {
+                verifier: TokenVerifier::rs256(include_bytes!("../testdata/jwt_rs256_public.pem"))
+                    .unwrap(),
+                issuer: TokenIssuer::rs256(private_key).unwrap(),
+                refresh_ttl: 3600,
+            },
+            TenantResolver::default(),
+        );
+        let key = EncodingKey::from_rsa_pem(private_key).unwrap();
+
+        for (token, expected_status) in [
+            (
+                sign_token(
+                    &key,
+                    &Header::new(jsonwebtoken::Algorithm::RS256),
+                    "test",
+                    "customers:read",
+                    3600,
+                ),
+                StatusCode::OK,
+            ),
+            // an HS256 token must not pass for one of the issuer
+            (test_token(), StatusCode::UNAUTHORIZED),
+        ] {
+            let (status_code, _, _) = api_call_with_headers(
+                &app,
+                http::Method::GET,
+                "/api/pg",
+                &[(http::header::AUTHORIZATION, &format!("Bearer {}", token))],
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(status_code, expected_status);
+        }
+    }
+
+    #[tokio::test]
+    async fn login_refresh_and_logout() {
+        let store = MemoryStore::default();
+        let password_hash = auth::hash_password("s3cret".to_string()).await.unwrap();
+        store
+            .create_user(None, "admin", &password_hash, "customers:read")
+            .await
+            .unwrap();
+        let app = create_router(
+            store.clone(),
+            store.clone(),
+            store.clone(),
+            store,
+            CustomerDeletePolicy::default(),
+            CursorSigner::new(b"test"),
+            Tokens::hs256(TEST_JWT_SECRET),
+            TenantResolver::default(),
+        );
+        let post = |uri: &'static str, headers: Vec<(http::HeaderName, String)>, body: Value| {
+            let app = app.clone();
+            async move {
+                let headers: Vec<(http::HeaderName, &str)> = headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.as_str()))
+                    .collect();
+                api_call_with_headers(
+                    &app,
+                    http::Method::POST,
+                    uri,
+                    &headers,
+                    Body::from(body.to_string()),
+                )
+                .await
+            }
+        };
+        let login = || {
+            post(
+                "/api/auth/login",
+                vec![],
+                json!({"username": "admin", "password": "s3cret"}),
+            )
+        };
+        let refresh = |token: &str| {
+            post(
+                "/api/auth/refresh",
+                vec![],
+                json!({ "refresh_token": token }),
+            )
+        };
+
+        // an unknown user fails like a wrong password
+        for (username, password) in [("admin", "wrong"), ("nobody", "s3cret")] {
+            let (status_code, _, response) = post(
+                "/api/auth/login",
+                vec![],
+                json!({"username": username, "password": password}),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+            assert_eq!(response["code"], "LOGIN_FAIL");
+        }
+
+        let (status_code, headers, response) = login().await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["token_type"], "Bearer");
+        assert_eq!(response["expires_in"], 900);
+        let access_token = response["access_token"].as_str().unwrap().to_string();
+        let first_refresh_token = response["refresh_token"].as_str().unwrap().to_string();
+        let cookies: Vec<&str> = headers
+            .get_all(http::header::SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            cookies,
+            [
+                format!(
+                    "access_token={}; HttpOnly; Secure; SameSite=Strict; Path=/api; Max-Age=900",
+                    access_token
+                ),
+                format!(
+                    "refresh_token={}; HttpOnly; Secure; SameSite=Strict; Path=/api/auth; Max-Age=1209600",
+                    first_refresh_token
+                ),
+            ]
+        );
+
+        // the access token is accepted as a bearer token and as a cookie
+        for header in [
+            (
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", access_token),
+            ),
+            (
+                http::header::COOKIE,
+                format!("access_token={}", access_token),
+            ),
+        ] {
+            let (status_code, _, _) = api_call_with_headers(
+                &app,
+                http::Method::GET,
+                "/api/pg",
+                &[(header.0, &header.1)],
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::OK);
+        }
+
+        // every refresh hands out a new refresh token, read from the body or the cookie
+        let (status_code, _, response) = refresh(&first_refresh_token).await;
+        assert_eq!(status_code, StatusCode::OK);
+        let second_refresh_token = response["refresh_token"].as_str().unwrap().to_string();
+        assert_ne!(second_refresh_token, first_refresh_token);
+        let (status_code, _, response) = post(
+            "/api/auth/refresh",
+            vec![(
+                http::header::COOKIE,
+                format!("refresh_token={}", second_refresh_token),
+            )],
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let third_refresh_token = response["refresh_token"].as_str().unwrap().to_string();
+
+        // reusing a refresh token revokes its whole family
+        let (status_code, _, _) = refresh(&first_refresh_token).await;
+        assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+        let (status_code, _, _) = refresh(&third_refresh_token).await;
+        assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+
+        // logging out revokes the refresh token and clears the cookies
+        let (_, _, response) = login().await;
+        let refresh_token = response["refresh_token"].as_str().unwrap().to_string();
+        let (status_code, headers, _) = post(
+            "/api/auth/logout",
+            vec![],
+            json!({ "refresh_token": refresh_token }),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(headers
+            .get_all(http::header::SET_COOKIE)
+            .iter()
+            .all(|value| value.to_str().unwrap().ends_with("Max-Age=0")));
+        let (status_code, _, _) = refresh(&refresh_token).await;
+        assert_eq!(status_code, StatusCode::UNAUTHORIZED);
     }
+
     #[tokio::test]
     async fn health_check() {
+        let app = init();
         let (status_code, response) =
-            api_call(http::Method::GET, "/api/healthchecker", Body::empty()).await;
+            api_call(&app, http::Method::GET, "/api/healthchecker", Body::empty()).await;
         assert_eq!(status_code, StatusCode::OK);
         let expected_json = GenericResponse {
             status: "success".to_string(),
@@ -167,9 +948,11 @@ mod tests {
 
     #[tokio::test]
     async fn create_customer() {
+        let app = init();
         let input = get_customer_model("paul", "doe");
 
         let (status_code, response) = api_call(
+            &app,
             http::Method::POST,
             "/api/pg",
             Body::from(serde_json::to_vec(&json!(input)).unwrap()),
@@ -187,11 +970,61 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn create_customer_validation() {
+        let app = init();
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/pg",
+            Body::from(
+                serde_json::to_vec(&json!({
+                    "customer_name": "   ",
+                    "customer_surname": "a".repeat(101),
+                }))
+                .unwrap(),
+            ),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["code"], "VALIDATION_FAILED");
+        assert_eq!(
+            response["errors"],
+            json!([
+                {"field": "customer_name", "code": "not_blank", "message": "customer_name must not be blank"},
+                {"field": "customer_surname", "code": "max_length", "message": "customer_surname must be at most 100 characters long"},
+            ])
+        );
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/pg",
+            Body::from(serde_json::to_vec(&json!({"customer_name": "j0hn"})).unwrap()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["errors"][0]["field"], "customer_name");
+        assert_eq!(response["errors"][0]["code"], "charset");
+        assert_eq!(response["errors"][1]["field"], "customer_surname");
+        assert_eq!(response["errors"][1]["code"], "required");
+
+        let (status_code, response) =
+            api_call(&app, http::Method::GET, "/api/pg", Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["data"], json!([]));
+    }
+
     #[tokio::test]
     async fn get_customer() {
+        let app = init();
         let input = get_customer_model("Blanche", "Jarvis");
 
         let (status_code, response) = api_call(
+            &app,
             http::Method::POST,
             "/api/pg",
             Body::from(serde_json::to_vec(&json!(input)).unwrap()),
@@ -203,7 +1036,8 @@ mod tests {
         let id = response.get("id").unwrap().as_str().unwrap();
         let uri = format!("/api/pg/{}", id);
 
-        let (status_code, response_get) = api_call(http::Method::GET, &uri, Body::empty()).await;
+        let (status_code, response_get) =
+            api_call(&app, http::Method::GET, &uri, Body::empty()).await;
         println!("{:?}", response_get);
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(
@@ -218,9 +1052,11 @@ mod tests {
 
     #[tokio::test]
     async fn list_customers() {
+        let app = init();
         let input = get_customer_model("Rafael", "Scott");
 
         let (status_code, response) = api_call(
+            &app,
             http::Method::POST,
             "/api/pg",
             Body::from(serde_json::to_vec(&json!(input)).unwrap()),
@@ -229,7 +1065,8 @@ mod tests {
         println!("{:?}", response);
         assert_eq!(status_code, StatusCode::CREATED);
 
-        let (status_code, response) = api_call(http::Method::GET, "/api/pg", Body::empty()).await;
+        let (status_code, response) =
+            api_call(&app, http::Method::GET, "/api/pg", Body::empty()).await;
         println!("{:?}", response);
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(response.get("status").unwrap().as_str().unwrap(), "success");
@@ -237,9 +1074,11 @@ mod tests {
 
     #[tokio::test]
     async fn delete_customer() {
+        let app = init();
         let input = get_customer_model("Polly", "Shepard");
 
         let (status_code, response) = api_call(
+            &app,
             http::Method::POST,
             "/api/pg",
             Body::from(serde_json::to_vec(&json!(input)).unwrap()),
@@ -251,7 +1090,8 @@ mod tests {
         let id = response.get("id").unwrap().as_str().unwrap();
         let uri = format!("/api/pg/{}", id);
 
-        let (status_code, response_get) = api_call(http::Method::DELETE, &uri, Body::empty()).await;
+        let (status_code, response_get) =
+            api_call(&app, http::Method::DELETE, &uri, Body::empty()).await;
         println!("{:?}", response_get);
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(
@@ -263,10 +1103,12 @@ mod tests {
 
     #[tokio::test]
     async fn patch_customer() {
+        let app = init();
         let original_input = get_customer_model("Polly", "Shepard");
         let modified_input = get_customer_model("Hattie", "Rodgers");
 
         let (status_code, response_create) = api_call(
+            &app,
             http::Method::POST,
             "/api/pg",
             Body::from(serde_json::to_vec(&json!(original_input)).unwrap()),
@@ -279,6 +1121,7 @@ mod tests {
         let uri = format!("/api/pg/{}", id);
 
         let (status_code, response_patch) = api_call(
+            &app,
             http::Method::PATCH,
             &uri,
             Body::from(serde_json::to_vec(&json!(modified_input)).unwrap()),
@@ -291,7 +1134,8 @@ mod tests {
             "success"
         );
 
-        let (status_code, response_get) = api_call(http::Method::GET, &uri, Body::empty()).await;
+        let (status_code, response_get) =
+            api_call(&app, http::Method::GET, &uri, Body::empty()).await;
         println!("{:?}", response_get);
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(
@@ -305,75 +1149,271 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn create_order() {
-        let input = get_order_schema("paul", "banana");
+    async fn merge_patch_customer() {
+        let app = init();
+        let input = get_customer_model("Polly", "Shepard");
 
-        let (status_code, response) = api_call(
+        let (status_code, response_create) = api_call(
+            &app,
             http::Method::POST,
-            "/api/mongo",
+            "/api/pg",
             Body::from(serde_json::to_vec(&json!(input)).unwrap()),
         )
         .await;
-        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CREATED);
+
+        let id = response_create.get("id").unwrap().as_str().unwrap();
+        let uri = format!("/api/pg/{}", id);
+
+        // explicit null clears the surname, the omitted name is left untouched
+        let (status_code, response_patch) = api_call(
+            &app,
+            http::Method::PATCH,
+            &uri,
+            Body::from(serde_json::to_vec(&json!({"customer_surname": null})).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_patch);
         assert_eq!(status_code, StatusCode::OK);
-        assert_eq!(
-            response["data"]["order"]["customer_name"].as_str().unwrap(),
-            input.customer_name.as_str()
-        );
-        assert_eq!(
-            response["data"]["order"]["product_name"].as_str().unwrap(),
-            input.product_name.as_str()
-        );
+        assert_eq!(response_patch["name"], "Polly");
+        assert!(response_patch["surname"].is_null());
+
+        let (status_code, response_patch) = api_call(
+            &app,
+            http::Method::PATCH,
+            &uri,
+            Body::from(serde_json::to_vec(&json!({"customer_name": "Hattie"})).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_patch);
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, response_get) =
+            api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response_get["name"], "Hattie");
+        assert!(response_get["surname"].is_null());
     }
 
     #[tokio::test]
-    async fn get_order() {
-        let input = get_order_schema("paul", "banana");
+    async fn put_customer() {
+        let app = init();
+        let input = get_customer_model("Polly", "Shepard");
+        let replacement = get_customer_model("Hattie", "Rodgers");
 
-        let (status_code, response_post) = api_call(
+        let (status_code, response_create) = api_call(
+            &app,
             http::Method::POST,
-            "/api/mongo",
+            "/api/pg",
             Body::from(serde_json::to_vec(&json!(input)).unwrap()),
         )
         .await;
-        println!("{:?}", response_post);
-        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(status_code, StatusCode::CREATED);
+
+        let id = response_create.get("id").unwrap().as_str().unwrap();
+        let uri = format!("/api/pg/{}", id);
+
+        // a full replacement needs every field
+        let (status_code, response_put) = api_call(
+            &app,
+            http::Method::PUT,
+            &uri,
+            Body::from(serde_json::to_vec(&json!({"customer_name": "Hattie"})).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_put);
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response_put["errors"][0]["field"], "customer_surname");
+
+        let (status_code, response_put) = api_call(
+            &app,
+            http::Method::PUT,
+            &uri,
+            Body::from(serde_json::to_vec(&json!(replacement)).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_put);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response_put["name"], "Hattie");
+        assert_eq!(response_put["surname"], "Rodgers");
+    }
+
+    #[tokio::test]
+    async fn missing_customer_not_found() {
+        let app = init();
+        let id = "6f1c8f1e-1f0b-4f43-9d2a-8d1f2b3c4d5e";
+        let uri = format!("/api/pg/{}", id);
+        let body = get_customer_model("Hattie", "Rodgers");
+
+        for (method, body) in [
+            (http::Method::GET, Body::empty()),
+            (
+                http::Method::PATCH,
+                Body::from(serde_json::to_vec(&json!(body)).unwrap()),
+            ),
+            (http::Method::DELETE, Body::empty()),
+        ] {
+            let (status_code, response) = api_call(&app, method, &uri, body).await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+            assert_eq!(response["status"], 404);
+            assert_eq!(response["code"], "RESOURCE_NOT_FOUND");
+            assert_eq!(response["resource"], "customer");
+            assert_eq!(response["id"], id);
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_id_problem_details() {
+        let app = init();
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/api/pg/not-a-uuid")
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {}", test_token()),
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        let request_id = response.headers()[route::REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response_body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&response_body).unwrap();
+        println!("{:?}", response);
+        assert_eq!(response["status"], 400);
+        assert_eq!(response["title"], "Bad Request");
+        assert_eq!(response["code"], "INVALID_PARAMS");
+        assert_eq!(response["field"], "id");
+        assert_eq!(response["instance"], "/api/pg/not-a-uuid");
+        assert_eq!(response["request_id"], request_id.as_str());
+    }
+
+    #[tokio::test]
+    async fn create_order() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let input = get_order_schema(&customer_id, "banana");
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CREATED);
+        assert_eq!(
+            response["data"]["order"]["customer_name"].as_str().unwrap(),
+            "paul doe"
+        );
+        assert_eq!(
+            response["data"]["order"]["product_name"].as_str().unwrap(),
+            "banana"
+        );
+    }
+
+    #[tokio::test]
+    async fn create_order_validation() {
+        let app = init();
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(serde_json::to_vec(&json!({"currency": 5})).unwrap()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["errors"][0]["field"], "customer_id");
+        assert_eq!(response["errors"][0]["code"], "required");
+        assert_eq!(response["errors"][1]["field"], "currency");
+        assert_eq!(response["errors"][1]["code"], "type");
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from("{\"currency\": "),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(response["code"], "INVALID_BODY");
+    }
+
+    #[tokio::test]
+    async fn get_order() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let input = get_order_schema(&customer_id, "banana");
+
+        let (status_code, response_post) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_post);
+        assert_eq!(status_code, StatusCode::CREATED);
 
         let id = response_post["data"]["order"]["id"].as_str().unwrap();
         let uri = format!("/api/mongo/{}", id);
 
-        let (status_code, response_get) = api_call(http::Method::GET, &uri, Body::empty()).await;
+        let (status_code, response_get) =
+            api_call(&app, http::Method::GET, &uri, Body::empty()).await;
         println!("{:?}", response_get);
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(
             response_get["data"]["order"]["customer_name"]
                 .as_str()
                 .unwrap(),
-            input.customer_name.as_str()
+            "paul doe"
         );
         assert_eq!(
             response_get["data"]["order"]["product_name"]
                 .as_str()
                 .unwrap(),
-            input.product_name.as_str()
+            "banana"
         );
     }
 
     #[tokio::test]
     async fn list_orders() {
-        let input = get_order_schema("paul", "banana");
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let input = get_order_schema(&customer_id, "banana");
 
         let (status_code, response_post) = api_call(
+            &app,
             http::Method::POST,
             "/api/mongo",
             Body::from(serde_json::to_vec(&json!(input)).unwrap()),
         )
         .await;
         println!("{:?}", response_post);
-        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(status_code, StatusCode::CREATED);
 
         let (status_code, response_get) =
-            api_call(http::Method::GET, "/api/mongo", Body::empty()).await;
+            api_call(&app, http::Method::GET, "/api/mongo", Body::empty()).await;
         println!("{:?}", response_get);
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(
@@ -384,46 +1424,106 @@ mod tests {
 
     #[tokio::test]
     async fn delete_order() {
-        let input = get_order_schema("paul", "banana");
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let input = get_order_schema(&customer_id, "banana");
 
         let (status_code, response_post) = api_call(
+            &app,
             http::Method::POST,
             "/api/mongo",
             Body::from(serde_json::to_vec(&json!(input)).unwrap()),
         )
         .await;
         println!("{:?}", response_post);
-        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(status_code, StatusCode::CREATED);
 
         let id = response_post["data"]["order"]["id"].as_str().unwrap();
         let uri = format!("/api/mongo/{}", id);
 
         let (status_code, response_delete) =
-            api_call(http::Method::DELETE, &uri, Body::empty()).await;
+            api_call(&app, http::Method::DELETE, &uri, Body::empty()).await;
         println!("{:?}", response_delete);
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(response_delete["status"].as_str().unwrap(), "deleted");
         assert_eq!(response_delete["id"].as_str().unwrap(), id);
+        assert_eq!(
+            response_delete["data"]["order"]["product_name"]
+                .as_str()
+                .unwrap(),
+            "banana"
+        );
+
+        let (status_code, _) = api_call(&app, http::Method::DELETE, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn soft_delete_order() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let input = get_order_schema(&customer_id, "banana");
+
+        let (status_code, response_post) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_post);
+        assert_eq!(status_code, StatusCode::CREATED);
+
+        let id = response_post["data"]["order"]["id"].as_str().unwrap();
+        let uri = format!("/api/mongo/{}", id);
+
+        let (status_code, response_delete) = api_call(
+            &app,
+            http::Method::DELETE,
+            &format!("{}?soft=true", uri),
+            Body::empty(),
+        )
+        .await;
+        println!("{:?}", response_delete);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response_delete["status"].as_str().unwrap(), "deleted");
+        assert_eq!(response_delete["data"]["order"]["id"].as_str().unwrap(), id);
+
+        let (status_code, _) = api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+
+        let (status_code, response_list) =
+            api_call(&app, http::Method::GET, "/api/mongo", Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response_list["results"].as_u64().unwrap(), 0);
     }
 
     #[tokio::test]
     async fn patch_order() {
-        let original_input = get_order_schema("paul", "banana");
-        let modified_input = get_order_schema("mark", "apple");
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let other_customer_id = create_named_customer(&app, "mark", "jones").await;
+        let original_input = get_order_schema(&customer_id, "banana");
+        let modified_input = json!({ "customer_id": other_customer_id });
 
         let (status_code, response_post) = api_call(
+            &app,
             http::Method::POST,
             "/api/mongo",
             Body::from(serde_json::to_vec(&json!(original_input)).unwrap()),
         )
         .await;
         println!("{:?}", response_post);
-        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(status_code, StatusCode::CREATED);
 
         let id = response_post["data"]["order"]["id"].as_str().unwrap();
         let uri = format!("/api/mongo/{}", id);
 
         let (status_code, response_patch) = api_call(
+            &app,
             http::Method::PATCH,
             &uri,
             Body::from(serde_json::to_vec(&json!(modified_input)).unwrap()),
@@ -436,20 +1536,2096 @@ mod tests {
             "success"
         );
 
-        let (status_code, response_get) = api_call(http::Method::GET, &uri, Body::empty()).await;
+        let (status_code, response_get) =
+            api_call(&app, http::Method::GET, &uri, Body::empty()).await;
         println!("{:?}", response_get);
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(
             response_get["data"]["order"]["customer_name"]
                 .as_str()
                 .unwrap(),
-            modified_input.customer_name.as_str()
+            "mark jones"
         );
         assert_eq!(
             response_get["data"]["order"]["product_name"]
                 .as_str()
                 .unwrap(),
-            modified_input.product_name.as_str()
+            "banana"
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_patch_order() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let other_customer_id = create_named_customer(&app, "mark", "jones").await;
+        let input = get_order_schema(&customer_id, "banana");
+
+        let (status_code, response_post) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+
+        let id = response_post["data"]["order"]["id"].as_str().unwrap();
+        let uri = format!("/api/mongo/{}", id);
+
+        let (status_code, response_patch) = api_call(
+            &app,
+            http::Method::PATCH,
+            &uri,
+            Body::from(serde_json::to_vec(&json!({ "customer_id": other_customer_id })).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_patch);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(
+            response_patch["data"]["order"]["customer_name"],
+            "mark jones"
         );
+        assert_eq!(response_patch["data"]["order"]["product_name"], "banana");
+
+        // order fields are required, so they cannot be cleared
+        let (status_code, response_patch) = api_call(
+            &app,
+            http::Method::PATCH,
+            &uri,
+            Body::from(serde_json::to_vec(&json!({"customer_id": null})).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_patch);
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response_patch["errors"][0]["code"], "not_null");
+
+        let (status_code, response_put) = api_call(
+            &app,
+            http::Method::PUT,
+            &uri,
+            Body::from(serde_json::to_vec(&json!(get_order_schema(&customer_id, "pear"))).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_put);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response_put["data"]["order"]["customer_name"], "paul doe");
+        assert_eq!(response_put["data"]["order"]["product_name"], "pear");
+    }
+
+    #[tokio::test]
+    async fn missing_order_not_found() {
+        let app = init();
+        let customer_id = create_test_customer(&app).await;
+        let id = "64b1f0c2a1b2c3d4e5f60718";
+        let uri = format!("/api/mongo/{}", id);
+        let body = get_order_schema(&customer_id, "apple");
+
+        for (method, body) in [
+            (http::Method::GET, Body::empty()),
+            (
+                http::Method::PATCH,
+                Body::from(serde_json::to_vec(&json!(body)).unwrap()),
+            ),
+            (http::Method::DELETE, Body::empty()),
+        ] {
+            let (status_code, response) = api_call(&app, method, &uri, body).await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+            assert_eq!(response["status"], 404);
+            assert_eq!(response["code"], "RESOURCE_NOT_FOUND");
+            assert_eq!(response["resource"], "order");
+            assert_eq!(response["id"], id);
+        }
+    }
+
+    #[tokio::test]
+    async fn customer_etag() {
+        let app = init();
+        let input = get_customer_model("Ada", "Shelby");
+
+        let (status_code, headers, response_create) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/pg",
+            &[],
+            Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        assert_eq!(headers[http::header::ETAG], "\"1\"");
+        assert_eq!(response_create["version"], 1);
+
+        let id = response_create.get("id").unwrap().as_str().unwrap();
+        let uri = format!("/api/pg/{}", id);
+
+        let (status_code, headers, response_get) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            &uri,
+            &[(http::header::IF_NONE_MATCH, "\"1\"")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::NOT_MODIFIED);
+        assert_eq!(headers[http::header::ETAG], "\"1\"");
+        assert!(response_get.is_null());
+
+        let (status_code, headers, response_patch) = api_call_with_headers(
+            &app,
+            http::Method::PATCH,
+            &uri,
+            &[(http::header::IF_MATCH, "\"1\"")],
+            Body::from(serde_json::to_vec(&json!({"customer_name": "Ada Thorne"})).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_patch);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(headers[http::header::ETAG], "\"2\"");
+        assert_eq!(response_patch["version"], 2);
+
+        // the stale version is rejected by every write
+        for (method, body) in [
+            (
+                http::Method::PATCH,
+                Body::from(serde_json::to_vec(&json!({"customer_name": "Ada"})).unwrap()),
+            ),
+            (
+                http::Method::PUT,
+                Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+            ),
+            (http::Method::DELETE, Body::empty()),
+        ] {
+            let (status_code, _, response) = api_call_with_headers(
+                &app,
+                method,
+                &uri,
+                &[(http::header::IF_MATCH, "\"1\"")],
+                body,
+            )
+            .await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::PRECONDITION_FAILED);
+            assert_eq!(response["code"], "PRECONDITION_FAILED");
+            assert_eq!(response["resource"], "customer");
+            assert_eq!(response["id"], id);
+        }
+
+        let (status_code, headers, response_get) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            &uri,
+            &[(http::header::IF_NONE_MATCH, "\"1\"")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(headers[http::header::ETAG], "\"2\"");
+        assert_eq!(response_get["name"], "Ada Thorne");
+
+        let (status_code, _, response_delete) = api_call_with_headers(
+            &app,
+            http::Method::DELETE,
+            &uri,
+            &[(http::header::IF_MATCH, "\"1\", \"2\"")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response_delete["status"], "deleted");
+    }
+
+    #[tokio::test]
+    async fn order_etag() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let input = get_order_schema(&customer_id, "banana");
+
+        let (status_code, headers, response_post) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            &[],
+            Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        assert_eq!(headers[http::header::ETAG], "\"1\"");
+
+        let id = response_post["data"]["order"]["id"].as_str().unwrap();
+        let uri = format!("/api/mongo/{}", id);
+
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            &uri,
+            &[(http::header::IF_NONE_MATCH, "W/\"1\"")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::NOT_MODIFIED);
+
+        let (status_code, headers, response_put) = api_call_with_headers(
+            &app,
+            http::Method::PUT,
+            &uri,
+            &[(http::header::IF_MATCH, "\"1\"")],
+            Body::from(serde_json::to_vec(&json!(get_order_schema(&customer_id, "pear"))).unwrap()),
+        )
+        .await;
+        println!("{:?}", response_put);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(headers[http::header::ETAG], "\"2\"");
+        assert_eq!(response_put["data"]["order"]["version"], 2);
+
+        for (method, body) in [
+            (
+                http::Method::PATCH,
+                Body::from(serde_json::to_vec(&json!({"product_name": "apple"})).unwrap()),
+            ),
+            (
+                http::Method::PUT,
+                Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+            ),
+            (http::Method::DELETE, Body::empty()),
+        ] {
+            let (status_code, _, response) = api_call_with_headers(
+                &app,
+                method,
+                &uri,
+                &[(http::header::IF_MATCH, "\"1\"")],
+                body,
+            )
+            .await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::PRECONDITION_FAILED);
+            assert_eq!(response["code"], "PRECONDITION_FAILED");
+            assert_eq!(response["resource"], "order");
+        }
+
+        // `*` matches any current version
+        let (status_code, _, response_delete) = api_call_with_headers(
+            &app,
+            http::Method::DELETE,
+            &format!("{}?soft=true", uri),
+            &[(http::header::IF_MATCH, "*")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response_delete["data"]["order"]["version"], 3);
+    }
+    #[tokio::test]
+    async fn order_requires_existing_customer() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+
+        for unknown_id in ["6f1c8f1e-1f0b-4f43-9d2a-8d1f2b3c4d5e", "not-a-uuid"] {
+            let (status_code, response) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(unknown_id, "banana"))).unwrap(),
+                ),
+            )
+            .await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(response["errors"][0]["field"], "customer_id");
+            assert_eq!(response["errors"][0]["code"], "unknown_customer");
+        }
+
+        let (status_code, response_post) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(
+                serde_json::to_vec(&json!(get_order_schema(&customer_id, "banana"))).unwrap(),
+            ),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        assert_eq!(response_post["data"]["order"]["customer_id"], customer_id);
+
+        let id = response_post["data"]["order"]["id"].as_str().unwrap();
+        let uri = format!("/api/mongo/{}", id);
+
+        let (status_code, response_patch) = api_call(
+            &app,
+            http::Method::PATCH,
+            &uri,
+            Body::from(
+                serde_json::to_vec(&json!({"customer_id": "6f1c8f1e-1f0b-4f43-9d2a-8d1f2b3c4d5e"}))
+                    .unwrap(),
+            ),
+        )
+        .await;
+        println!("{:?}", response_patch);
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response_patch["errors"][0]["code"], "unknown_customer");
+    }
+
+    /// Creates a customer with one order and deletes the customer, returns the
+    /// status of the delete and of a subsequent GET on the order.
+    async fn delete_customer_with_order(policy: CustomerDeletePolicy) -> (StatusCode, StatusCode) {
+        let app = init_with_policy(policy);
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+
+        let (status_code, response_post) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(
+                serde_json::to_vec(&json!(get_order_schema(&customer_id, "banana"))).unwrap(),
+            ),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        let order_uri = format!(
+            "/api/mongo/{}",
+            response_post["data"]["order"]["id"].as_str().unwrap()
+        );
+
+        let (delete_status, response_delete) = api_call(
+            &app,
+            http::Method::DELETE,
+            &format!("/api/pg/{}", customer_id),
+            Body::empty(),
+        )
+        .await;
+        println!("{:?}", response_delete);
+        if delete_status == StatusCode::CONFLICT {
+            assert_eq!(response_delete["code"], "RESOURCE_IN_USE");
+            assert_eq!(response_delete["dependent"], "order");
+            assert_eq!(response_delete["count"], 1);
+        }
+
+        let (order_status, _) = api_call(&app, http::Method::GET, &order_uri, Body::empty()).await;
+        (delete_status, order_status)
+    }
+
+    #[tokio::test]
+    async fn delete_customer_policies() {
+        assert_eq!(
+            delete_customer_with_order(CustomerDeletePolicy::Reject).await,
+            (StatusCode::CONFLICT, StatusCode::OK)
+        );
+        assert_eq!(
+            delete_customer_with_order(CustomerDeletePolicy::Cascade).await,
+            (StatusCode::OK, StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            delete_customer_with_order(CustomerDeletePolicy::Orphan).await,
+            (StatusCode::OK, StatusCode::OK)
+        );
+    }
+
+    #[tokio::test]
+    async fn cascade_deletes_every_order_when_the_stock_is_not_released() {
+        let app = init_with_stuck_stock(CustomerDeletePolicy::Cascade);
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+
+        let mut order_uris = Vec::new();
+        for product_name in ["apple", "banana"] {
+            let (status_code, response) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(&customer_id, product_name)))
+                        .unwrap(),
+                ),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+            order_uris.push(format!(
+                "/api/mongo/{}",
+                response["data"]["order"]["id"].as_str().unwrap()
+            ));
+        }
+
+        let (status_code, _) = api_call(
+            &app,
+            http::Method::DELETE,
+            &format!("/api/pg/{}", customer_id),
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        for order_uri in &order_uris {
+            let (status_code, _) =
+                api_call(&app, http::Method::GET, order_uri, Body::empty()).await;
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn cascade_keeps_orders_of_a_customer_that_is_not_deleted() {
+        let app = init_with_policy(CustomerDeletePolicy::Cascade);
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+
+        let (status_code, response_post) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(
+                serde_json::to_vec(&json!(get_order_schema(&customer_id, "banana"))).unwrap(),
+            ),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        let order_uri = format!(
+            "/api/mongo/{}",
+            response_post["data"]["order"]["id"].as_str().unwrap()
+        );
+
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::DELETE,
+            &format!("/api/pg/{}", customer_id),
+            &[(http::header::IF_MATCH, "\"2\"")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::PRECONDITION_FAILED);
+
+        let (status_code, _) = api_call(
+            &app,
+            http::Method::DELETE,
+            &format!("/api/pg/{}", uuid::Uuid::new_v4()),
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+
+        let (status_code, _) = api_call(&app, http::Method::GET, &order_uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+    #[tokio::test]
+    async fn list_customer_orders() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let other_customer_id = create_test_customer(&app).await;
+
+        for (owner, product_name) in [
+            (&customer_id, "banana"),
+            (&customer_id, "apple"),
+            (&other_customer_id, "pear"),
+            (&customer_id, "cherry"),
+        ] {
+            let (status_code, _) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(owner, product_name))).unwrap(),
+                ),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+
+        let products = |response: &serde_json::Value| -> Vec<String> {
+            response["orders"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|order| order["product_name"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        for (query, expected) in [
+            ("", vec!["banana", "apple", "cherry"]),
+            ("?sort=product_name", vec!["apple", "banana", "cherry"]),
+            ("?sort=-product_name", vec!["cherry", "banana", "apple"]),
+            ("?sort=-id&limit=2&page=2", vec!["banana"]),
+        ] {
+            let uri = format!("/api/pg/{}/orders{}", customer_id, query);
+            let (status_code, response) =
+                api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(products(&response), expected);
+        }
+
+        let uri = format!("/api/pg/{}/orders?sort=price", customer_id);
+        let (status_code, response) = api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(response["code"], "INVALID_PARAMS");
+        assert_eq!(response["field"], "sort");
+
+        let uri = "/api/pg/6f1c8f1e-1f0b-4f43-9d2a-8d1f2b3c4d5e/orders";
+        let (status_code, response) = api_call(&app, http::Method::GET, uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+        assert_eq!(response["code"], "RESOURCE_NOT_FOUND");
+        assert_eq!(response["resource"], "customer");
+
+        let customer_id = create_named_customer(&app, "gone", "away").await;
+        let uri = format!("/api/pg/{}", customer_id);
+        let (status_code, _) = api_call(&app, http::Method::DELETE, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        let uri = format!("/api/pg/{}/orders", customer_id);
+        let (status_code, response) = api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+        assert_eq!(response["resource"], "customer");
+    }
+
+    #[tokio::test]
+    async fn expand_customer_orders() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let uri = format!("/api/pg/{}", customer_id);
+
+        let (status_code, _) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(
+                serde_json::to_vec(&json!(get_order_schema(&customer_id, "banana"))).unwrap(),
+            ),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+
+        let (status_code, response) = api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(response.get("orders").is_none());
+
+        let (status_code, headers, response) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            &format!("{}?expand=orders", uri),
+            &[(http::header::IF_NONE_MATCH, "\"1\"")],
+            Body::empty(),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(headers.get(http::header::ETAG).is_none());
+        assert_eq!(response["orders"].as_array().unwrap().len(), 1);
+        assert_eq!(response["orders"][0]["product_name"], "banana");
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::GET,
+            &format!("{}?expand=invoices", uri),
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(response["field"], "expand");
+    }
+    #[tokio::test]
+    async fn pagination() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        create_test_customer(&app).await;
+        create_test_customer(&app).await;
+
+        for product_name in ["banana", "apple", "cherry"] {
+            let (status_code, _) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(&customer_id, product_name)))
+                        .unwrap(),
+                ),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+
+        for (uri, items) in [
+            ("/api/pg?limit=2&page=2", "data"),
+            ("/api/mongo?limit=2&page=2", "orders"),
+            (
+                &format!("/api/pg/{}/orders?limit=2&page=2", customer_id),
+                "orders",
+            ),
+        ] {
+            let (status_code, response) =
+                api_call(&app, http::Method::GET, uri, Body::empty()).await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(response[items].as_array().unwrap().len(), 1);
+            assert_eq!(response["page"], 2);
+            assert_eq!(response["limit"], 2);
+            assert_eq!(response["total_count"], 3);
+            assert_eq!(response["total_pages"], 2);
+        }
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::GET,
+            "/api/mongo?page=3&limit=2",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["results"], 0);
+        assert_eq!(response["total_count"], 3);
+
+        for (query, field) in [
+            ("page=0", "page"),
+            ("limit=0", "limit"),
+            ("limit=101", "limit"),
+            ("page=abc", "query"),
+        ] {
+            for uri in [
+                format!("/api/pg?{}", query),
+                format!("/api/mongo?{}", query),
+            ] {
+                let (status_code, response) =
+                    api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+                println!("{:?}", response);
+                assert_eq!(status_code, StatusCode::BAD_REQUEST);
+                assert_eq!(response["code"], "INVALID_PARAMS");
+                assert_eq!(response["field"], field);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cursor_pagination() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        for _ in 0..4 {
+            create_test_customer(&app).await;
+        }
+        for _ in 0..5 {
+            let (status_code, _) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(&customer_id, "apple"))).unwrap(),
+                ),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+
+        for (uri, items) in [("/api/pg", "data"), ("/api/mongo", "orders")] {
+            let mut seen = Vec::new();
+            let mut cursor = String::new();
+            loop {
+                let (status_code, response) = api_call(
+                    &app,
+                    http::Method::GET,
+                    &format!("{}?limit=2&cursor={}", uri, cursor),
+                    Body::empty(),
+                )
+                .await;
+                assert_eq!(status_code, StatusCode::OK);
+                assert_eq!(response["limit"], 2);
+                assert!(response.get("total_count").is_none());
+                for item in response[items].as_array().unwrap() {
+                    seen.push(item["id"].as_str().unwrap().to_string());
+                }
+                match response["next_cursor"].as_str() {
+                    Some(next) => cursor = next.to_string(),
+                    None => break,
+                }
+            }
+            let mut unique = seen.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(seen.len(), 5);
+            assert_eq!(unique.len(), 5);
+        }
+
+        let (_, response) = api_call(
+            &app,
+            http::Method::GET,
+            "/api/pg?limit=2&cursor=",
+            Body::empty(),
+        )
+        .await;
+        let cursor = response["next_cursor"].as_str().unwrap();
+        for (uri, field) in [
+            (format!("/api/pg?cursor={}x", cursor), "cursor"),
+            (format!("/api/mongo?cursor={}", cursor), "cursor"),
+            ("/api/pg?cursor=garbage".to_string(), "cursor"),
+            ("/api/pg?cursor=&page=2".to_string(), "page"),
+            (format!("/api/pg/{}/orders?cursor=", customer_id), "cursor"),
+        ] {
+            let (status_code, response) =
+                api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::BAD_REQUEST);
+            assert_eq!(response["code"], "INVALID_PARAMS");
+            assert_eq!(response["field"], field);
+        }
+    }
+
+    #[tokio::test]
+    async fn filtering() {
+        let app = init();
+        create_order_products(&app).await;
+        for (name, surname) in [("paul", "smith"), ("paula", "jones"), ("mark", "paulson")] {
+            let (status_code, _) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/pg",
+                Body::from(serde_json::to_vec(&json!(get_customer_model(name, surname))).unwrap()),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+        let customer_id = create_test_customer(&app).await;
+        for product_name in ["apple", "Pineapple", "banana"] {
+            let (status_code, _) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(&customer_id, product_name)))
+                        .unwrap(),
+                ),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+
+        for (uri, items, expected) in [
+            ("/api/pg?customer_name=paula", "data", 1),
+            ("/api/pg?customer_name_prefix=paul", "data", 3),
+            ("/api/pg?customer_name_prefix=PAUL", "data", 0),
+            ("/api/pg?customer_surname_contains=PAUL", "data", 1),
+            (
+                "/api/pg?customer_name_prefix=paul&customer_surname=doe",
+                "data",
+                1,
+            ),
+            ("/api/pg?customer_name=", "data", 4),
+            ("/api/mongo?product_name_contains=APPLE", "orders", 2),
+            ("/api/mongo?product_name_prefix=app", "orders", 1),
+            ("/api/mongo?customer_name=mark", "orders", 0),
+            ("/api/mongo?created_after=2000-01-01T00:00:00Z", "orders", 3),
+            (
+                "/api/mongo?created_before=2000-01-01T00:00:00Z",
+                "orders",
+                0,
+            ),
+            ("/api/mongo?created_after=2999-01-01T00:00:00Z", "orders", 0),
+            (
+                "/api/mongo?cursor=&limit=5&product_name_contains=apple",
+                "orders",
+                2,
+            ),
+        ] {
+            let (status_code, response) =
+                api_call(&app, http::Method::GET, uri, Body::empty()).await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(response[items].as_array().unwrap().len(), expected);
+            if response.get("total_count").is_some() {
+                assert_eq!(response["total_count"], expected);
+            }
+        }
+
+        for (uri, field) in [
+            (
+                format!("/api/pg?customer_name_contains={}", "a".repeat(101)),
+                "customer_name_contains",
+            ),
+            ("/api/mongo?created_after=yesterday".to_string(), "query"),
+        ] {
+            let (status_code, response) =
+                api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+            assert_eq!(status_code, StatusCode::BAD_REQUEST);
+            assert_eq!(response["code"], "INVALID_PARAMS");
+            assert_eq!(response["field"], field);
+        }
+    }
+
+    #[tokio::test]
+    async fn sorting() {
+        let app = init();
+        create_order_products(&app).await;
+        let mut ids = Vec::new();
+        for (name, surname) in [("paul", "smith"), ("anna", "smith"), ("mark", "jones")] {
+            let (status_code, response) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/pg",
+                Body::from(serde_json::to_vec(&json!(get_customer_model(name, surname))).unwrap()),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+            ids.push(response["id"].as_str().unwrap().to_string());
+        }
+        create_test_customer(&app).await;
+        // orders take the name of their customer
+        for (customer_id, product_name) in
+            [(&ids[2], "apple"), (&ids[1], "banana"), (&ids[1], "apple")]
+        {
+            let (status_code, _) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(customer_id, product_name)))
+                        .unwrap(),
+                ),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::GET,
+            "/api/pg?sort=customer_surname,-customer_name",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let names: Vec<&str> = response["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|customer| customer["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["paul", "mark", "paul", "anna"]);
+
+        let (_, response) =
+            api_call(&app, http::Method::GET, "/api/pg?sort=-id", Body::empty()).await;
+        let ids: Vec<&str> = response["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|customer| customer["id"].as_str().unwrap())
+            .collect();
+        let mut descending = ids.clone();
+        descending.sort_by(|a, b| b.cmp(a));
+        assert_eq!(ids, descending);
+
+        for (sort, expected) in [
+            (
+                "customer_name,-product_name",
+                [
+                    ("anna smith", "banana"),
+                    ("anna smith", "apple"),
+                    ("mark jones", "apple"),
+                ],
+            ),
+            (
+                "product_name",
+                [
+                    ("mark jones", "apple"),
+                    ("anna smith", "apple"),
+                    ("anna smith", "banana"),
+                ],
+            ),
+            (
+                "-id",
+                [
+                    ("anna smith", "apple"),
+                    ("anna smith", "banana"),
+                    ("mark jones", "apple"),
+                ],
+            ),
+        ] {
+            let (status_code, response) = api_call(
+                &app,
+                http::Method::GET,
+                &format!("/api/mongo?sort={}", sort),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::OK);
+            let orders: Vec<(&str, &str)> = response["orders"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|order| {
+                    (
+                        order["customer_name"].as_str().unwrap(),
+                        order["product_name"].as_str().unwrap(),
+                    )
+                })
+                .collect();
+            assert_eq!(orders, expected);
+        }
+
+        for uri in [
+            "/api/pg?sort=surname",
+            "/api/pg?sort=id,-id",
+            "/api/mongo?sort=product_name,",
+            "/api/mongo?sort=product_name&cursor=",
+        ] {
+            let (status_code, response) =
+                api_call(&app, http::Method::GET, uri, Body::empty()).await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::BAD_REQUEST);
+            assert_eq!(response["code"], "INVALID_PARAMS");
+            assert_eq!(response["field"], "sort");
+        }
+    }
+
+    #[tokio::test]
+    async fn search() {
+        let app = init();
+        create_order_products(&app).await;
+        for (name, surname) in [("paul", "smith"), ("mark", "paulson"), ("anna", "jones")] {
+            let (status_code, _) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/pg",
+                Body::from(serde_json::to_vec(&json!(get_customer_model(name, surname))).unwrap()),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+        let (_, response) = api_call(
+            &app,
+            http::Method::GET,
+            "/api/pg?customer_name=anna",
+            Body::empty(),
+        )
+        .await;
+        let customer_id = response["data"][0]["id"].as_str().unwrap().to_string();
+        for product_name in ["Paul's pears", "apple"] {
+            let (status_code, _) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(&customer_id, product_name)))
+                        .unwrap(),
+                ),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+
+        let (status_code, response) =
+            api_call(&app, http::Method::GET, "/api/search?q=PAUL", Body::empty()).await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::OK);
+        let hits: Vec<(&str, &Value)> = response["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| (hit["type"].as_str().unwrap(), &hit["data"]))
+            .collect();
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].0, "customer");
+        assert_eq!(hits[0].1["name"], "paul");
+        assert_eq!(hits[1].0, "order");
+        assert_eq!(hits[1].1["product_name"], "Paul's pears");
+        assert_eq!(hits[2].0, "customer");
+        assert_eq!(hits[2].1["name"], "mark");
+        assert!(
+            response["results"][0]["score"].as_f64() > response["results"][2]["score"].as_f64()
+        );
+        assert_eq!(response["total_count"], 3);
+
+        let (_, response) = api_call(
+            &app,
+            http::Method::GET,
+            "/api/search?q=paul&limit=2&page=2",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response["results"].as_array().unwrap().len(), 1);
+        assert_eq!(response["results"][0]["data"]["name"], "mark");
+        assert_eq!(response["total_pages"], 2);
+
+        // the last page before the depth limit is shorter than the limit
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::GET,
+            "/api/search?q=paul&page=34&limit=30",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["results"].as_array().unwrap().len(), 0);
+
+        for (uri, field) in [
+            ("/api/search", "q"),
+            ("/api/search?q=%20", "q"),
+            ("/api/search?q=paul&page=11&limit=100", "page"),
+            ("/api/search?q=paul&cursor=", "cursor"),
+        ] {
+            let (status_code, response) =
+                api_call(&app, http::Method::GET, uri, Body::empty()).await;
+            assert_eq!(status_code, StatusCode::BAD_REQUEST);
+            assert_eq!(response["code"], "INVALID_PARAMS");
+            assert_eq!(response["field"], field);
+        }
+    }
+
+    #[tokio::test]
+    async fn timestamps() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_ids = [
+            create_test_customer(&app).await,
+            create_test_customer(&app).await,
+        ];
+        let mut order_ids = Vec::new();
+        for customer_id in &customer_ids {
+            let (_, response) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(
+                    serde_json::to_vec(&json!(get_order_schema(customer_id, "apple"))).unwrap(),
+                ),
+            )
+            .await;
+            let order = &response["data"]["order"];
+            assert_eq!(order["created_at"], order["updated_at"]);
+            order_ids.push(order["id"].as_str().unwrap().to_string());
+        }
+
+        // Mongo timestamps have millisecond precision
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        let (_, customer) = api_call(
+            &app,
+            http::Method::PATCH,
+            &format!("/api/pg/{}", customer_ids[1]),
+            Body::from(r#"{"customer_surname":"smith"}"#),
+        )
+        .await;
+        assert_ne!(customer["created_at"], customer["updated_at"]);
+        let (_, response) = api_call(
+            &app,
+            http::Method::PATCH,
+            &format!("/api/mongo/{}", order_ids[1]),
+            Body::from(format!(r#"{{"customer_id":"{}"}}"#, customer_ids[0])),
+        )
+        .await;
+        let order = &response["data"]["order"];
+        assert_ne!(order["created_at"], order["updated_at"]);
+
+        for (uri, items, id) in [
+            (
+                format!(
+                    "/api/pg?updated_after={}",
+                    customer["updated_at"].as_str().unwrap()
+                ),
+                "data",
+                &customer_ids[1],
+            ),
+            (
+                format!(
+                    "/api/pg?created_before={}",
+                    customer["created_at"].as_str().unwrap()
+                ),
+                "data",
+                &customer_ids[0],
+            ),
+            (
+                "/api/pg?sort=-updated_at".to_string(),
+                "data",
+                &customer_ids[1],
+            ),
+            (
+                format!(
+                    "/api/mongo?updated_after={}",
+                    order["updated_at"].as_str().unwrap()
+                ),
+                "orders",
+                &order_ids[1],
+            ),
+            (
+                "/api/mongo?sort=-updated_at,id".to_string(),
+                "orders",
+                &order_ids[1],
+            ),
+            (
+                "/api/mongo?sort=created_at".to_string(),
+                "orders",
+                &order_ids[0],
+            ),
+        ] {
+            let (status_code, response) =
+                api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(response[items][0]["id"], id.as_str());
+            if !uri.contains("sort") {
+                assert_eq!(response[items].as_array().unwrap().len(), 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn products() {
+        let app = init();
+        let input = json!({"sku": "APL-1", "product_name": "apple", "price": "0.35", "stock": 10});
+
+        let (status_code, headers, response) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/products",
+            &[],
+            Body::from(input.to_string()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CREATED);
+        assert_eq!(headers[http::header::ETAG], "\"1\"");
+        assert_eq!(response["sku"], "APL-1");
+        assert_eq!(response["price"], "0.35");
+        assert_eq!(response["description"], Value::Null);
+        let uri = format!("/api/products/{}", response["id"].as_str().unwrap());
+
+        // the catalog holds the stock orders reserve, so it is not public
+        let response = app
+            .clone()
+            .oneshot(
+                Request::put(&uri)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(json!({"stock": 1000}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let read_only = sign_token(
+            &EncodingKey::from_secret(TEST_JWT_SECRET),
+            &Header::default(),
+            "test",
+            "products:read",
+            3600,
+        );
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::DELETE,
+            &uri,
+            &[(
+                http::header::AUTHORIZATION,
+                &format!("Bearer {}", read_only),
+            )],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+        assert_eq!(response["missing"], json!(["products:delete"]));
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/products",
+            Body::from(input.to_string()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CONFLICT);
+        assert_eq!(response["resource"], "product");
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::PATCH,
+            &uri,
+            Body::from(json!({"description": "Granny Smith", "stock": 4}).to_string()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["description"], "Granny Smith");
+        assert_eq!(response["stock"], 4);
+        assert_eq!(response["price"], "0.35");
+        assert_eq!(response["version"], 2);
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::PUT,
+            &uri,
+            Body::from(
+                json!({"sku": "APL-2", "product_name": "apple", "price": "0.4", "stock": 3})
+                    .to_string(),
+            ),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["sku"], "APL-2");
+        assert_eq!(response["price"], "0.40");
+        assert_eq!(response["description"], Value::Null);
+
+        let (status_code, response) =
+            api_call(&app, http::Method::GET, "/api/products", Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["total_count"], 1);
+        assert_eq!(response["data"][0]["sku"], "APL-2");
+
+        for (body, field, code) in [
+            (
+                json!({"sku": "APL 3", "product_name": "apple", "price": "1", "stock": 1}),
+                "sku",
+                "charset",
+            ),
+            (
+                json!({"sku": "APL-3", "product_name": "apple", "price": "-1", "stock": 1}),
+                "price",
+                "range",
+            ),
+            (
+                json!({"sku": "APL-3", "product_name": "apple", "price": "0.001", "stock": 1}),
+                "price",
+                "scale",
+            ),
+            (
+                json!({"sku": "APL-3", "product_name": "apple", "price": "1", "stock": -1}),
+                "stock",
+                "range",
+            ),
+        ] {
+            let (status_code, response) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/products",
+                Body::from(body.to_string()),
+            )
+            .await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(response["errors"][0]["field"], field);
+            assert_eq!(response["errors"][0]["code"], code);
+        }
+
+        let (status_code, response) =
+            api_call(&app, http::Method::DELETE, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["status"], "deleted");
+        let (status_code, _) = api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn customer_contact_details() {
+        let app = init();
+        let mut input = json!(get_customer_model("jane", "doe"));
+        input["email"] = json!("Jane.Doe@Example.com");
+        input["phone"] = json!("+30 (210) 123-4567");
+        input["addresses"] = json!([
+            {"kind": "shipping", "line1": "1 Main St", "city": "Athens", "postal_code": "105 57", "country": "GR"},
+            {"kind": "billing", "line1": "2 Side St", "line2": "Floor 3", "city": "Athens", "postal_code": "105 58", "country": "GR"},
+            {"kind": "shipping", "line1": "3 Harbour Rd", "city": "Piraeus", "postal_code": "185 31", "country": "GR", "is_default": true},
+        ]);
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/pg",
+            Body::from(input.to_string()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CREATED);
+        assert_eq!(response["email"], "jane.doe@example.com");
+        assert_eq!(response["phone"], "+30 (210) 123-4567");
+        // the first billing address becomes the default, the shipping one is chosen
+        let defaults: Vec<_> = response["addresses"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|address| address["is_default"].as_bool().unwrap())
+            .collect();
+        assert_eq!(defaults, [false, true, true]);
+        let uri = format!("/api/pg/{}", response["id"].as_str().unwrap());
+
+        let (status_code, response) = api_call(&app, http::Method::GET, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["addresses"][1]["line2"], "Floor 3");
+
+        // emails are unique regardless of case
+        input["email"] = json!("JANE.DOE@example.com");
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/pg",
+            Body::from(input.to_string()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CONFLICT);
+        assert_eq!(response["resource"], "customer");
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::GET,
+            "/api/pg?email=JANE.DOE@EXAMPLE.COM",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["data"].as_array().unwrap().len(), 1);
+        assert_eq!(response["data"][0]["email"], "jane.doe@example.com");
+
+        // a PATCH replaces the addresses as a whole and `null` clears the email
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::PATCH,
+            &uri,
+            Body::from(
+                json!({
+                    "email": null,
+                    "addresses": [{"kind": "billing", "line1": "2 Side St", "city": "Athens", "postal_code": "105 58", "country": "GR"}],
+                })
+                .to_string(),
+            ),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["email"], Value::Null);
+        assert_eq!(response["phone"], "+30 (210) 123-4567");
+        assert_eq!(response["addresses"].as_array().unwrap().len(), 1);
+        assert_eq!(response["addresses"][0]["is_default"], true);
+
+        let address = |country: &str, is_default: bool| json!({"kind": "billing", "line1": "2 Side St", "city": "Athens", "postal_code": "105 58", "country": country, "is_default": is_default});
+        for (field, value, error_field, code) in [
+            ("email", json!("jane.example.com"), "email", "format"),
+            ("email", json!("jane@localhost"), "email", "format"),
+            ("phone", json!("12-34"), "phone", "format"),
+            ("phone", json!("+30 210 CALL-ME"), "phone", "charset"),
+            ("addresses", Value::Null, "addresses", "not_null"),
+            (
+                "addresses",
+                json!([address("gr", false)]),
+                "addresses[0].country",
+                "format",
+            ),
+            (
+                "addresses",
+                json!([address("GR", true), address("CY", true)]),
+                "addresses[1].is_default",
+                "duplicate",
+            ),
+        ] {
+            let mut input = json!(get_customer_model("jane", "doe"));
+            input[field] = value;
+            let (status_code, response) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/pg",
+                Body::from(input.to_string()),
+            )
+            .await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(response["errors"][0]["field"], error_field);
+            assert_eq!(response["errors"][0]["code"], code);
+        }
+    }
+
+    /// Adds the products of the orders built by `get_order_schema`.
+    async fn create_order_products(app: &Router) {
+        for product_name in ORDER_PRODUCTS {
+            let (status_code, response) = api_call(
+                app,
+                http::Method::POST,
+                "/api/products",
+                Body::from(
+                    json!({
+                        "sku": order_sku(product_name),
+                        "product_name": product_name,
+                        "price": "1.00",
+                        "stock": 1_000,
+                    })
+                    .to_string(),
+                ),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED, "{:?}", response);
+        }
+    }
+
+    async fn create_test_product(app: &Router, sku: &str, price: &str, stock: i32) -> String {
+        let (status_code, response) = api_call(
+            app,
+            http::Method::POST,
+            "/api/products",
+            Body::from(
+                json!({"sku": sku, "product_name": sku.to_lowercase(), "price": price, "stock": stock})
+                    .to_string(),
+            ),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CREATED);
+        format!("/api/products/{}", response["id"].as_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn order_line_items() {
+        let app = init();
+        let customer_id = create_test_customer(&app).await;
+        create_test_product(&app, "APPLE", "0.35", 10).await;
+        create_test_product(&app, "BREAD", "2.10", 1).await;
+        create_test_product(&app, "PEAR", "1.5", 5).await;
+        let mut input = json!(get_order_schema(&customer_id, "groceries"));
+        input["items"] = json!([
+            {"sku": "APPLE", "quantity": 3},
+            {"sku": "BREAD", "quantity": 1},
+        ]);
+        input["currency"] = json!("EUR");
+
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(serde_json::to_vec(&input).unwrap()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CREATED);
+        let order = &response["data"]["order"];
+        assert_eq!(order["items"][0]["product_name"], "apple");
+        assert_eq!(order["items"][0]["unit_price"], "0.35");
+        assert_eq!(order["currency"], "EUR");
+        assert_eq!(order["total"], "3.15");
+        assert_eq!(order["status"], "pending");
+
+        // a PUT replaces the items and recomputes the total
+        input["items"] = json!([{"sku": "PEAR", "quantity": 2}]);
+        let (status_code, response) = api_call(
+            &app,
+            http::Method::PUT,
+            &format!("/api/mongo/{}", order["id"].as_str().unwrap()),
+            Body::from(serde_json::to_vec(&input).unwrap()),
+        )
+        .await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["data"]["order"]["total"], "3.00");
+
+        for (items, currency, field, code) in [
+            (json!([]), json!("EUR"), "items", "min_items"),
+            (
+                json!([{"sku": "PEAR", "quantity": 1}]),
+                json!("eur"),
+                "currency",
+                "format",
+            ),
+            (
+                json!([{"sku": "PEAR", "quantity": 1}]),
+                Value::Null,
+                "currency",
+                "required",
+            ),
+            (
+                json!([{"sku": " ", "quantity": 1}]),
+                json!("EUR"),
+                "items[0].sku",
+                "not_blank",
+            ),
+            (
+                json!([{"sku": "PEAR", "quantity": 0}]),
+                json!("EUR"),
+                "items[0].quantity",
+                "range",
+            ),
+            (
+                json!([{"sku": "PEAR", "quantity": 1}, {"sku": "PEAR", "quantity": 1}]),
+                json!("EUR"),
+                "items[1].sku",
+                "duplicate",
+            ),
+            (
+                json!([{"sku": "KIWI", "quantity": 1}]),
+                json!("EUR"),
+                "items[0].sku",
+                "unknown_product",
+            ),
+        ] {
+            input["items"] = items;
+            input["currency"] = currency;
+            let (status_code, response) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(serde_json::to_vec(&input).unwrap()),
+            )
+            .await;
+            println!("{:?}", response);
+            assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(response["errors"][0]["field"], field);
+            assert_eq!(response["errors"][0]["code"], code);
+        }
+    }
+
+    async fn product_stock(app: &Router, uri: &str) -> i64 {
+        let (_, response) = api_call(app, http::Method::GET, uri, Body::empty()).await;
+        response["stock"].as_i64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn stock_reservations() {
+        let app = init();
+        let customer_id = create_test_customer(&app).await;
+        let apple = create_test_product(&app, "APPLE", "0.35", 10).await;
+        let bread = create_test_product(&app, "BREAD", "2.10", 1).await;
+        let mut input = json!(get_order_schema(&customer_id, "groceries"));
+        input["currency"] = json!("EUR");
+        let place = |input: &Value| Body::from(serde_json::to_vec(input).unwrap());
+
+        input["items"] = json!([{"sku": "APPLE", "quantity": 4}]);
+        let (status_code, response) =
+            api_call(&app, http::Method::POST, "/api/mongo", place(&input)).await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        let uri = format!(
+            "/api/mongo/{}",
+            response["data"]["order"]["id"].as_str().unwrap()
+        );
+        assert_eq!(product_stock(&app, &apple).await, 6);
+
+        // nothing is reserved when one of the items is short
+        input["items"] = json!([
+            {"sku": "APPLE", "quantity": 1},
+            {"sku": "BREAD", "quantity": 2},
+        ]);
+        let (status_code, response) =
+            api_call(&app, http::Method::POST, "/api/mongo", place(&input)).await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::CONFLICT);
+        assert_eq!(response["code"], "INSUFFICIENT_STOCK");
+        assert_eq!(response["sku"], "BREAD");
+        assert_eq!(response["requested"], 2);
+        assert_eq!(response["available"], 1);
+        assert_eq!(product_stock(&app, &apple).await, 6);
+
+        // a PUT swaps the reservation of the order, counting what it already holds
+        input["items"] = json!([{"sku": "APPLE", "quantity": 10}]);
+        let (status_code, _) = api_call(&app, http::Method::PUT, &uri, place(&input)).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(product_stock(&app, &apple).await, 0);
+        input["items"] = json!([{"sku": "BREAD", "quantity": 1}]);
+        let (status_code, _) = api_call(&app, http::Method::PUT, &uri, place(&input)).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(product_stock(&app, &apple).await, 10);
+        assert_eq!(product_stock(&app, &bread).await, 0);
+
+        // deleting the order releases its stock
+        let (status_code, _) = api_call(&app, http::Method::DELETE, &uri, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(product_stock(&app, &bread).await, 1);
+
+        // so does cancelling it, while a shipped order keeps its stock
+        input["items"] = json!([{"sku": "APPLE", "quantity": 3}]);
+        for (transitions, expected_stock) in [(&["cancelled"][..], 10), (&["paid", "shipped"], 7)] {
+            let (_, response) =
+                api_call(&app, http::Method::POST, "/api/mongo", place(&input)).await;
+            let uri = format!(
+                "/api/mongo/{}",
+                response["data"]["order"]["id"].as_str().unwrap()
+            );
+            assert_eq!(product_stock(&app, &apple).await, 7);
+            for status in transitions {
+                let (status_code, _) = api_call(
+                    &app,
+                    http::Method::POST,
+                    &format!("{}/transition", uri),
+                    Body::from(json!({ "status": status }).to_string()),
+                )
+                .await;
+                assert_eq!(status_code, StatusCode::OK);
+            }
+            assert_eq!(product_stock(&app, &apple).await, expected_stock);
+
+            // their items are final, a PUT neither changes them nor takes stock
+            let (status_code, response) =
+                api_call(&app, http::Method::PUT, &uri, place(&input)).await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(response["data"]["order"]["items"][0]["quantity"], 3);
+            input["items"] = json!([{"sku": "APPLE", "quantity": 1}]);
+            let (status_code, response) =
+                api_call(&app, http::Method::PUT, &uri, place(&input)).await;
+            assert_eq!(status_code, StatusCode::CONFLICT);
+            assert_eq!(response["code"], "ITEMS_FINAL");
+            assert_eq!(response["status"], transitions[transitions.len() - 1]);
+            input["items"] = json!([{"sku": "APPLE", "quantity": 3}]);
+            assert_eq!(product_stock(&app, &apple).await, expected_stock);
+
+            let (status_code, _) = api_call(&app, http::Method::DELETE, &uri, Body::empty()).await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(product_stock(&app, &apple).await, expected_stock);
+        }
+    }
+
+    #[tokio::test]
+    async fn order_status_transitions() {
+        let app = init();
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let input = get_order_schema(&customer_id, "banana");
+
+        let (_, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+        )
+        .await;
+        let uri = format!(
+            "/api/mongo/{}/transition",
+            response["data"]["order"]["id"].as_str().unwrap()
+        );
+        let transition = |status: &str| Body::from(json!({ "status": status }).to_string());
+
+        for (status, expected_status, expected_version) in [
+            ("paid", StatusCode::OK, "\"2\""),
+            ("pending", StatusCode::CONFLICT, ""),
+            ("delivered", StatusCode::CONFLICT, ""),
+            ("shipped", StatusCode::OK, "\"3\""),
+            ("cancelled", StatusCode::CONFLICT, ""),
+            ("delivered", StatusCode::OK, "\"4\""),
+        ] {
+            let (status_code, headers, response) =
+                api_call_with_headers(&app, http::Method::POST, &uri, &[], transition(status))
+                    .await;
+            println!("{:?}", response);
+            assert_eq!(status_code, expected_status);
+            if status_code == StatusCode::OK {
+                assert_eq!(headers[http::header::ETAG], expected_version);
+                assert_eq!(response["data"]["order"]["status"], status);
+            } else {
+                assert_eq!(response["code"], "INVALID_TRANSITION");
+                assert_eq!(response["to"], status);
+            }
+        }
+
+        // a pending order can be cancelled, unless If-Match is stale
+        let (_, response) = api_call(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+        )
+        .await;
+        let uri = format!(
+            "/api/mongo/{}/transition",
+            response["data"]["order"]["id"].as_str().unwrap()
+        );
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            &uri,
+            &[(http::header::IF_MATCH, "\"2\"")],
+            transition("cancelled"),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::PRECONDITION_FAILED);
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            &uri,
+            &[(http::header::IF_MATCH, "\"1\"")],
+            transition("cancelled"),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["data"]["order"]["status"], "cancelled");
+
+        let (status_code, response) =
+            api_call(&app, http::Method::POST, &uri, transition("refunded")).await;
+        println!("{:?}", response);
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["code"], "INVALID_BODY");
+    }
+
+    #[tokio::test]
+    async fn order_writes_stand_when_the_stock_is_not_released() {
+        let app = init_with_stuck_stock(CustomerDeletePolicy::default());
+        create_order_products(&app).await;
+        let customer_id = create_test_customer(&app).await;
+        let input = get_order_schema(&customer_id, "banana");
+
+        let mut order_uris = Vec::new();
+        for _ in 0..3 {
+            let (status_code, response) = api_call(
+                &app,
+                http::Method::POST,
+                "/api/mongo",
+                Body::from(serde_json::to_vec(&json!(input)).unwrap()),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::CREATED);
+            order_uris.push(format!(
+                "/api/mongo/{}",
+                response["data"]["order"]["id"].as_str().unwrap()
+            ));
+        }
+
+        // the order is moved even though its stock stays reserved
+        for (uri, transitions) in [
+            (&order_uris[0], &["cancelled"][..]),
+            (&order_uris[1], &["paid", "shipped"]),
+        ] {
+            for status in transitions {
+                let (status_code, response) = api_call(
+                    &app,
+                    http::Method::POST,
+                    &format!("{}/transition", uri),
+                    Body::from(json!({ "status": status }).to_string()),
+                )
+                .await;
+                println!("{:?}", response);
+                assert_eq!(status_code, StatusCode::OK);
+                assert_eq!(response["data"]["order"]["status"], *status);
+            }
+            let (_, response) = api_call(&app, http::Method::GET, uri, Body::empty()).await;
+            assert_eq!(
+                response["data"]["order"]["status"],
+                transitions[transitions.len() - 1]
+            );
+        }
+
+        // and deleted for good
+        let (status_code, _) =
+            api_call(&app, http::Method::DELETE, &order_uris[2], Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        let (status_code, _) =
+            api_call(&app, http::Method::GET, &order_uris[2], Body::empty()).await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn tenant_isolation() {
+        let app = init();
+        create_order_products(&app).await;
+        let tenant_header = http::HeaderName::from_static(tenant::TENANT_HEADER);
+        let operator = operator_token();
+        let operator_auth = (http::header::AUTHORIZATION, operator.as_str());
+        let acme = [operator_auth.clone(), (tenant_header.clone(), "acme")];
+        let globex = [operator_auth.clone(), (tenant_header.clone(), "globex")];
+
+        let mut customer = get_customer_model("paul", "doe");
+        customer.email = Some("paul@example.com".to_string());
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/pg",
+            &acme,
+            Body::from(serde_json::to_vec(&json!(customer)).unwrap()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        let customer_id = response["id"].as_str().unwrap().to_string();
+        let customer_uri = format!("/api/pg/{}", customer_id);
+
+        // emails are only unique within a tenant
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/pg",
+            &globex,
+            Body::from(serde_json::to_vec(&json!(customer)).unwrap()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            &acme,
+            Body::from(
+                serde_json::to_vec(&json!(get_order_schema(&customer_id, "banana"))).unwrap(),
+            ),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        let order_uri = format!(
+            "/api/mongo/{}",
+            response["data"]["order"]["id"].as_str().unwrap()
+        );
+
+        // another tenant can neither read nor write them
+        for (method, uri, body) in [
+            (Method::GET, customer_uri.clone(), json!(null)),
+            (Method::GET, format!("{}/orders", customer_uri), json!(null)),
+            (
+                Method::PATCH,
+                customer_uri.clone(),
+                json!({"customer_name": "x"}),
+            ),
+            (Method::PUT, customer_uri.clone(), json!(customer)),
+            (Method::DELETE, customer_uri.clone(), json!(null)),
+            (Method::GET, order_uri.clone(), json!(null)),
+            (
+                Method::PATCH,
+                order_uri.clone(),
+                json!({"product_name": "x"}),
+            ),
+            (
+                Method::POST,
+                format!("{}/transition", order_uri),
+                json!({"status": "paid"}),
+            ),
+            (Method::DELETE, order_uri.clone(), json!(null)),
+        ] {
+            let (status_code, _, response) = api_call_with_headers(
+                &app,
+                method.clone(),
+                &uri,
+                &globex,
+                Body::from(body.to_string()),
+            )
+            .await;
+            println!("{} {} {:?}", method, uri, response);
+            assert_eq!(status_code, StatusCode::NOT_FOUND);
+        }
+
+        // nor place orders for a customer of another tenant
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/mongo",
+            &globex,
+            Body::from(
+                serde_json::to_vec(&json!(get_order_schema(&customer_id, "banana"))).unwrap(),
+            ),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["errors"][0]["code"], "unknown_customer");
+
+        // lists and search only show the data of the tenant
+        for (uri, path, expected) in [
+            ("/api/pg", "/data", 1),
+            ("/api/mongo", "/orders", 0),
+            ("/api/search?q=paul", "/results", 1),
+        ] {
+            let (status_code, _, response) =
+                api_call_with_headers(&app, http::Method::GET, uri, &globex, Body::empty()).await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert_eq!(
+                response.pointer(path).unwrap().as_array().unwrap().len(),
+                expected
+            );
+        }
+        let (_, _, response) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            "/api/search?q=paul",
+            &acme,
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response["results"].as_array().unwrap().len(), 2);
+
+        // requests naming no tenant act on the default one
+        let (_, response) = api_call(&app, http::Method::GET, "/api/pg", Body::empty()).await;
+        assert_eq!(response["data"].as_array().unwrap().len(), 0);
+
+        // and callers without `tenants:*` cannot name another one
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            &customer_uri,
+            &[(tenant_header.clone(), "acme")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+        assert_eq!(response["code"], "WRONG_TENANT");
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            "/api/pg",
+            &[(tenant_header.clone(), "default")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        // the data is untouched in its own tenant
+        let (status_code, _, response) =
+            api_call_with_headers(&app, http::Method::GET, &order_uri, &acme, Body::empty()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["data"]["order"]["version"], 1);
+        let (status_code, _, response) =
+            api_call_with_headers(&app, http::Method::GET, &customer_uri, &acme, Body::empty())
+                .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(response["name"], "paul");
+
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            "/api/pg",
+            &[(tenant_header, "Not A Tenant")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(response["field"], "tenant");
+    }
+
+    #[tokio::test]
+    async fn tenant_bound_credentials() {
+        let store = MemoryStore::default();
+        let app = create_router(
+            store.clone(),
+            store.clone(),
+            store.clone(),
+            store,
+            CustomerDeletePolicy::default(),
+            CursorSigner::new(b"test"),
+            Tokens::hs256(TEST_JWT_SECRET),
+            TenantResolver::with_domain("api.example.com"),
+        );
+        let tenant_header = http::HeaderName::from_static(tenant::TENANT_HEADER);
+        let claims = auth::Claims {
+            sub: "acme-ops".to_string(),
+            exp: (chrono::Utc::now().timestamp() + 3600) as u64,
+            scope: format!("admin {}", ALL_SCOPES),
+            tenant: Some("acme".to_string()),
+        };
+        let acme_token = format!(
+            "Bearer {}",
+            encode(
+                &Header::default(),
+                &claims,
+                &EncodingKey::from_secret(TEST_JWT_SECRET)
+            )
+            .unwrap()
+        );
+        let acme_auth = (http::header::AUTHORIZATION, acme_token.as_str());
+
+        // the subdomain names the tenant of operators
+        let operator = operator_token();
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/pg",
+            &[
+                (http::header::AUTHORIZATION, &operator),
+                (http::header::HOST, "acme.api.example.com:8000"),
+            ],
+            Body::from(serde_json::to_vec(&json!(get_customer_model("paul", "doe"))).unwrap()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        let customer_uri = format!("/api/pg/{}", response["id"].as_str().unwrap());
+
+        // a token bound to a tenant acts on it without naming it
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            &customer_uri,
+            &[(http::header::AUTHORIZATION, &acme_token)],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        // and cannot switch to another one
+        for headers in [
+            [acme_auth.clone(), (tenant_header.clone(), "globex")],
+            [
+                acme_auth.clone(),
+                (http::header::HOST, "globex.api.example.com"),
+            ],
+        ] {
+            let (status_code, _, response) = api_call_with_headers(
+                &app,
+                http::Method::GET,
+                &customer_uri,
+                &headers,
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::FORBIDDEN);
+            assert_eq!(response["code"], "WRONG_TENANT");
+            assert_eq!(response["tenant"], "globex");
+
+            // the product catalog is shared, no tenant is checked on it
+            let (status_code, _, _) = api_call_with_headers(
+                &app,
+                http::Method::GET,
+                "/api/products",
+                &headers,
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::OK);
+        }
+
+        // API keys are bound to the tenant they are minted in
+        let (status_code, _, response) = api_call_with_headers(
+            &app,
+            http::Method::POST,
+            "/api/admin/api-keys",
+            &[(http::header::AUTHORIZATION, &acme_token)],
+            Body::from(json!({"name": "sync", "scopes": ["customers:read"]}).to_string()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::CREATED);
+        assert_eq!(response["tenant"], "acme");
+        let key = response["key"].as_str().unwrap().to_string();
+        let key_id = response["id"].as_str().unwrap().to_string();
+        let api_key_header = http::HeaderName::from_static(auth::API_KEY_HEADER);
+
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            &customer_uri,
+            &[(api_key_header.clone(), &key)],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            &customer_uri,
+            &[(api_key_header, &key), (tenant_header.clone(), "globex")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+
+        // the keys of a tenant are only listed and revoked in it
+        let admin_auth = (http::header::AUTHORIZATION, operator.as_str());
+        let (_, _, response) = api_call_with_headers(
+            &app,
+            http::Method::GET,
+            "/api/admin/api-keys",
+            &[admin_auth.clone(), (tenant_header.clone(), "globex")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response["data"].as_array().unwrap().len(), 0);
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::DELETE,
+            &format!("/api/admin/api-keys/{}", key_id),
+            &[admin_auth.clone(), (tenant_header.clone(), "globex")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+        let (status_code, _, _) = api_call_with_headers(
+            &app,
+            http::Method::DELETE,
+            &format!("/api/admin/api-keys/{}", key_id),
+            &[admin_auth, (tenant_header, "acme")],
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
     }
 }
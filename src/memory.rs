@@ -0,0 +1,1292 @@
+use crate::model::{
+    AddressModel, ApiKeyModel, CustomerModel, LineItem, OrderModel, OrderStatus, ProductModel,
+    RefreshTokenModel, UserModel, PRICE_SCALE,
+};
+use crate::response::{
+    CustomerListResponse, CustomerResponse, DeleteOrderResponse, OrderData, OrderListResponse,
+    OrderResponse, Pagination, ProductListResponse, ProductResponse, SingleCustomerResponse,
+    SingleOrderResponse, SingleProductResponse,
+};
+use crate::schema::{
+    AddressSchema, CreateCustomerSchema, CreateOrderSchema, CreateProductSchema, CustomerCursor,
+    CustomerFilter, OrderCursor, OrderFilter, OrderItemSchema, PageRequest, Sort,
+    UpdateCustomerSchema, UpdateOrderSchema, UpdateProductSchema,
+};
+use crate::store::{
+    ensure_customer_exists, same_items, unknown_product, CustomerStore, OrderStore, ProductStore,
+    UserStore,
+};
+use crate::tenant::Tenant;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use mongodb::bson::{self, oid::ObjectId};
+use sqlx::types::Uuid;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::ops::Bound;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+/// Thread-safe in-memory backend implementing every store trait. Used by the
+/// test suite so it can run without Postgres or MongoDB.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    customers: Arc<RwLock<HashMap<Uuid, CustomerModel>>>,
+    /// Addresses of each customer in `position` order, keyed by customer id.
+    addresses: Arc<RwLock<HashMap<Uuid, Vec<AddressModel>>>>,
+    // ObjectIds grow monotonically, so the map iterates in insertion order
+    // like a Mongo collection without an explicit sort.
+    orders: Arc<RwLock<BTreeMap<ObjectId, OrderModel>>>,
+    products: Arc<RwLock<HashMap<Uuid, ProductModel>>>,
+    reservations: Arc<RwLock<Reservations>>,
+    users: Arc<RwLock<HashMap<Uuid, UserModel>>>,
+    /// Refresh tokens keyed by their hash.
+    refresh_tokens: Arc<RwLock<HashMap<String, RefreshTokenModel>>>,
+    api_keys: Arc<RwLock<HashMap<Uuid, ApiKeyModel>>>,
+}
+
+/// Products and quantities held by each order, keyed by order id.
+type Reservations = HashMap<String, Vec<(Uuid, u32)>>;
+
+impl MemoryStore {
+    fn parse_uuid(id: &str) -> Result<Uuid> {
+        Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })
+    }
+
+    fn parse_order_id(id: &str) -> Result<ObjectId> {
+        ObjectId::from_str(id).map_err(|e| Error::MongoInvalidIDError { e: (e.to_string()) })
+    }
+
+    /// Fails with `PreconditionFailed` unless `version` is one of the `If-Match` versions.
+    fn check_version(
+        version: i32,
+        if_match: Option<&[i32]>,
+        resource: &str,
+        id: &str,
+    ) -> Result<()> {
+        match if_match {
+            Some(versions) if !versions.contains(&version) => {
+                Err(Error::precondition_failed(resource, id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with `NotFound` unless the customer exists in `tenant`, which like
+    /// the `WHERE` of an update in Postgres goes before a conflicting email.
+    fn check_tenant(
+        customers: &HashMap<Uuid, CustomerModel>,
+        tenant: &Tenant,
+        customer_id: Uuid,
+        id: &str,
+    ) -> Result<()> {
+        match customers.get(&customer_id) {
+            Some(customer) if customer.tenant_id == tenant.as_str() => Ok(()),
+            _ => Err(Error::not_found("customer", id)),
+        }
+    }
+
+    /// The email is unique within a tenant like in the `customer` table, `id`
+    /// is the customer being written.
+    fn check_email(
+        customers: &HashMap<Uuid, CustomerModel>,
+        tenant: &Tenant,
+        email: Option<&str>,
+        id: Option<Uuid>,
+    ) -> Result<()> {
+        let email = match email {
+            Some(email) => email,
+            None => return Ok(()),
+        };
+        if customers.values().any(|customer| {
+            customer.tenant_id == tenant.as_str()
+                && customer.email.as_deref() == Some(email)
+                && Some(customer.customer_id) != id
+        }) {
+            return Err(Error::Conflict {
+                resource: "customer".to_string(),
+                key: format!("{{ email: \"{}\" }}", email),
+            });
+        }
+        Ok(())
+    }
+
+    fn address_models(customer_id: Uuid, addresses: &[AddressSchema]) -> Vec<AddressModel> {
+        AddressSchema::with_defaults(addresses)
+            .into_iter()
+            .zip(0..)
+            .map(|(address, position)| AddressModel {
+                address_id: Uuid::new_v4(),
+                customer_id,
+                position,
+                kind: address.kind.as_ref().to_string(),
+                line1: address.line1,
+                line2: address.line2,
+                city: address.city,
+                postal_code: address.postal_code,
+                country: address.country,
+                is_default: address.is_default,
+            })
+            .collect()
+    }
+
+    /// The SKU is unique like in the `product` table, `id` is the product being written.
+    fn check_sku(
+        products: &HashMap<Uuid, ProductModel>,
+        sku: &str,
+        id: Option<Uuid>,
+    ) -> Result<()> {
+        if products
+            .values()
+            .any(|product| product.sku == sku && Some(product.product_id) != id)
+        {
+            return Err(Error::Conflict {
+                resource: "product".to_string(),
+                key: format!("{{ sku: \"{}\" }}", sku),
+            });
+        }
+        Ok(())
+    }
+
+    /// Puts `reserved` back in stock, products deleted since are skipped.
+    fn restock(products: &mut HashMap<Uuid, ProductModel>, reserved: &[(Uuid, u32)]) {
+        for (product_id, quantity) in reserved {
+            if let Some(product) = products.get_mut(product_id) {
+                product.stock += *quantity as i32;
+                product.version += 1;
+                product.updated_at = Utc::now();
+            }
+        }
+    }
+
+    /// `ProductStore::reserve_stock` without awaiting, so that `edit_order`
+    /// can call it while holding the orders lock. Every item is checked before
+    /// the stock is touched.
+    fn reserve(&self, order_id: &str, items: &[OrderItemSchema]) -> Result<Vec<LineItem>> {
+        let mut products = self.products.write().map_err(|_| Error::HandlerError)?;
+        let mut reservations = self.reservations.write().map_err(|_| Error::HandlerError)?;
+        let previous = reservations.remove(order_id).unwrap_or_default();
+
+        let mut reserved = Vec::new();
+        for (index, item) in items.iter().enumerate() {
+            let product = match products.values().find(|product| product.sku == item.sku) {
+                Some(product) => product,
+                None => {
+                    reservations.insert(order_id.to_string(), previous);
+                    return Err(unknown_product(index, &item.sku));
+                }
+            };
+            // what the order held before is available to it again
+            let held: u32 = previous
+                .iter()
+                .filter(|(product_id, _)| *product_id == product.product_id)
+                .map(|(_, quantity)| quantity)
+                .sum();
+            let available = product.stock + held as i32;
+            if available < item.quantity as i32 {
+                let error = Error::InsufficientStock {
+                    sku: item.sku.to_owned(),
+                    requested: item.quantity,
+                    available,
+                };
+                reservations.insert(order_id.to_string(), previous);
+                return Err(error);
+            }
+            reserved.push((product.product_id, item.quantity));
+        }
+
+        Self::restock(&mut products, &previous);
+        let mut line_items = Vec::new();
+        for (product_id, quantity) in &reserved {
+            // unwrap() is allowed as the product was looked up above while holding the lock
+            let product = products.get_mut(product_id).unwrap();
+            product.stock -= *quantity as i32;
+            product.version += 1;
+            product.updated_at = Utc::now();
+
+            let product = Self::product_to_response(product);
+            line_items.push(LineItem {
+                sku: product.sku,
+                product_name: product.name,
+                quantity: *quantity,
+                unit_price: product.price,
+            });
+        }
+        if !reserved.is_empty() {
+            reservations.insert(order_id.to_string(), reserved);
+        }
+
+        Ok(line_items)
+    }
+
+    fn offset(page: PageRequest) -> Result<usize> {
+        usize::try_from(page.offset()).map_err(|e| Error::MongoParsingError { e: (e.to_string()) })
+    }
+
+    /// One page of `orders`, which are already filtered and sorted.
+    fn order_page(orders: Vec<&OrderModel>, page: PageRequest) -> Result<OrderListResponse> {
+        let total_count = orders.len() as u64;
+        let json_result: Vec<OrderResponse> = orders
+            .into_iter()
+            .skip(Self::offset(page)?)
+            .take(page.limit as usize)
+            .map(Self::doc_to_order)
+            .collect();
+
+        Ok(OrderListResponse {
+            status: "success".to_string(),
+            results: json_result.len(),
+            orders: json_result,
+            pagination: Pagination::new(page, total_count),
+        })
+    }
+
+    /// Customers are sorted by name then id, with missing names last like in Postgres.
+    fn customer_sort_key(customer: &CustomerModel) -> (bool, Option<&String>, Uuid) {
+        (
+            customer.customer_name.is_none(),
+            customer.customer_name.as_ref(),
+            customer.customer_id,
+        )
+    }
+
+    /// Orders by `sort` then by id, which is the creation order.
+    fn compare_orders(a: &OrderModel, b: &OrderModel, sort: &Sort) -> Ordering {
+        sort.keys
+            .iter()
+            .map(|key| {
+                let ordering = match key.field {
+                    "customer_name" => a.customer_name.cmp(&b.customer_name),
+                    "product_name" => a.product_name.cmp(&b.product_name),
+                    "created_at" => a.created_at().cmp(&b.created_at()),
+                    "updated_at" => a.updated_at().cmp(&b.updated_at()),
+                    _ => a.id.cmp(&b.id),
+                };
+                if key.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .fold(Ordering::Equal, Ordering::then)
+            .then_with(|| a.id.cmp(&b.id))
+    }
+
+    /// Customers by `sort` then by id. Missing values sort as the largest,
+    /// like NULLs in Postgres.
+    fn compare_customers(a: &CustomerModel, b: &CustomerModel, sort: &Sort) -> Ordering {
+        let nulls_last = |a: &Option<String>, b: &Option<String>| match (a, b) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        };
+
+        sort.keys
+            .iter()
+            .map(|key| {
+                let ordering = match key.field {
+                    "customer_name" => nulls_last(&a.customer_name, &b.customer_name),
+                    "customer_surname" => nulls_last(&a.customer_surname, &b.customer_surname),
+                    "created_at" => a.created_at.cmp(&b.created_at),
+                    "updated_at" => a.updated_at.cmp(&b.updated_at),
+                    _ => a.customer_id.cmp(&b.customer_id),
+                };
+                if key.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .fold(Ordering::Equal, Ordering::then)
+            .then_with(|| a.customer_id.cmp(&b.customer_id))
+    }
+
+    /// Stands in for the search indexes: `document` matches when it contains
+    /// `query` ignoring case, and the fewer other characters it has the better.
+    fn search_rank(document: &str, query: &str) -> Option<usize> {
+        let document = document.trim().to_lowercase();
+        let query = query.to_lowercase();
+
+        document
+            .contains(&query)
+            .then(|| document.chars().count() - query.chars().count())
+    }
+
+    fn customer_matches(customer: &CustomerModel, filter: &CustomerFilter) -> bool {
+        filter.time.iter().all(|range| {
+            range.contains(match range.field {
+                "created_at" => customer.created_at,
+                _ => customer.updated_at,
+            })
+        }) && filter.text.iter().all(|condition| {
+            condition.matches(match condition.field {
+                "email" => customer.email.as_deref(),
+                "customer_name" => customer.customer_name.as_deref(),
+                "customer_surname" => customer.customer_surname.as_deref(),
+                _ => None,
+            })
+        })
+    }
+
+    fn order_matches(order: &OrderModel, filter: &OrderFilter) -> bool {
+        filter.created_after.map_or(true, |after| order.id >= after)
+            && filter
+                .created_before
+                .map_or(true, |before| order.id < before)
+            && filter.time.iter().all(|range| {
+                range.contains(match range.field {
+                    "created_at" => order.created_at(),
+                    _ => order.updated_at(),
+                })
+            })
+            && filter.text.iter().all(|condition| {
+                condition.matches(match condition.field {
+                    "customer_name" => Some(order.customer_name.as_str()),
+                    "product_name" => Some(order.product_name.as_str()),
+                    _ => None,
+                })
+            })
+    }
+
+    fn customer_to_response(customer: &CustomerModel) -> CustomerResponse {
+        CustomerResponse {
+            id: customer.customer_id.to_string(),
+            name: customer.customer_name.to_owned(),
+            surname: customer.customer_surname.to_owned(),
+            email: customer.email.to_owned(),
+            phone: customer.phone.to_owned(),
+            version: customer.version,
+            created_at: customer.created_at,
+            updated_at: customer.updated_at,
+        }
+    }
+
+    fn customer_to_single(
+        customer: &CustomerModel,
+        addresses: &[AddressModel],
+        status: &str,
+    ) -> SingleCustomerResponse {
+        SingleCustomerResponse {
+            id: customer.customer_id.to_string(),
+            name: customer.customer_name.to_owned(),
+            surname: customer.customer_surname.to_owned(),
+            email: customer.email.to_owned(),
+            phone: customer.phone.to_owned(),
+            addresses: addresses.iter().cloned().map(Into::into).collect(),
+            status: status.to_string(),
+            version: customer.version,
+            created_at: customer.created_at,
+            updated_at: customer.updated_at,
+            orders: None,
+        }
+    }
+
+    fn product_to_response(product: &ProductModel) -> ProductResponse {
+        // prices have as many decimal places as the NUMERIC(12, 2) column
+        let mut price = product.price;
+        price.rescale(PRICE_SCALE);
+
+        ProductResponse {
+            id: product.product_id.to_string(),
+            sku: product.sku.to_owned(),
+            name: product.product_name.to_owned(),
+            description: product.description.to_owned(),
+            price,
+            stock: product.stock,
+            version: product.version,
+            created_at: product.created_at,
+            updated_at: product.updated_at,
+        }
+    }
+
+    fn product_to_single(product: &ProductModel, status: &str) -> SingleProductResponse {
+        SingleProductResponse {
+            status: status.to_string(),
+            product: Self::product_to_response(product),
+        }
+    }
+
+    fn order_to_single(order: &OrderModel) -> SingleOrderResponse {
+        SingleOrderResponse {
+            status: "success".to_string(),
+            data: OrderData {
+                order: Self::doc_to_order(order),
+            },
+        }
+    }
+
+    fn doc_to_order(order: &OrderModel) -> OrderResponse {
+        OrderResponse {
+            id: order.id.to_hex(),
+            customer_id: order.customer_id.to_owned(),
+            customer_name: order.customer_name.to_owned(),
+            product_name: order.product_name.to_owned(),
+            items: order.items.to_owned(),
+            currency: order.currency.to_owned(),
+            total: order.total,
+            status: order.status,
+            version: order.version,
+            created_at: order.created_at(),
+            updated_at: order.updated_at(),
+        }
+    }
+}
+
+#[async_trait]
+impl CustomerStore for MemoryStore {
+    async fn create_customer(
+        &self,
+        tenant: &Tenant,
+        body: &CreateCustomerSchema,
+    ) -> Result<SingleCustomerResponse> {
+        let now = Utc::now();
+        let customer = CustomerModel {
+            customer_id: Uuid::new_v4(),
+            tenant_id: tenant.as_str().to_string(),
+            customer_name: Some(body.customer_name.to_owned()),
+            customer_surname: Some(body.customer_surname.to_owned()),
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            email: body.normalized_email(),
+            phone: body.phone.to_owned(),
+        };
+        let addresses = Self::address_models(customer.customer_id, &body.addresses);
+
+        let mut customers = self.customers.write().map_err(|_| Error::CustomerError)?;
+        Self::check_email(&customers, tenant, customer.email.as_deref(), None)?;
+        customers.insert(customer.customer_id, customer.clone());
+        let mut stored = self.addresses.write().map_err(|_| Error::CustomerError)?;
+        stored.insert(customer.customer_id, addresses.clone());
+
+        Ok(Self::customer_to_single(&customer, &addresses, "success"))
+    }
+
+    async fn list_customers(
+        &self,
+        tenant: &Tenant,
+        filter: &CustomerFilter,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<CustomerListResponse> {
+        let customers = self.customers.read().map_err(|_| Error::CustomerError)?;
+
+        let mut sorted: Vec<&CustomerModel> = customers
+            .values()
+            .filter(|customer| customer.tenant_id == tenant.as_str())
+            .filter(|customer| Self::customer_matches(customer, filter))
+            .collect();
+        sorted.sort_by(|a, b| Self::compare_customers(a, b, sort));
+        let total_count = sorted.len() as u64;
+
+        let json_result: Vec<CustomerResponse> = sorted
+            .into_iter()
+            .skip(Self::offset(page)?)
+            .take(page.limit as usize)
+            .map(Self::customer_to_response)
+            .collect();
+
+        Ok(CustomerListResponse {
+            status: "success".to_string(),
+            data: json_result,
+            pagination: Pagination::new(page, total_count),
+        })
+    }
+
+    async fn list_customers_after(
+        &self,
+        tenant: &Tenant,
+        filter: &CustomerFilter,
+        after: Option<&CustomerCursor>,
+        limit: i64,
+    ) -> Result<Vec<CustomerResponse>> {
+        let after = match after {
+            Some(cursor) => Some((
+                cursor.name.is_none(),
+                cursor.name.as_ref(),
+                Self::parse_uuid(&cursor.id)?,
+            )),
+            None => None,
+        };
+
+        let customers = self.customers.read().map_err(|_| Error::CustomerError)?;
+        let mut sorted: Vec<&CustomerModel> = customers
+            .values()
+            .filter(|customer| customer.tenant_id == tenant.as_str())
+            .filter(|customer| Self::customer_matches(customer, filter))
+            .filter(|customer| {
+                after.map_or(true, |after| Self::customer_sort_key(customer) > after)
+            })
+            .collect();
+        sorted.sort_by_key(|customer| Self::customer_sort_key(customer));
+
+        Ok(sorted
+            .into_iter()
+            .take(limit as usize)
+            .map(Self::customer_to_response)
+            .collect())
+    }
+
+    async fn search_customers(
+        &self,
+        tenant: &Tenant,
+        query: &str,
+        limit: i64,
+    ) -> Result<(Vec<CustomerResponse>, u64)> {
+        let customers = self.customers.read().map_err(|_| Error::CustomerError)?;
+        let mut matches: Vec<(usize, &CustomerModel)> = customers
+            .values()
+            .filter(|customer| customer.tenant_id == tenant.as_str())
+            .filter_map(|customer| {
+                let document = format!(
+                    "{} {}",
+                    customer.customer_name.as_deref().unwrap_or_default(),
+                    customer.customer_surname.as_deref().unwrap_or_default()
+                );
+                Self::search_rank(&document, query).map(|rank| (rank, customer))
+            })
+            .collect();
+        matches.sort_by_key(|(rank, customer)| (*rank, customer.customer_id));
+
+        Ok((
+            matches
+                .iter()
+                .take(limit as usize)
+                .map(|(_, customer)| Self::customer_to_response(customer))
+                .collect(),
+            matches.len() as u64,
+        ))
+    }
+
+    async fn get_customer(&self, tenant: &Tenant, id: &str) -> Result<SingleCustomerResponse> {
+        let customer_id = Self::parse_uuid(id)?;
+
+        let customers = self.customers.read().map_err(|_| Error::CustomerError)?;
+        let customer = customers
+            .get(&customer_id)
+            .filter(|customer| customer.tenant_id == tenant.as_str())
+            .ok_or_else(|| Error::not_found("customer", id))?;
+        let addresses = self.addresses.read().map_err(|_| Error::CustomerError)?;
+
+        Ok(Self::customer_to_single(
+            customer,
+            addresses.get(&customer_id).map_or(&[], Vec::as_slice),
+            "success",
+        ))
+    }
+
+    async fn update_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &CreateCustomerSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse> {
+        let customer_id = Self::parse_uuid(id)?;
+
+        let email = body.normalized_email();
+
+        let mut customers = self.customers.write().map_err(|_| Error::CustomerError)?;
+        Self::check_tenant(&customers, tenant, customer_id, id)?;
+        Self::check_email(&customers, tenant, email.as_deref(), Some(customer_id))?;
+        // unwrap() is allowed as the customer was looked up above while holding the lock
+        let customer = customers.get_mut(&customer_id).unwrap();
+        Self::check_version(customer.version, if_match, "customer", id)?;
+        customer.customer_name = Some(body.customer_name.to_owned());
+        customer.customer_surname = Some(body.customer_surname.to_owned());
+        customer.email = email;
+        customer.phone = body.phone.to_owned();
+        customer.version += 1;
+        customer.updated_at = Utc::now();
+        let addresses = Self::address_models(customer_id, &body.addresses);
+        let mut stored = self.addresses.write().map_err(|_| Error::CustomerError)?;
+        stored.insert(customer_id, addresses.clone());
+
+        Ok(Self::customer_to_single(customer, &addresses, "success"))
+    }
+
+    async fn patch_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &UpdateCustomerSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse> {
+        let customer_id = Self::parse_uuid(id)?;
+
+        let email = body.normalized_email();
+
+        let mut customers = self.customers.write().map_err(|_| Error::CustomerError)?;
+        Self::check_tenant(&customers, tenant, customer_id, id)?;
+        if let Some(email) = &email {
+            Self::check_email(&customers, tenant, email.as_deref(), Some(customer_id))?;
+        }
+        // unwrap() is allowed as the customer was looked up above while holding the lock
+        let customer = customers.get_mut(&customer_id).unwrap();
+        Self::check_version(customer.version, if_match, "customer", id)?;
+        if let Some(name) = &body.customer_name {
+            customer.customer_name = name.to_owned();
+        }
+        if let Some(surname) = &body.customer_surname {
+            customer.customer_surname = surname.to_owned();
+        }
+        if let Some(email) = email {
+            customer.email = email;
+        }
+        if let Some(phone) = &body.phone {
+            customer.phone = phone.to_owned();
+        }
+        customer.version += 1;
+        customer.updated_at = Utc::now();
+        let mut stored = self.addresses.write().map_err(|_| Error::CustomerError)?;
+        if let Some(addresses) = &body.addresses {
+            stored.insert(customer_id, Self::address_models(customer_id, addresses));
+        }
+
+        Ok(Self::customer_to_single(
+            customer,
+            stored.get(&customer_id).map_or(&[], Vec::as_slice),
+            "success",
+        ))
+    }
+
+    async fn delete_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse> {
+        let customer_id = Self::parse_uuid(id)?;
+
+        let mut customers = self.customers.write().map_err(|_| Error::CustomerError)?;
+        let version = customers
+            .get(&customer_id)
+            .filter(|customer| customer.tenant_id == tenant.as_str())
+            .ok_or_else(|| Error::not_found("customer", id))?
+            .version;
+        Self::check_version(version, if_match, "customer", id)?;
+        // unwrap() is allowed as the customer was looked up above while holding the lock
+        let customer = customers.remove(&customer_id).unwrap();
+        let mut stored = self.addresses.write().map_err(|_| Error::CustomerError)?;
+        let addresses = stored.remove(&customer_id).unwrap_or_default();
+
+        Ok(Self::customer_to_single(&customer, &addresses, "deleted"))
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn create_user(
+        &self,
+        tenant: Option<&Tenant>,
+        username: &str,
+        password_hash: &str,
+        scope: &str,
+    ) -> Result<UserModel> {
+        let mut users = self.users.write().map_err(|_| Error::HandlerError)?;
+        if users.values().any(|user| user.username == username) {
+            return Err(Error::Conflict {
+                resource: "user".to_string(),
+                key: format!("{{ username: \"{}\" }}", username),
+            });
+        }
+
+        let now = Utc::now();
+        let user = UserModel {
+            user_id: Uuid::new_v4(),
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            scope: scope.to_string(),
+            tenant_id: tenant.map(|tenant| tenant.as_str().to_string()),
+            created_at: now,
+            updated_at: now,
+        };
+        users.insert(user.user_id, user.clone());
+
+        Ok(user)
+    }
+
+    async fn get_user(&self, id: Uuid) -> Result<Option<UserModel>> {
+        let users = self.users.read().map_err(|_| Error::HandlerError)?;
+        Ok(users.get(&id).cloned())
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<UserModel>> {
+        let users = self.users.read().map_err(|_| Error::HandlerError)?;
+        Ok(users
+            .values()
+            .find(|user| user.username == username)
+            .cloned())
+    }
+
+    async fn insert_refresh_token(&self, token: &RefreshTokenModel) -> Result<()> {
+        let mut tokens = self
+            .refresh_tokens
+            .write()
+            .map_err(|_| Error::HandlerError)?;
+        tokens.insert(token.token_hash.to_owned(), token.clone());
+        Ok(())
+    }
+
+    async fn consume_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenModel>> {
+        let mut tokens = self
+            .refresh_tokens
+            .write()
+            .map_err(|_| Error::HandlerError)?;
+        let now = Utc::now();
+        let token = match tokens.get_mut(token_hash) {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        match token.revoked_at {
+            None if token.expires_at > now => {
+                token.revoked_at = Some(now);
+                Ok(Some(token.clone()))
+            }
+            None => Ok(None),
+            Some(_) => {
+                let family_id = token.family_id;
+                for token in tokens.values_mut() {
+                    if token.family_id == family_id && token.revoked_at.is_none() {
+                        token.revoked_at = Some(now);
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    async fn revoke_refresh_family(&self, token_hash: &str) -> Result<u64> {
+        let mut tokens = self
+            .refresh_tokens
+            .write()
+            .map_err(|_| Error::HandlerError)?;
+        let family_id = match tokens.get(token_hash) {
+            Some(token) => token.family_id,
+            None => return Ok(0),
+        };
+
+        let now = Utc::now();
+        let mut revoked = 0;
+        for token in tokens.values_mut() {
+            if token.family_id == family_id && token.revoked_at.is_none() {
+                token.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+
+    async fn insert_api_key(&self, key: &ApiKeyModel) -> Result<()> {
+        let mut keys = self.api_keys.write().map_err(|_| Error::HandlerError)?;
+        keys.insert(key.api_key_id, key.clone());
+        Ok(())
+    }
+
+    async fn list_api_keys(&self, tenant: &Tenant) -> Result<Vec<ApiKeyModel>> {
+        let keys = self.api_keys.read().map_err(|_| Error::HandlerError)?;
+        let mut keys: Vec<ApiKeyModel> = keys
+            .values()
+            .filter(|key| key.tenant_id == tenant.as_str())
+            .cloned()
+            .collect();
+        keys.sort_by_key(|key| (key.created_at, key.api_key_id));
+        Ok(keys)
+    }
+
+    async fn revoke_api_key(&self, tenant: &Tenant, id: &str) -> Result<ApiKeyModel> {
+        let api_key_id = Self::parse_uuid(id)?;
+        let mut keys = self.api_keys.write().map_err(|_| Error::HandlerError)?;
+        let key = keys
+            .get_mut(&api_key_id)
+            .filter(|key| key.tenant_id == tenant.as_str())
+            .ok_or_else(|| Error::not_found("api key", id))?;
+        key.revoked_at.get_or_insert_with(Utc::now);
+        Ok(key.clone())
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyModel>> {
+        let mut keys = self.api_keys.write().map_err(|_| Error::HandlerError)?;
+        let now = Utc::now();
+        let key = keys.values_mut().find(|key| {
+            key.key_hash == key_hash
+                && key.revoked_at.is_none()
+                && key.expires_at.map_or(true, |expires_at| expires_at > now)
+        });
+        Ok(key.map(|key| {
+            key.last_used_at = Some(now);
+            key.clone()
+        }))
+    }
+}
+
+#[async_trait]
+impl ProductStore for MemoryStore {
+    async fn create_product(&self, body: &CreateProductSchema) -> Result<SingleProductResponse> {
+        let now = Utc::now();
+        let product = ProductModel {
+            product_id: Uuid::new_v4(),
+            sku: body.sku.to_owned(),
+            product_name: body.product_name.to_owned(),
+            description: body.description.to_owned(),
+            price: body.price,
+            stock: body.stock,
+            version: 1,
+            created_at: now,
+            updated_at: now,
+        };
+
+        let mut products = self.products.write().map_err(|_| Error::HandlerError)?;
+        Self::check_sku(&products, &product.sku, None)?;
+        products.insert(product.product_id, product.clone());
+
+        Ok(Self::product_to_single(&product, "success"))
+    }
+
+    async fn list_products(&self, page: PageRequest) -> Result<ProductListResponse> {
+        let products = self.products.read().map_err(|_| Error::HandlerError)?;
+
+        let mut sorted: Vec<&ProductModel> = products.values().collect();
+        sorted.sort_by(|a, b| a.sku.cmp(&b.sku));
+        let total_count = sorted.len() as u64;
+
+        Ok(ProductListResponse {
+            status: "success".to_string(),
+            data: sorted
+                .into_iter()
+                .skip(Self::offset(page)?)
+                .take(page.limit as usize)
+                .map(Self::product_to_response)
+                .collect(),
+            pagination: Pagination::new(page, total_count),
+        })
+    }
+
+    async fn get_product(&self, id: &str) -> Result<SingleProductResponse> {
+        let product_id = Self::parse_uuid(id)?;
+
+        let products = self.products.read().map_err(|_| Error::HandlerError)?;
+        let product = products
+            .get(&product_id)
+            .ok_or_else(|| Error::not_found("product", id))?;
+
+        Ok(Self::product_to_single(product, "success"))
+    }
+
+    async fn get_product_by_sku(&self, sku: &str) -> Result<ProductResponse> {
+        let products = self.products.read().map_err(|_| Error::HandlerError)?;
+        products
+            .values()
+            .find(|product| product.sku == sku)
+            .map(Self::product_to_response)
+            .ok_or_else(|| Error::not_found("product", sku))
+    }
+
+    async fn update_product(
+        &self,
+        id: &str,
+        body: &CreateProductSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        let product_id = Self::parse_uuid(id)?;
+
+        let mut products = self.products.write().map_err(|_| Error::HandlerError)?;
+        let version = products
+            .get(&product_id)
+            .ok_or_else(|| Error::not_found("product", id))?
+            .version;
+        Self::check_version(version, if_match, "product", id)?;
+        Self::check_sku(&products, &body.sku, Some(product_id))?;
+        // unwrap() is allowed as the product was looked up above while holding the lock
+        let product = products.get_mut(&product_id).unwrap();
+        product.sku = body.sku.to_owned();
+        product.product_name = body.product_name.to_owned();
+        product.description = body.description.to_owned();
+        product.price = body.price;
+        product.stock = body.stock;
+        product.version += 1;
+        product.updated_at = Utc::now();
+
+        Ok(Self::product_to_single(product, "success"))
+    }
+
+    async fn patch_product(
+        &self,
+        id: &str,
+        body: &UpdateProductSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        let product_id = Self::parse_uuid(id)?;
+
+        let mut products = self.products.write().map_err(|_| Error::HandlerError)?;
+        let version = products
+            .get(&product_id)
+            .ok_or_else(|| Error::not_found("product", id))?
+            .version;
+        Self::check_version(version, if_match, "product", id)?;
+        if let Some(sku) = &body.sku {
+            Self::check_sku(&products, sku, Some(product_id))?;
+        }
+        let product = products.get_mut(&product_id).unwrap();
+        if let Some(sku) = &body.sku {
+            product.sku = sku.to_owned();
+        }
+        if let Some(product_name) = &body.product_name {
+            product.product_name = product_name.to_owned();
+        }
+        if let Some(description) = &body.description {
+            product.description = description.to_owned();
+        }
+        if let Some(price) = body.price {
+            product.price = price;
+        }
+        if let Some(stock) = body.stock {
+            product.stock = stock;
+        }
+        product.version += 1;
+        product.updated_at = Utc::now();
+
+        Ok(Self::product_to_single(product, "success"))
+    }
+
+    async fn delete_product(
+        &self,
+        id: &str,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        let product_id = Self::parse_uuid(id)?;
+
+        let mut products = self.products.write().map_err(|_| Error::HandlerError)?;
+        let version = products
+            .get(&product_id)
+            .ok_or_else(|| Error::not_found("product", id))?
+            .version;
+        Self::check_version(version, if_match, "product", id)?;
+        let product = products.remove(&product_id).unwrap();
+
+        Ok(Self::product_to_single(&product, "deleted"))
+    }
+
+    async fn reserve_stock(
+        &self,
+        order_id: &str,
+        items: &[OrderItemSchema],
+    ) -> Result<Vec<LineItem>> {
+        self.reserve(order_id, items)
+    }
+
+    async fn release_stock(&self, order_id: &str) -> Result<u64> {
+        let mut products = self.products.write().map_err(|_| Error::HandlerError)?;
+        let mut reservations = self.reservations.write().map_err(|_| Error::HandlerError)?;
+        let reserved = reservations.remove(order_id).unwrap_or_default();
+        Self::restock(&mut products, &reserved);
+
+        Ok(reserved.len() as u64)
+    }
+
+    async fn settle_stock(&self, order_id: &str) -> Result<u64> {
+        let mut reservations = self.reservations.write().map_err(|_| Error::HandlerError)?;
+        let reserved = reservations.remove(order_id).unwrap_or_default();
+
+        Ok(reserved.len() as u64)
+    }
+}
+
+#[async_trait]
+impl OrderStore for MemoryStore {
+    async fn create_order(
+        &self,
+        tenant: &Tenant,
+        body: &CreateOrderSchema,
+    ) -> Result<SingleOrderResponse> {
+        let customer_name = ensure_customer_exists(self, tenant, &body.customer_id).await?;
+        let id = ObjectId::new();
+        let items = self.reserve(&id.to_hex(), &body.items)?;
+
+        let now = bson::DateTime::now();
+        let order = OrderModel {
+            id,
+            tenant_id: tenant.as_str().to_string(),
+            customer_id: body.customer_id.to_owned(),
+            customer_name,
+            product_name: LineItem::names(&items),
+            total: LineItem::total(&items),
+            items,
+            currency: Some(body.currency.to_owned()),
+            status: OrderStatus::Pending,
+            deleted: false,
+            version: 1,
+            created_at: Some(now),
+            updated_at: Some(now),
+        };
+
+        let mut orders = self.orders.write().map_err(|_| Error::MongoError)?;
+        orders.insert(order.id, order.clone());
+
+        Ok(Self::order_to_single(&order))
+    }
+
+    async fn fetch_orders(
+        &self,
+        tenant: &Tenant,
+        filter: &OrderFilter,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse> {
+        let orders = self.orders.read().map_err(|_| Error::MongoError)?;
+        let mut sorted: Vec<&OrderModel> = orders
+            .values()
+            .filter(|order| order.tenant_id == tenant.as_str())
+            .filter(|order| !order.deleted && Self::order_matches(order, filter))
+            .collect();
+        sorted.sort_by(|a, b| Self::compare_orders(a, b, sort));
+
+        Self::order_page(sorted, page)
+    }
+
+    async fn fetch_orders_after(
+        &self,
+        tenant: &Tenant,
+        filter: &OrderFilter,
+        after: Option<&OrderCursor>,
+        limit: i64,
+    ) -> Result<Vec<OrderResponse>> {
+        let start = match after {
+            Some(cursor) => Bound::Excluded(Self::parse_order_id(&cursor.id)?),
+            None => Bound::Unbounded,
+        };
+
+        let orders = self.orders.read().map_err(|_| Error::MongoError)?;
+        Ok(orders
+            .range((start, Bound::Unbounded))
+            .map(|(_, order)| order)
+            .filter(|order| order.tenant_id == tenant.as_str())
+            .filter(|order| !order.deleted && Self::order_matches(order, filter))
+            .take(limit as usize)
+            .map(Self::doc_to_order)
+            .collect())
+    }
+
+    async fn search_orders(
+        &self,
+        tenant: &Tenant,
+        query: &str,
+        limit: i64,
+    ) -> Result<(Vec<OrderResponse>, u64)> {
+        let orders = self.orders.read().map_err(|_| Error::MongoError)?;
+        let mut matches: Vec<(usize, &OrderModel)> = orders
+            .values()
+            .filter(|order| order.tenant_id == tenant.as_str() && !order.deleted)
+            .filter_map(|order| {
+                let document = format!("{} {}", order.customer_name, order.product_name);
+                Self::search_rank(&document, query).map(|rank| (rank, order))
+            })
+            .collect();
+        matches.sort_by_key(|(rank, order)| (*rank, order.id));
+
+        Ok((
+            matches
+                .iter()
+                .take(limit as usize)
+                .map(|(_, order)| Self::doc_to_order(order))
+                .collect(),
+            matches.len() as u64,
+        ))
+    }
+
+    async fn get_order(&self, tenant: &Tenant, id: &str) -> Result<SingleOrderResponse> {
+        let oid = Self::parse_order_id(id)?;
+
+        let orders = self.orders.read().map_err(|_| Error::MongoError)?;
+        let order = orders
+            .get(&oid)
+            .filter(|order| order.tenant_id == tenant.as_str() && !order.deleted)
+            .ok_or_else(|| Error::not_found("order", id))?;
+
+        Ok(Self::order_to_single(order))
+    }
+
+    async fn fetch_customer_orders(
+        &self,
+        tenant: &Tenant,
+        customer_id: &str,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse> {
+        let orders = self.orders.read().map_err(|_| Error::MongoError)?;
+        let mut sorted: Vec<&OrderModel> = orders
+            .values()
+            .filter(|order| order.tenant_id == tenant.as_str())
+            .filter(|order| !order.deleted && order.customer_id == customer_id)
+            .collect();
+        sorted.sort_by(|a, b| Self::compare_orders(a, b, sort));
+
+        Self::order_page(sorted, page)
+    }
+
+    async fn edit_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &CreateOrderSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse> {
+        let oid = Self::parse_order_id(id)?;
+        let customer_name = ensure_customer_exists(self, tenant, &body.customer_id).await?;
+
+        let mut orders = self.orders.write().map_err(|_| Error::MongoError)?;
+        let order = orders
+            .get_mut(&oid)
+            .filter(|order| order.tenant_id == tenant.as_str() && !order.deleted)
+            .ok_or_else(|| Error::not_found("order", id))?;
+        Self::check_version(order.version, if_match, "order", id)?;
+        // like Mongo, the items of a shipped or cancelled order are final
+        if order.status.holds_stock() {
+            let items = self.reserve(&oid.to_hex(), &body.items)?;
+            order.product_name = LineItem::names(&items);
+            order.total = LineItem::total(&items);
+            order.items = items;
+        } else if !same_items(&order.items, &body.items) {
+            return Err(Error::items_final("order", id, order.status.as_ref()));
+        }
+        order.customer_id = body.customer_id.to_owned();
+        order.customer_name = customer_name;
+        order.currency = Some(body.currency.to_owned());
+        order.version += 1;
+        order.updated_at = Some(bson::DateTime::now());
+
+        Ok(Self::order_to_single(order))
+    }
+
+    async fn patch_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &UpdateOrderSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse> {
+        let oid = Self::parse_order_id(id)?;
+        let customer_name = match &body.customer_id {
+            Some(customer_id) => Some(ensure_customer_exists(self, tenant, customer_id).await?),
+            None => None,
+        };
+
+        let mut orders = self.orders.write().map_err(|_| Error::MongoError)?;
+        let order = orders
+            .get_mut(&oid)
+            .filter(|order| order.tenant_id == tenant.as_str() && !order.deleted)
+            .ok_or_else(|| Error::not_found("order", id))?;
+        Self::check_version(order.version, if_match, "order", id)?;
+        // like Mongo, an empty patch changes nothing and keeps the version
+        if body.customer_id.is_none() {
+            return Ok(Self::order_to_single(order));
+        }
+        if let Some(customer_id) = &body.customer_id {
+            order.customer_id = customer_id.to_owned();
+        }
+        if let Some(customer_name) = customer_name {
+            order.customer_name = customer_name;
+        }
+        order.version += 1;
+        order.updated_at = Some(bson::DateTime::now());
+
+        Ok(Self::order_to_single(order))
+    }
+
+    async fn transition_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        status: OrderStatus,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse> {
+        let oid = Self::parse_order_id(id)?;
+
+        let mut orders = self.orders.write().map_err(|_| Error::MongoError)?;
+        let order = orders
+            .get_mut(&oid)
+            .filter(|order| order.tenant_id == tenant.as_str() && !order.deleted)
+            .ok_or_else(|| Error::not_found("order", id))?;
+        Self::check_version(order.version, if_match, "order", id)?;
+        if !order.status.can_transition_to(status) {
+            return Err(Error::invalid_transition(
+                "order",
+                id,
+                order.status.as_ref(),
+                status.as_ref(),
+            ));
+        }
+        order.status = status;
+        order.version += 1;
+        order.updated_at = Some(bson::DateTime::now());
+
+        Ok(Self::order_to_single(order))
+    }
+
+    async fn delete_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        soft: bool,
+        if_match: Option<&[i32]>,
+    ) -> Result<DeleteOrderResponse> {
+        let oid = Self::parse_order_id(id)?;
+
+        let mut orders = self.orders.write().map_err(|_| Error::MongoError)?;
+        let version = orders
+            .get(&oid)
+            .filter(|order| order.tenant_id == tenant.as_str() && !order.deleted)
+            .ok_or_else(|| Error::not_found("order", id))?
+            .version;
+        Self::check_version(version, if_match, "order", id)?;
+
+        let order = if soft {
+            // unwrap() is allowed as the order was looked up above while holding the lock
+            let order = orders.get_mut(&oid).unwrap();
+            order.deleted = true;
+            order.version += 1;
+            order.updated_at = Some(bson::DateTime::now());
+            order.clone()
+        } else {
+            orders.remove(&oid).unwrap()
+        };
+
+        Ok(DeleteOrderResponse {
+            status: "deleted".to_string(),
+            id: id.to_string(),
+            data: OrderData {
+                order: Self::doc_to_order(&order),
+            },
+        })
+    }
+    async fn count_customer_orders(&self, tenant: &Tenant, customer_id: &str) -> Result<u64> {
+        let orders = self.orders.read().map_err(|_| Error::MongoError)?;
+        let count = orders
+            .values()
+            .filter(|order| order.tenant_id == tenant.as_str())
+            .filter(|order| !order.deleted && order.customer_id == customer_id)
+            .count();
+
+        Ok(count as u64)
+    }
+
+    async fn delete_customer_orders(
+        &self,
+        tenant: &Tenant,
+        customer_id: &str,
+    ) -> Result<Vec<String>> {
+        let mut orders = self.orders.write().map_err(|_| Error::MongoError)?;
+        let ids: Vec<ObjectId> = orders
+            .values()
+            .filter(|order| order.tenant_id == tenant.as_str() && order.customer_id == customer_id)
+            .map(|order| order.id)
+            .collect();
+        for id in &ids {
+            orders.remove(id);
+        }
+
+        Ok(ids.iter().map(|id| id.to_hex()).collect())
+    }
+}
@@ -1,20 +1,234 @@
-use mongodb::bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use mongodb::bson::{self, oid::ObjectId};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+use crate::tenant::DEFAULT_TENANT;
+
+/// Decimal places of prices, as stored in the `product` table.
+pub const PRICE_SCALE: u32 = 2;
+
+/// One product of an order, its name and price are copied from the catalog
+/// when the order is placed. Prices are decimals, serialized as strings so that
+/// neither JSON nor BSON round them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LineItem {
+    pub sku: String,
+    pub product_name: String,
+    pub quantity: u32,
+    pub unit_price: Decimal,
+}
+
+impl LineItem {
+    pub fn subtotal(&self) -> Decimal {
+        self.unit_price * Decimal::from(self.quantity)
+    }
+
+    /// What an order is for, the names of its products.
+    pub fn names(items: &[LineItem]) -> String {
+        items
+            .iter()
+            .map(|item| item.product_name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Sum of the subtotals with `PRICE_SCALE` decimal places.
+    pub fn total(items: &[LineItem]) -> Decimal {
+        let mut total: Decimal = items.iter().map(LineItem::subtotal).sum();
+        total.rescale(PRICE_SCALE);
+        total
+    }
+}
+
+/// Lifecycle of an order: `pending -> paid -> shipped -> delivered`, an order
+/// that has not shipped yet can also be `cancelled`.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, strum_macros::AsRefStr,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum OrderStatus {
+    #[default]
+    Pending,
+    Paid,
+    Shipped,
+    Delivered,
+    Cancelled,
+}
+
+impl OrderStatus {
+    /// The statuses an order may move to `to` from.
+    pub fn sources(to: Self) -> &'static [Self] {
+        match to {
+            Self::Pending => &[],
+            Self::Paid => &[Self::Pending],
+            Self::Shipped => &[Self::Paid],
+            Self::Delivered => &[Self::Shipped],
+            Self::Cancelled => &[Self::Pending, Self::Paid],
+        }
+    }
+
+    pub fn can_transition_to(self, to: Self) -> bool {
+        Self::sources(to).contains(&self)
+    }
+
+    /// Open orders hold a stock reservation, shipping settles it and
+    /// cancelling releases it.
+    pub fn holds_stock(self) -> bool {
+        matches!(self, Self::Pending | Self::Paid)
+    }
+}
+
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OrderModel {
     #[serde(rename = "_id")]
     pub id: ObjectId,
+    // orders created before tenants were added belong to the default tenant
+    #[serde(default = "default_tenant")]
+    pub tenant_id: String,
+    // orders created before customers were linked have no customer id
+    #[serde(default)]
+    pub customer_id: String,
     pub customer_name: String,
     pub product_name: String,
+    // orders created before line items were added have none, a zero total and
+    // no currency, and are pending
+    #[serde(default)]
+    pub items: Vec<LineItem>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub total: Decimal,
+    #[serde(default)]
+    pub status: OrderStatus,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default)]
+    pub version: i32,
+    // orders created before timestamps were added have neither, see `created_at()`
+    #[serde(default)]
+    pub created_at: Option<bson::DateTime>,
+    #[serde(default)]
+    pub updated_at: Option<bson::DateTime>,
+}
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+impl OrderModel {
+    /// Falls back to the creation time embedded in the id.
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+            .unwrap_or_else(|| self.id.timestamp())
+            .to_chrono()
+    }
+
+    /// Falls back to the creation time for orders that were never updated since.
+    pub fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at
+            .map(bson::DateTime::to_chrono)
+            .unwrap_or_else(|| self.created_at())
+    }
 }
 
 #[allow(non_snake_case)]
-#[derive(Debug, FromRow, Deserialize, Serialize)]
+#[derive(Debug, FromRow, Deserialize, Serialize, Clone)]
 pub struct CustomerModel {
     pub customer_id: sqlx::types::Uuid,
+    pub tenant_id: String,
     pub customer_name: Option<String>,
     pub customer_surname: Option<String>,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Lowercased, customers created before contact details were added have none.
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, strum_macros::AsRefStr,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AddressKind {
+    Billing,
+    Shipping,
+}
+
+/// A row of the `customer_address` table, `position` keeps the order in which
+/// the addresses were sent. `kind` is the `AddressKind` as a string.
+#[derive(Debug, FromRow, Deserialize, Serialize, Clone)]
+pub struct AddressModel {
+    pub address_id: sqlx::types::Uuid,
+    pub customer_id: sqlx::types::Uuid,
+    pub position: i32,
+    pub kind: String,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub postal_code: String,
+    pub country: String,
+    pub is_default: bool,
+}
+
+#[derive(Debug, FromRow, Deserialize, Serialize, Clone)]
+pub struct ProductModel {
+    pub product_id: sqlx::types::Uuid,
+    pub sku: String,
+    pub product_name: String,
+    pub description: Option<String>,
+    pub price: Decimal,
+    pub stock: i32,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A row of the `users` table, `scope` holds the scopes granted to the
+/// account separated by spaces.
+#[derive(Debug, FromRow, Clone)]
+pub struct UserModel {
+    pub user_id: sqlx::types::Uuid,
+    pub username: String,
+    pub password_hash: String,
+    pub scope: String,
+    /// The tenant the user signs in to, `None` for operators of every tenant.
+    pub tenant_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A row of the `api_key` table. Only the hash of a key is stored, `prefix` is
+/// its first characters so that the owner can tell keys apart.
+#[derive(Debug, FromRow, Clone)]
+pub struct ApiKeyModel {
+    pub api_key_id: sqlx::types::Uuid,
+    pub name: String,
+    pub owner: String,
+    pub key_hash: String,
+    pub prefix: String,
+    pub scope: String,
+    /// The only tenant the key may act on.
+    pub tenant_id: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A row of the `refresh_token` table. Only the hash of a token is stored,
+/// every token rotated from one login shares its `family_id`.
+#[derive(Debug, FromRow, Clone)]
+pub struct RefreshTokenModel {
+    pub token_hash: String,
+    pub user_id: sqlx::types::Uuid,
+    pub family_id: sqlx::types::Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
 }
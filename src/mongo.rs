@@ -1,30 +1,163 @@
+use crate::model::{LineItem, OrderModel, OrderStatus};
 use crate::response::{
-    DeleteOrderResponse, OrderData, OrderListResponse, OrderResponse, SingleOrderResponse,
+    DeleteOrderResponse, OrderData, OrderListResponse, OrderResponse, Pagination,
+    SingleOrderResponse,
 };
-use crate::{model::OrderModel, schema::CreateOrderSchema};
+use crate::schema::{
+    CreateOrderSchema, OrderCursor, OrderFilter, OrderItemSchema, PageRequest, Sort, TextMatch,
+    UpdateOrderSchema,
+};
+use crate::store::{ensure_customer_exists, same_items, DynCustomerStore, DynProductStore};
+use crate::tenant::Tenant;
 use crate::{Error, Result};
 use autometrics::autometrics;
 use futures::StreamExt;
-use mongodb::bson::{doc, oid::ObjectId, Document};
-use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
-use mongodb::{bson, options::ClientOptions, Client, Collection};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, IndexOptions, ReturnDocument};
+use mongodb::{bson, options::ClientOptions, Client, Collection, IndexModel};
 use std::convert::TryFrom;
 use std::str::FromStr;
 use tracing::instrument;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug)]
 pub struct MONGO {
     pub note_collection: Collection<OrderModel>,
     pub collection: Collection<Document>,
+    /// Orders reference customers, which are checked before every write.
+    pub customers: DynCustomerStore,
+    /// Line items are priced from the product catalog.
+    pub products: DynProductStore,
+}
+
+const DUPLICATE_KEY_ERROR_CODE: i32 = 11000;
+
+/// Text index over the customer and product name of orders.
+const SEARCH_INDEX: &str = "order_search";
+/// Index every query of a tenant starts with.
+const TENANT_INDEX: &str = "order_tenant";
+
+/// Condition on `tenant_id` matching the orders of `tenant`. Orders created
+/// before tenants were added have none and belong to the default tenant.
+fn tenant_condition(tenant: &Tenant) -> Bson {
+    if tenant.is_default() {
+        Bson::Document(doc! {"$in": [tenant.as_str(), Bson::Null]})
+    } else {
+        Bson::from(tenant.as_str())
+    }
+}
+
+/// The `sort` option for `sort`, ties are broken by `_id`, which follows the
+/// creation order.
+fn sort_document(sort: &Sort) -> Document {
+    let mut document = Document::new();
+    for key in &sort.keys {
+        let field = match key.field {
+            "id" => "_id",
+            field => field,
+        };
+        document.insert(field, if key.descending { -1 } else { 1 });
+    }
+    if !document.contains_key("_id") {
+        document.insert("_id", 1);
+    }
+
+    document
+}
+
+/// The live orders of `tenant` matching `filter`. The conditions are combined
+/// with `$and` as several of them can apply to the same field, values are
+/// matched literally.
+fn order_filter(tenant: &Tenant, filter: &OrderFilter) -> Document {
+    let mut conditions = vec![
+        doc! {"tenant_id": tenant_condition(tenant)},
+        doc! {"deleted": {"$ne": true}},
+    ];
+
+    for condition in &filter.text {
+        let value = &condition.value;
+        let matcher = match condition.op {
+            TextMatch::Exact => doc! {"$eq": value},
+            TextMatch::Prefix => doc! {"$regex": format!("^{}", regex::escape(value))},
+            TextMatch::Contains => doc! {"$regex": regex::escape(value), "$options": "i"},
+        };
+        conditions.push(doc! {condition.field: matcher});
+    }
+
+    // older orders lack the timestamps, which fall back like in `OrderModel`
+    for range in &filter.time {
+        let time = doc! {"$ifNull": [
+            format!("${}", range.field),
+            {"$ifNull": ["$created_at", {"$toDate": "$_id"}]},
+        ]};
+        let mut bounds = Vec::new();
+        if let Some(after) = range.after {
+            bounds.push(doc! {"$gte": [&time, bson::DateTime::from_chrono(after)]});
+        }
+        if let Some(before) = range.before {
+            bounds.push(doc! {"$lt": [&time, bson::DateTime::from_chrono(before)]});
+        }
+        conditions.push(doc! {"$expr": {"$and": bounds}});
+    }
+
+    // ObjectIds start with their creation time, see `OrderFilterOptions`
+    let mut created = Document::new();
+    if let Some(after) = filter.created_after {
+        created.insert("$gte", after);
+    }
+    if let Some(before) = filter.created_before {
+        created.insert("$lt", before);
+    }
+    if !created.is_empty() {
+        conditions.push(doc! {"_id": created});
+    }
+
+    doc! {"$and": conditions}
+}
+
+/// Translates a driver error into an `Error`, so that duplicate keys surface as a
+/// conflict and an unreachable server as unavailable instead of a generic query error.
+fn query_error(e: mongodb::error::Error) -> Error {
+    match e.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write_error))
+            if write_error.code == DUPLICATE_KEY_ERROR_CODE =>
+        {
+            tracing::error!("🔥 MongoDuplicateError: {:?}", e);
+            // the server reports the key as `... dup key: { customer_name: "john" }`
+            let key = write_error
+                .message
+                .split_once("dup key: ")
+                .map(|(_, key)| key.to_string())
+                .unwrap_or_default();
+            Error::Conflict {
+                resource: "order".to_string(),
+                key,
+            }
+        }
+        ErrorKind::Io(_)
+        | ErrorKind::ServerSelection { .. }
+        | ErrorKind::ConnectionPoolCleared { .. }
+        | ErrorKind::DnsResolve { .. } => {
+            tracing::error!("🔥 MongoConnectionError: {:?}", e);
+            Error::MongoConnectionError { e: (e.to_string()) }
+        }
+        _ => {
+            tracing::error!("🔥 MongoQueryError: {:?}", e);
+            Error::MongoQueryError { e: (e.to_string()) }
+        }
+    }
 }
 
 impl MONGO {
-    #[instrument]
+    #[instrument(skip(customers, products))]
     #[autometrics]
     pub async fn init(
         mongodb_username: String,
         mongodb_passwd: String,
         mongodb_server: String,
+        customers: DynCustomerStore,
+        products: DynProductStore,
     ) -> Result<Self> {
         // let mongodb_username: String = std::env::var("ME_CONFIG_MONGODB_ADMINUSERNAME")
         //     .expect("ME_CONFIG_MONGODB_ADMINUSERNAME must be set.");
@@ -37,9 +170,13 @@ impl MONGO {
             mongodb_username, mongodb_passwd, mongodb_server
         );
         let database_name: String =
-            std::env::var("MONGO_INITDB_DATABASE").expect("MONGO_INITDB_DATABASE must be set.");
+            std::env::var("MONGO_INITDB_DATABASE").map_err(|_| Error::ConfigMissing {
+                name: "MONGO_INITDB_DATABASE".to_string(),
+            })?;
         let mongodb_note_collection: String =
-            std::env::var("MONGODB_NOTE_COLLECTION").expect("MONGODB_NOTE_COLLECTION must be set.");
+            std::env::var("MONGODB_NOTE_COLLECTION").map_err(|_| Error::ConfigMissing {
+                name: "MONGODB_NOTE_COLLECTION".to_string(),
+            })?;
 
         let mut client_options = ClientOptions::parse(mongodb_uri)
             .await
@@ -54,42 +191,147 @@ impl MONGO {
         let note_collection = database.collection(mongodb_note_collection.as_str());
         let collection = database.collection::<Document>(mongodb_note_collection.as_str());
 
+        // `$text` queries need the index, the API still starts without it
+        let search_index = IndexModel::builder()
+            .keys(doc! {"customer_name": "text", "product_name": "text"})
+            .options(
+                IndexOptions::builder()
+                    .name(SEARCH_INDEX.to_string())
+                    .build(),
+            )
+            .build();
+        if let Err(e) = note_collection.create_index(search_index, None).await {
+            tracing::warn!("Could not create the {} index: {}", SEARCH_INDEX, e);
+        }
+        let tenant_index = IndexModel::builder()
+            .keys(doc! {"tenant_id": 1, "_id": 1})
+            .options(
+                IndexOptions::builder()
+                    .name(TENANT_INDEX.to_string())
+                    .build(),
+            )
+            .build();
+        if let Err(e) = note_collection.create_index(tenant_index, None).await {
+            tracing::warn!("Could not create the {} index: {}", TENANT_INDEX, e);
+        }
+
         tracing::info!("✅ Database connected successfully");
 
         Ok(Self {
             note_collection,
             collection,
+            customers,
+            products,
         })
     }
 
     #[instrument]
     #[autometrics]
-    pub async fn fetch_orders(&self, limit: i64, page: i64) -> Result<OrderListResponse> {
+    pub async fn fetch_orders(
+        &self,
+        tenant: &Tenant,
+        filter: &OrderFilter,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse> {
+        self.find_orders(order_filter(tenant, filter), page, sort)
+            .await
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn fetch_orders_after(
+        &self,
+        tenant: &Tenant,
+        filter: &OrderFilter,
+        after: Option<&OrderCursor>,
+        limit: i64,
+    ) -> Result<Vec<OrderResponse>> {
+        let mut filter = order_filter(tenant, filter);
+        if let Some(cursor) = after {
+            let oid = ObjectId::from_str(&cursor.id)
+                .map_err(|e| Error::MongoInvalidIDError { e: (e.to_string()) })?;
+            filter = doc! {"$and": [filter, {"_id": {"$gt": oid}}]};
+        }
+
         let find_options = FindOptions::builder()
             .limit(limit)
+            .sort(doc! {"_id": 1})
+            .build();
+
+        let mut cursor = self
+            .note_collection
+            .find(filter, find_options)
+            .await
+            .map_err(query_error)?;
+
+        let mut json_result: Vec<OrderResponse> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            json_result.push(self.doc_to_order(&doc.map_err(query_error)?));
+        }
+
+        Ok(json_result)
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn fetch_customer_orders(
+        &self,
+        tenant: &Tenant,
+        customer_id: &str,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse> {
+        self.find_orders(
+            doc! {
+                "tenant_id": tenant_condition(tenant),
+                "customer_id": customer_id,
+                "deleted": {"$ne": true},
+            },
+            page,
+            sort,
+        )
+        .await
+    }
+
+    /// One page of the orders matching `filter`, along with their total count.
+    async fn find_orders(
+        &self,
+        filter: Document,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse> {
+        let total_count = self
+            .note_collection
+            .count_documents(filter.clone(), None)
+            .await
+            .map_err(query_error)?;
+
+        let find_options = FindOptions::builder()
+            .limit(page.limit)
             .skip(
-                u64::try_from((page - 1) * limit)
+                u64::try_from(page.offset())
                     .map_err(|e| Error::MongoParsingError { e: (e.to_string()) })?,
             )
+            .sort(sort_document(sort))
             .build();
 
         let mut cursor = self
             .note_collection
-            .find(None, find_options)
+            .find(filter, find_options)
             .await
-            .map_err(|e| Error::MongoQueryError { e: (e.to_string()) })?;
+            .map_err(query_error)?;
 
         let mut json_result: Vec<OrderResponse> = Vec::new();
         while let Some(doc) = cursor.next().await {
-            println!("{:?}", doc);
-            // unwrap() is allowed as there is no case where an None type will enter the while loop
-            json_result.push(self.doc_to_order(&doc.unwrap()));
+            json_result.push(self.doc_to_order(&doc.map_err(query_error)?));
         }
 
         let json_note_list = OrderListResponse {
             status: "success".to_string(),
             results: json_result.len(),
             orders: json_result,
+            pagination: Pagination::new(page, total_count),
         };
 
         Ok(json_note_list)
@@ -97,33 +339,61 @@ impl MONGO {
 
     #[instrument]
     #[autometrics]
-    pub async fn create_order(&self, body: &CreateOrderSchema) -> Result<SingleOrderResponse> {
-        let customer_name = body.customer_name.to_owned();
-        let product_name = body.product_name.to_owned();
+    pub async fn create_order(
+        &self,
+        tenant: &Tenant,
+        body: &CreateOrderSchema,
+    ) -> Result<SingleOrderResponse> {
+        let customer_name =
+            ensure_customer_exists(self.customers.as_ref(), tenant, &body.customer_id).await?;
+        // the id is chosen up front as the stock is reserved for it before the insert
+        let id = ObjectId::new();
+        let line_items = self
+            .products
+            .reserve_stock(&id.to_hex(), &body.items)
+            .await?;
+
+        let customer_id = body.customer_id.to_owned();
+        let product_name = LineItem::names(&line_items);
+
+        let items = bson::to_bson(&line_items)
+            .map_err(|e| Error::MongoSerializeBsonError { e: (e.to_string()) })?;
 
-        let doc = doc! {"customer_name": customer_name, "product_name": product_name};
+        let now = bson::DateTime::now();
+        let doc = doc! {
+            "_id": id,
+            "tenant_id": tenant.as_str(),
+            "customer_id": customer_id,
+            "customer_name": customer_name,
+            "product_name": product_name,
+            "items": items,
+            "currency": body.currency.as_str(),
+            // decimals are kept as strings, BSON doubles would round them
+            "total": LineItem::total(&line_items).to_string(),
+            "status": OrderStatus::Pending.as_ref(),
+            "version": 1,
+            "created_at": now,
+            "updated_at": now,
+        };
 
-        let insert_result = self.collection.insert_one(&doc, None).await.map_err(|e| {
-            if e.to_string()
-                .contains("E11000 duplicate key error collection")
-            {
-                tracing::error!("🔥 MongoDuplicateError: {:?}", e);
-                std::process::exit(1);
+        let insert_result = match self.collection.insert_one(&doc, None).await {
+            Ok(insert_result) => insert_result,
+            Err(e) => {
+                self.restore_stock(&id.to_hex(), &[]).await;
+                return Err(query_error(e));
             }
-            tracing::error!("🔥 MongoQueryError: {:?}", e);
-            std::process::exit(1);
-        })?;
+        };
 
         let new_id = insert_result
             .inserted_id
             .as_object_id()
-            .expect("issue with new _id");
+            .ok_or(Error::MongoError)?;
 
         let order_doc = self
             .note_collection
             .find_one(doc! {"_id":new_id }, None)
             .await
-            .map_err(|e| Error::MongoQueryError { e: (e.to_string()) })?
+            .map_err(query_error)?
             .ok_or(Error::MongoError)?;
 
         let note_response = SingleOrderResponse {
@@ -136,18 +406,62 @@ impl MONGO {
         Ok(note_response)
     }
 
+    /// Matches whole words, stemmed, through the `order_search` text index.
+    #[instrument]
+    #[autometrics]
+    pub async fn search_orders(
+        &self,
+        tenant: &Tenant,
+        query: &str,
+        limit: i64,
+    ) -> Result<(Vec<OrderResponse>, u64)> {
+        let filter = doc! {
+            "$text": {"$search": query},
+            "tenant_id": tenant_condition(tenant),
+            "deleted": {"$ne": true},
+        };
+
+        let total_count = self
+            .note_collection
+            .count_documents(filter.clone(), None)
+            .await
+            .map_err(query_error)?;
+
+        let find_options = FindOptions::builder()
+            .projection(doc! {"score": {"$meta": "textScore"}})
+            .sort(doc! {"score": {"$meta": "textScore"}, "_id": 1})
+            .limit(limit)
+            .build();
+
+        let mut cursor = self
+            .note_collection
+            .find(filter, find_options)
+            .await
+            .map_err(query_error)?;
+
+        let mut json_result: Vec<OrderResponse> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            json_result.push(self.doc_to_order(&doc.map_err(query_error)?));
+        }
+
+        Ok((json_result, total_count))
+    }
+
     #[instrument]
     #[autometrics]
-    pub async fn get_order(&self, id: &str) -> Result<SingleOrderResponse> {
+    pub async fn get_order(&self, tenant: &Tenant, id: &str) -> Result<SingleOrderResponse> {
         let oid = ObjectId::from_str(id)
             .map_err(|e| Error::MongoInvalidIDError { e: (e.to_string()) })?;
 
         let note_doc = self
             .note_collection
-            .find_one(doc! {"_id":oid }, None)
+            .find_one(
+                doc! {"_id": oid, "tenant_id": tenant_condition(tenant), "deleted": {"$ne": true}},
+                None,
+            )
             .await
-            .map_err(|e| Error::MongoQueryError { e: (e.to_string()) })?
-            .ok_or(Error::MongoError)?;
+            .map_err(query_error)?
+            .ok_or_else(|| Error::not_found("order", id))?;
 
         let note_response = SingleOrderResponse {
             status: "success".to_string(),
@@ -163,14 +477,16 @@ impl MONGO {
     #[autometrics]
     pub async fn edit_order(
         &self,
+        tenant: &Tenant,
         id: &str,
         body: &CreateOrderSchema,
+        if_match: Option<&[i32]>,
     ) -> Result<SingleOrderResponse> {
         let oid = ObjectId::from_str(id)
             .map_err(|e| Error::MongoInvalidIDError { e: (e.to_string()) })?;
-        let query = doc! {
-            "_id": oid,
-        };
+        let customer_name =
+            ensure_customer_exists(self.customers.as_ref(), tenant, &body.customer_id).await?;
+        let mut query = self.write_query(tenant, oid, if_match);
 
         let find_one_and_update_options = FindOneAndUpdateOptions::builder()
             .return_document(ReturnDocument::After)
@@ -178,17 +494,93 @@ impl MONGO {
 
         let serialized_data = bson::to_bson(body)
             .map_err(|e| Error::MongoSerializeBsonError { e: (e.to_string()) })?;
-        let document = serialized_data
+        let mut document = serialized_data
             .as_document()
-            .ok_or(Error::MongoSerializeError)?;
-        let update = doc! {"$set": document};
+            .ok_or(Error::MongoSerializeError)?
+            .clone();
+        document.insert("customer_name", customer_name);
+        document.insert("updated_at", bson::DateTime::now());
+
+        let order = self.get_order(tenant, id).await?.data.order;
+        // shipped orders settled their reservation and cancelled ones released
+        // it, their items are final and the stock is left alone
+        if !order.status.holds_stock() {
+            if if_match.is_some_and(|versions| !versions.contains(&order.version)) {
+                return Err(Error::precondition_failed("order", id));
+            }
+            if !same_items(&order.items, &body.items) {
+                return Err(Error::items_final("order", id, order.status.as_ref()));
+            }
+            document.remove("items");
+
+            let note_doc = self
+                .note_collection
+                .find_one_and_update(
+                    query,
+                    doc! {"$set": document, "$inc": {"version": 1}},
+                    find_one_and_update_options,
+                )
+                .await
+                .map_err(query_error)?;
+            return match note_doc {
+                Some(note_doc) => Ok(SingleOrderResponse {
+                    status: "success".to_string(),
+                    data: OrderData {
+                        order: self.doc_to_order(&note_doc),
+                    },
+                }),
+                None => Err(self.write_failed(tenant, id).await),
+            };
+        }
 
-        let note_doc = self
+        // Postgres and MongoDB share no transaction, so the new items are reserved
+        // first and the previous ones again when the order is not written
+        let previous: Vec<OrderItemSchema> = order
+            .items
+            .into_iter()
+            .map(|item| OrderItemSchema {
+                sku: item.sku,
+                quantity: item.quantity,
+            })
+            .collect();
+        query.insert(
+            "status",
+            doc! {"$in": [OrderStatus::Pending.as_ref(), OrderStatus::Paid.as_ref(), Bson::Null]},
+        );
+        let line_items = self
+            .products
+            .reserve_stock(&oid.to_hex(), &body.items)
+            .await?;
+        document.insert(
+            "items",
+            bson::to_bson(&line_items)
+                .map_err(|e| Error::MongoSerializeBsonError { e: (e.to_string()) })?,
+        );
+        document.insert("product_name", LineItem::names(&line_items));
+        document.insert("total", LineItem::total(&line_items).to_string());
+        let update = doc! {"$set": document, "$inc": {"version": 1}};
+
+        let note_doc = match self
             .note_collection
             .find_one_and_update(query, update, find_one_and_update_options)
             .await
-            .map_err(|e| Error::MongoQueryError { e: (e.to_string()) })?
-            .ok_or(Error::MongoError)?;
+        {
+            Ok(Some(note_doc)) => note_doc,
+            result => {
+                // an order shipped, cancelled or deleted meanwhile holds no
+                // stock anymore, the reservation made above is dropped instead
+                let open = matches!(
+                    self.get_order(tenant, id).await,
+                    Ok(current) if current.data.order.status.holds_stock()
+                );
+                let restored: &[OrderItemSchema] = if open { &previous } else { &[] };
+                self.restore_stock(&oid.to_hex(), restored).await;
+                return Err(match result {
+                    Err(e) => query_error(e),
+                    _ => self.write_failed(tenant, id).await,
+                });
+            }
+        };
 
         let note_response = SingleOrderResponse {
             status: "success".to_string(),
@@ -202,32 +594,356 @@ impl MONGO {
 
     #[instrument]
     #[autometrics]
-    pub async fn delete_order(&self, id: &str) -> Result<DeleteOrderResponse> {
+    pub async fn patch_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &UpdateOrderSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse> {
+        let serialized_data = bson::to_bson(body)
+            .map_err(|e| Error::MongoSerializeBsonError { e: (e.to_string()) })?;
+        let mut document = serialized_data
+            .as_document()
+            .ok_or(Error::MongoSerializeError)?
+            .clone();
+
+        // an empty `$set` is rejected by the server, there is nothing to change anyway
+        if document.is_empty() {
+            let note_response = self.get_order(tenant, id).await?;
+            if if_match
+                .is_some_and(|versions| !versions.contains(&note_response.data.order.version))
+            {
+                return Err(Error::precondition_failed("order", id));
+            }
+            return Ok(note_response);
+        }
+
         let oid = ObjectId::from_str(id)
             .map_err(|e| Error::MongoInvalidIDError { e: (e.to_string()) })?;
+        if let Some(customer_id) = &body.customer_id {
+            let customer_name =
+                ensure_customer_exists(self.customers.as_ref(), tenant, customer_id).await?;
+            document.insert("customer_name", customer_name);
+        }
+        let query = self.write_query(tenant, oid, if_match);
+        document.insert("updated_at", bson::DateTime::now());
 
-        let _result = self
-            .collection
-            .delete_one(doc! {"_id":oid }, None)
+        let find_one_and_update_options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let note_doc = match self
+            .note_collection
+            .find_one_and_update(
+                query,
+                doc! {"$set": document, "$inc": {"version": 1}},
+                find_one_and_update_options,
+            )
+            .await
+            .map_err(query_error)?
+        {
+            Some(note_doc) => note_doc,
+            None => return Err(self.write_failed(tenant, id).await),
+        };
+
+        let note_response = SingleOrderResponse {
+            status: "success".to_string(),
+            data: OrderData {
+                order: self.doc_to_order(&note_doc),
+            },
+        };
+
+        Ok(note_response)
+    }
+
+    /// The status is only changed when it is still one the transition is
+    /// allowed from, so concurrent transitions cannot both succeed.
+    #[instrument]
+    #[autometrics]
+    pub async fn transition_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        status: OrderStatus,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse> {
+        let oid = ObjectId::from_str(id)
+            .map_err(|e| Error::MongoInvalidIDError { e: (e.to_string()) })?;
+        let mut query = self.write_query(tenant, oid, if_match);
+        let mut sources: Vec<Bson> = OrderStatus::sources(status)
+            .iter()
+            .map(|source| Bson::from(source.as_ref()))
+            .collect();
+        // orders created before the status was added have none and are pending
+        if OrderStatus::sources(status).contains(&OrderStatus::Pending) {
+            sources.push(Bson::Null);
+        }
+        query.insert("status", doc! {"$in": sources});
+
+        let find_one_and_update_options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let order_doc = match self
+            .note_collection
+            .find_one_and_update(
+                query,
+                doc! {
+                    "$set": {"status": status.as_ref(), "updated_at": bson::DateTime::now()},
+                    "$inc": {"version": 1},
+                },
+                find_one_and_update_options,
+            )
             .await
-            .map_err(|e| Error::MongoQueryError { e: (e.to_string()) })?;
+            .map_err(query_error)?
+        {
+            Some(order_doc) => order_doc,
+            None => return Err(self.transition_failed(tenant, id, status, if_match).await),
+        };
+
+        Ok(SingleOrderResponse {
+            status: "success".to_string(),
+            data: OrderData {
+                order: self.doc_to_order(&order_doc),
+            },
+        })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn delete_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        soft: bool,
+        if_match: Option<&[i32]>,
+    ) -> Result<DeleteOrderResponse> {
+        let oid = ObjectId::from_str(id)
+            .map_err(|e| Error::MongoInvalidIDError { e: (e.to_string()) })?;
+        let query = self.write_query(tenant, oid, if_match);
+
+        // a soft delete only flags the document, every other query skips flagged orders
+        let order_doc = if soft {
+            let find_one_and_update_options = FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build();
+
+            self.note_collection
+                .find_one_and_update(
+                    query,
+                    doc! {
+                        "$set": {"deleted": true, "updated_at": bson::DateTime::now()},
+                        "$inc": {"version": 1},
+                    },
+                    find_one_and_update_options,
+                )
+                .await
+        } else {
+            self.note_collection.find_one_and_delete(query, None).await
+        }
+        .map_err(query_error)?;
+
+        let order_doc = match order_doc {
+            Some(order_doc) => order_doc,
+            None => return Err(self.write_failed(tenant, id).await),
+        };
 
         let order_response = DeleteOrderResponse {
             status: "deleted".to_string(),
             id: id.to_string(),
+            data: OrderData {
+                order: self.doc_to_order(&order_doc),
+            },
         };
         Ok(order_response)
     }
 
+    #[instrument]
+    #[autometrics]
+    pub async fn count_customer_orders(&self, tenant: &Tenant, customer_id: &str) -> Result<u64> {
+        self.note_collection
+            .count_documents(
+                doc! {
+                    "tenant_id": tenant_condition(tenant),
+                    "customer_id": customer_id,
+                    "deleted": {"$ne": true},
+                },
+                None,
+            )
+            .await
+            .map_err(query_error)
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn delete_customer_orders(
+        &self,
+        tenant: &Tenant,
+        customer_id: &str,
+    ) -> Result<Vec<String>> {
+        let find_options = FindOptions::builder().projection(doc! {"_id": 1}).build();
+        let mut cursor = self
+            .collection
+            .find(
+                doc! {"tenant_id": tenant_condition(tenant), "customer_id": customer_id},
+                find_options,
+            )
+            .await
+            .map_err(query_error)?;
+
+        let mut ids = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            let doc = doc.map_err(query_error)?;
+            ids.push(doc.get_object_id("_id").map_err(|_| Error::MongoError)?);
+        }
+
+        self.note_collection
+            .delete_many(doc! {"_id": {"$in": ids.clone()}}, None)
+            .await
+            .map_err(query_error)?;
+
+        Ok(ids.iter().map(|id| id.to_hex()).collect())
+    }
+
+    /// An order was not written after its stock was reserved, so the reservation
+    /// goes back to `items`, what the order held before.
+    async fn restore_stock(&self, order_id: &str, items: &[OrderItemSchema]) {
+        if let Err(e) = self.products.reserve_stock(order_id, items).await {
+            tracing::error!(
+                "🔥 Could not restore the stock reserved for order {}: {:?}",
+                order_id,
+                e
+            );
+        }
+    }
+
+    /// Filter of a write on a live order of `tenant`, restricted to the
+    /// `If-Match` versions when given.
+    fn write_query(&self, tenant: &Tenant, oid: ObjectId, if_match: Option<&[i32]>) -> Document {
+        let mut query = doc! {
+            "_id": oid,
+            "tenant_id": tenant_condition(tenant),
+            "deleted": {"$ne": true},
+        };
+        if let Some(versions) = if_match {
+            query.insert("version", doc! {"$in": versions});
+        }
+        query
+    }
+
+    /// A conditional write matched no document, either the order does not exist
+    /// in the tenant or its version did not match `If-Match`.
+    async fn write_failed(&self, tenant: &Tenant, id: &str) -> Error {
+        match self.get_order(tenant, id).await {
+            Ok(_) => Error::precondition_failed("order", id),
+            Err(e) => e,
+        }
+    }
+
+    /// A transition matched no document, either like any conditional write or
+    /// because the current status does not allow it.
+    async fn transition_failed(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        status: OrderStatus,
+        if_match: Option<&[i32]>,
+    ) -> Error {
+        let order = match self.get_order(tenant, id).await {
+            Ok(order_response) => order_response.data.order,
+            Err(e) => return e,
+        };
+        if if_match.is_some_and(|versions| !versions.contains(&order.version))
+            || order.status.can_transition_to(status)
+        {
+            return Error::precondition_failed("order", id);
+        }
+        Error::invalid_transition("order", id, order.status.as_ref(), status.as_ref())
+    }
+
     #[instrument]
     #[autometrics]
     fn doc_to_order(&self, order: &OrderModel) -> OrderResponse {
-        let order_response = OrderResponse {
+        OrderResponse {
             id: order.id.to_hex(),
+            customer_id: order.customer_id.to_owned(),
             customer_name: order.customer_name.to_owned(),
             product_name: order.product_name.to_owned(),
-        };
+            items: order.items.to_owned(),
+            currency: order.currency.to_owned(),
+            total: order.total,
+            status: order.status,
+            version: order.version,
+            created_at: order.created_at(),
+            updated_at: order.updated_at(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::error::WriteError;
+
+    fn write_error(code: i32, message: &str) -> mongodb::error::Error {
+        let write_error: WriteError =
+            bson::from_document(doc! {"code": code, "errmsg": message}).unwrap();
+        ErrorKind::Write(WriteFailure::WriteError(write_error)).into()
+    }
+
+    #[test]
+    fn duplicate_key_is_conflict() {
+        let e = write_error(
+            11000,
+            r#"E11000 duplicate key error collection: rust_mongodb.notes index: product_name_1 dup key: { product_name: "apple" }"#,
+        );
 
-        order_response
+        match query_error(e) {
+            Error::Conflict { resource, key } => {
+                assert_eq!(resource, "order");
+                assert_eq!(key, r#"{ product_name: "apple" }"#);
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn io_error_is_connection_error() {
+        let e: mongodb::error::Error = ErrorKind::Io(std::sync::Arc::new(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "connection refused",
+        )))
+        .into();
+
+        assert!(matches!(query_error(e), Error::MongoConnectionError { .. }));
+    }
+
+    #[test]
+    fn other_write_error_is_query_error() {
+        let e = write_error(121, "Document failed validation");
+
+        assert!(matches!(query_error(e), Error::MongoQueryError { .. }));
+    }
+
+    #[test]
+    fn order_filter_is_scoped_to_the_tenant() {
+        let acme = Tenant::parse("acme").unwrap();
+        let filter = order_filter(&acme, &OrderFilter::default());
+        let conditions = filter.get_array("$and").unwrap();
+
+        assert!(conditions.contains(&Bson::Document(doc! {"tenant_id": "acme"})));
+    }
+
+    #[test]
+    fn default_tenant_includes_orders_without_one() {
+        assert_eq!(
+            tenant_condition(&Tenant::default()),
+            Bson::Document(doc! {"$in": ["default", Bson::Null]})
+        );
+        assert_eq!(
+            tenant_condition(&Tenant::parse("acme").unwrap()),
+            Bson::from("acme")
+        );
     }
 }
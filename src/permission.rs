@@ -0,0 +1,112 @@
+use crate::auth::Ctx;
+use crate::{Error, Result};
+use axum::extract::{FromRequestParts, MatchedPath, State};
+use axum::http::{Method, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// What a request does to a resource, derived from its method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Read,
+    Write,
+    Delete,
+}
+
+impl Action {
+    pub fn of(method: &Method) -> Self {
+        if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+            Self::Read
+        } else if method == Method::DELETE {
+            Self::Delete
+        } else {
+            Self::Write
+        }
+    }
+}
+
+/// One entry of a permission map: the scopes a caller needs, all of them, to
+/// perform `action` on `route`, or on any route when `route` is `None`.
+#[derive(Debug)]
+pub struct Permission {
+    route: Option<&'static str>,
+    action: Action,
+    scopes: &'static [&'static str],
+}
+
+impl Permission {
+    pub const fn any(action: Action, scopes: &'static [&'static str]) -> Self {
+        Self {
+            route: None,
+            action,
+            scopes,
+        }
+    }
+
+    /// `route` is the full path as declared in the router, e.g. `/api/pg/:name/orders`.
+    pub const fn on(route: &'static str, action: Action, scopes: &'static [&'static str]) -> Self {
+        Self {
+            route: Some(route),
+            action,
+            scopes,
+        }
+    }
+
+    fn matches(&self, route: Option<&str>, action: Action) -> bool {
+        self.action == action && self.route.map_or(true, |own| Some(own) == route)
+    }
+}
+
+/// The permissions of a router, the first matching entry applies. Requests no
+/// entry covers are refused, so a new route is closed until it is listed.
+pub type PermissionMap = &'static [Permission];
+
+/// The caller permissions are checked against. `Ctx` stands for the bearer of
+/// an access token, another identity only has to be extractable from the
+/// request to be plugged into `require_permission`.
+pub trait Caller {
+    fn has_scope(&self, scope: &str) -> bool;
+}
+
+impl Caller for Ctx {
+    fn has_scope(&self, scope: &str) -> bool {
+        Ctx::has_scope(self, scope)
+    }
+}
+
+/// Rejects requests whose caller lacks a scope the permission map requires,
+/// it runs after the middleware that authenticates the caller.
+pub async fn require_permission<C, B>(
+    State(permissions): State<PermissionMap>,
+    caller: C,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response>
+where
+    C: Caller + FromRequestParts<PermissionMap>,
+{
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str);
+    let action = Action::of(request.method());
+
+    let required = permissions
+        .iter()
+        .find(|permission| permission.matches(route, action))
+        .map(|permission| permission.scopes)
+        .ok_or_else(|| {
+            tracing::info!("No permission covers {:?} on {:?}", action, route);
+            Error::AuthFailMissingScope { scopes: Vec::new() }
+        })?;
+    let missing: Vec<String> = required
+        .iter()
+        .filter(|scope| !caller.has_scope(scope))
+        .map(|scope| scope.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::AuthFailMissingScope { scopes: missing });
+    }
+
+    Ok(next.run(request).await)
+}
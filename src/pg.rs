@@ -1,8 +1,20 @@
-use crate::response::{CustomerListResponse, CustomerResponse, SingleCustomerResponse};
-use crate::{model::CustomerModel, schema::CreateCustomerSchema};
+use crate::model::{
+    AddressModel, ApiKeyModel, CustomerModel, LineItem, ProductModel, RefreshTokenModel, UserModel,
+    PRICE_SCALE,
+};
+use crate::response::{
+    CustomerListResponse, CustomerResponse, Pagination, ProductListResponse, ProductResponse,
+    SingleCustomerResponse, SingleProductResponse,
+};
+use crate::schema::{
+    AddressSchema, CreateCustomerSchema, CreateProductSchema, CustomerCursor, CustomerFilter,
+    OrderItemSchema, PageRequest, Sort, TextMatch, UpdateCustomerSchema, UpdateProductSchema,
+};
+use crate::store::unknown_product;
+use crate::tenant::Tenant;
 use crate::{Error, Result};
 use sqlx::types::Uuid;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use sqlx::{postgres::PgPoolOptions, PgConnection, Pool, Postgres, QueryBuilder};
 
 #[derive(Clone, Debug)]
 pub struct PG {
@@ -42,7 +54,9 @@ impl PG {
             }
             Err(err) => {
                 tracing::error!("🔥 Failed to connect to the database: {:?}", err);
-                std::process::exit(1);
+                return Err(Error::PGConnectionError {
+                    e: (err.to_string()),
+                });
             }
         };
 
@@ -53,49 +67,77 @@ impl PG {
     #[autometrics]
     pub async fn create_customer(
         &self,
+        tenant: &Tenant,
         body: &CreateCustomerSchema,
     ) -> Result<SingleCustomerResponse> {
         let name = body.customer_name.to_owned();
         let surname = body.customer_surname.to_owned();
+        let email = body.normalized_email();
+
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
 
         let query_result = sqlx::query_as!(
             CustomerModel,
-            "INSERT INTO customer (customer_name,customer_surname) VALUES ($1, $2) RETURNING *",
+            "INSERT INTO customer (tenant_id,customer_name,customer_surname,email,phone) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+            tenant.as_str(),
             name,
             surname,
+            email,
+            body.phone,
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await
-        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        .map_err(|e| customer_write_error(e, email.as_deref()))?;
 
-        let customer_response = SingleCustomerResponse {
-            id: query_result.customer_id.to_string(),
-            name: query_result.customer_name.unwrap_or("john doe".to_string()),
-            surname: query_result.customer_surname.unwrap_or("doe".to_string()),
-            status: "success".to_string(),
-        };
+        let addresses =
+            replace_addresses(&mut tx, query_result.customer_id, &body.addresses).await?;
 
-        Ok(customer_response)
+        tx.commit()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(customer_to_single(query_result, addresses, "success"))
     }
 
     #[instrument]
     #[autometrics]
     pub async fn list_customers(
         &self,
-        limit: i64,
-        offset: i64,
-    ) -> Result<Option<CustomerListResponse>> {
-        let query_result = sqlx::query_as!(
-            CustomerModel,
-            "SELECT * FROM customer ORDER by customer_name LIMIT $1 OFFSET $2",
-            limit as i32,
-            offset as i32
-        )
-        .fetch_all(&self.pool)
-        .await
-        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        tenant: &Tenant,
+        filter: &CustomerFilter,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<CustomerListResponse> {
+        let mut query = QueryBuilder::new("SELECT * FROM customer WHERE tenant_id = ");
+        query.push_bind(tenant.as_str());
+        push_customer_filter(&mut query, filter);
+        push_customer_order_by(&mut query, sort);
+        query
+            .push(" LIMIT ")
+            .push_bind(page.limit)
+            .push(" OFFSET ")
+            .push_bind(page.offset());
 
-        tracing::info!("{:?}", query_result);
+        let query_result = query
+            .build_query_as::<CustomerModel>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM customer WHERE tenant_id = ");
+        count.push_bind(tenant.as_str());
+        push_customer_filter(&mut count, filter);
+        let total_count: i64 = count
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        tracing::debug!("Listed {} customer(s)", query_result.len());
 
         let mut json_result: Vec<CustomerResponse> = Vec::new();
         for customer in query_result {
@@ -105,105 +147,786 @@ impl PG {
         let customer_response = CustomerListResponse {
             status: "success".to_string(),
             data: json_result,
+            pagination: Pagination::new(page, total_count as u64),
         };
 
-        Ok(Some(customer_response))
+        Ok(customer_response)
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn list_customers_after(
+        &self,
+        tenant: &Tenant,
+        filter: &CustomerFilter,
+        after: Option<&CustomerCursor>,
+        limit: i64,
+    ) -> Result<Vec<CustomerResponse>> {
+        let mut query = QueryBuilder::new("SELECT * FROM customer WHERE tenant_id = ");
+        query.push_bind(tenant.as_str());
+        push_customer_filter(&mut query, filter);
+
+        // seeks past the cursor in `ORDER BY customer_name, customer_id`, which
+        // sorts NULL names last, so a row comparison alone would skip them
+        if let Some(cursor) = after {
+            let after_id =
+                Uuid::parse_str(&cursor.id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+            match &cursor.name {
+                Some(name) => {
+                    query
+                        .push(" AND (customer_name > ")
+                        .push_bind(name.to_owned())
+                        .push(" OR (customer_name = ")
+                        .push_bind(name.to_owned())
+                        .push(" AND customer_id > ")
+                        .push_bind(after_id)
+                        .push(") OR customer_name IS NULL)");
+                }
+                None => {
+                    query
+                        .push(" AND customer_name IS NULL AND customer_id > ")
+                        .push_bind(after_id);
+                }
+            }
+        }
+        query
+            .push(" ORDER BY customer_name, customer_id LIMIT ")
+            .push_bind(limit);
+
+        let query_result = query
+            .build_query_as::<CustomerModel>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        query_result
+            .iter()
+            .map(|customer| self.model_to_result(customer))
+            .collect()
     }
 
+    /// Matches whole words of the name and surname through the `tsvector` index
+    /// and fragments of them through the trigram index, see the changelog.
     #[instrument]
     #[autometrics]
-    pub async fn get_customer(&self, id: &String) -> Result<Option<SingleCustomerResponse>> {
-        let customer_id =
-            Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+    pub async fn search_customers(
+        &self,
+        tenant: &Tenant,
+        query: &str,
+        limit: i64,
+    ) -> Result<(Vec<CustomerResponse>, u64)> {
+        let pattern = format!("%{}%", escape_like(query));
 
         let query_result = sqlx::query_as!(
             CustomerModel,
-            "SELECT * FROM customer WHERE customer_id=$1",
-            customer_id,
+            r#"SELECT customer_id AS "customer_id!", tenant_id AS "tenant_id!", customer_name, customer_surname,
+                version AS "version!", created_at AS "created_at!", updated_at AS "updated_at!",
+                email, phone
+            FROM (
+                SELECT *,
+                    coalesce(customer_name, '') || ' ' || coalesce(customer_surname, '') AS document
+                FROM customer
+                WHERE tenant_id = $4
+            ) AS searchable
+            WHERE to_tsvector('simple', document) @@ plainto_tsquery('simple', $1)
+                OR document ILIKE $2
+                OR $1 <% document
+            ORDER BY greatest(
+                    ts_rank(to_tsvector('simple', document), plainto_tsquery('simple', $1)),
+                    word_similarity($1, document)
+                ) DESC,
+                customer_id
+            LIMIT $3"#,
+            query,
+            pattern,
+            limit,
+            tenant.as_str()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        let total_count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!"
+            FROM (
+                SELECT coalesce(customer_name, '') || ' ' || coalesce(customer_surname, '') AS document
+                FROM customer
+                WHERE tenant_id = $3
+            ) AS searchable
+            WHERE to_tsvector('simple', document) @@ plainto_tsquery('simple', $1)
+                OR document ILIKE $2
+                OR $1 <% document"#,
+            query,
+            pattern,
+            tenant.as_str()
         )
         .fetch_one(&self.pool)
         .await
         .map_err(|e| Error::PGError { e: (e.to_string()) })?;
 
-        let customer_response = SingleCustomerResponse {
-            id: query_result.customer_id.to_string(),
-            name: query_result.customer_name.unwrap_or("john".to_string()),
-            surname: query_result.customer_surname.unwrap_or("doe".to_string()),
-            status: "success".to_string(),
-        };
+        let customers = query_result
+            .iter()
+            .map(|customer| self.model_to_result(customer))
+            .collect::<Result<Vec<_>>>()?;
 
-        Ok(Some(customer_response))
+        Ok((customers, total_count as u64))
     }
 
     #[instrument]
     #[autometrics]
-    pub async fn delete_customer(&self, id: &String) -> Result<Option<SingleCustomerResponse>> {
+    pub async fn get_customer(&self, tenant: &Tenant, id: &str) -> Result<SingleCustomerResponse> {
         let customer_id =
             Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
 
-        let customer_info = sqlx::query_as!(
+        let query_result = sqlx::query_as!(
             CustomerModel,
-            "SELECT * FROM customer WHERE customer_id=$1",
+            "SELECT * FROM customer WHERE customer_id=$1 AND tenant_id=$2",
             customer_id,
+            tenant.as_str(),
         )
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
         .await
-        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?
+        .ok_or_else(|| Error::not_found("customer", id))?;
 
-        sqlx::query_as!(
+        let mut conn = self
+            .pool
+            .acquire()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        let addresses = fetch_addresses(&mut conn, customer_id).await?;
+
+        Ok(customer_to_single(query_result, addresses, "success"))
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn delete_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse> {
+        let customer_id =
+            Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        // the addresses go with the customer through the foreign key cascade
+        let addresses = fetch_addresses(&mut tx, customer_id).await?;
+
+        let customer_info = sqlx::query_as!(
             CustomerModel,
-            "DELETE FROM customer WHERE customer_id=$1",
+            "DELETE FROM customer WHERE customer_id=$1 AND tenant_id=$2 AND ($3::int[] IS NULL OR version = ANY($3)) RETURNING *",
             customer_id,
+            tenant.as_str(),
+            if_match,
         )
-        .execute(&self.pool)
+        .fetch_optional(&mut *tx)
         .await
         .map_err(|e| Error::PGError { e: (e.to_string()) })?;
 
-        let customer_response = SingleCustomerResponse {
-            id: customer_info.customer_id.to_string(),
-            name: customer_info.customer_name.unwrap_or("john".to_string()),
-            surname: customer_info.customer_surname.unwrap_or("doe".to_string()),
-            status: "deleted".to_string(),
+        let customer_info = match customer_info {
+            Some(customer_info) => customer_info,
+            None => return Err(self.write_failed(tenant, id).await),
         };
-        Ok(Some(customer_response))
+
+        tx.commit()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(customer_to_single(customer_info, addresses, "deleted"))
     }
 
     #[instrument]
     #[autometrics]
     pub async fn update_customer(
         &self,
-        id: &String,
+        tenant: &Tenant,
+        id: &str,
         body: &CreateCustomerSchema,
+        if_match: Option<&[i32]>,
     ) -> Result<SingleCustomerResponse> {
         let customer_id =
             Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
 
         let name = body.customer_name.to_owned();
         let surname = body.customer_surname.to_owned();
+        let email = body.normalized_email();
 
-        // ensure customer exists
-        let result = self.get_customer(&id).await?;
-        tracing::info!("{:?}", result);
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
 
         let query_result = sqlx::query_as!(
             CustomerModel,
-            "UPDATE customer SET customer_name=$1,customer_surname=$2 WHERE customer_id=$3 RETURNING *",
+            "UPDATE customer SET customer_name=$1,customer_surname=$2,email=$3,phone=$4,version=version+1,updated_at=now() WHERE customer_id=$5 AND tenant_id=$6 AND ($7::int[] IS NULL OR version = ANY($7)) RETURNING *",
             name,
             surname,
+            email,
+            body.phone,
+            customer_id,
+            tenant.as_str(),
+            if_match,
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| customer_write_error(e, email.as_deref()))?;
+
+        let query_result = match query_result {
+            Some(query_result) => query_result,
+            None => return Err(self.write_failed(tenant, id).await),
+        };
+
+        let addresses = replace_addresses(&mut tx, customer_id, &body.addresses).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(customer_to_single(query_result, addresses, "success"))
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn patch_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &UpdateCustomerSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse> {
+        let customer_id =
+            Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+
+        let email = body.normalized_email();
+
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        // a column is only written when its field is part of the patch, `Some(None)` clears it
+        let query_result = sqlx::query_as!(
+            CustomerModel,
+            "UPDATE customer SET customer_name = CASE WHEN $1 THEN $2 ELSE customer_name END, customer_surname = CASE WHEN $3 THEN $4 ELSE customer_surname END, email = CASE WHEN $5 THEN $6 ELSE email END, phone = CASE WHEN $7 THEN $8 ELSE phone END, version=version+1, updated_at=now() WHERE customer_id=$9 AND tenant_id=$10 AND ($11::int[] IS NULL OR version = ANY($11)) RETURNING *",
+            body.customer_name.is_some(),
+            body.customer_name.to_owned().flatten(),
+            body.customer_surname.is_some(),
+            body.customer_surname.to_owned().flatten(),
+            email.is_some(),
+            email.to_owned().flatten(),
+            body.phone.is_some(),
+            body.phone.to_owned().flatten(),
             customer_id,
+            tenant.as_str(),
+            if_match,
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| customer_write_error(e, email.to_owned().flatten().as_deref()))?;
+
+        let query_result = match query_result {
+            Some(query_result) => query_result,
+            None => return Err(self.write_failed(tenant, id).await),
+        };
+
+        let addresses = match &body.addresses {
+            Some(addresses) => replace_addresses(&mut tx, customer_id, addresses).await?,
+            None => fetch_addresses(&mut tx, customer_id).await?,
+        };
+
+        tx.commit()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(customer_to_single(query_result, addresses, "success"))
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn create_product(
+        &self,
+        body: &CreateProductSchema,
+    ) -> Result<SingleProductResponse> {
+        let query_result = sqlx::query_as!(
+            ProductModel,
+            "INSERT INTO product (sku,product_name,description,price,stock) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+            body.sku,
+            body.product_name,
+            body.description,
+            body.price,
+            body.stock,
         )
         .fetch_one(&self.pool)
         .await
-        .map_err(|e|Error::PGError { e: (e.to_string()) })?;
+        .map_err(|e| product_write_error(e, &body.sku))?;
 
-        let customer_response = SingleCustomerResponse {
-            id: query_result.customer_id.to_string(),
-            name: query_result.customer_name.unwrap_or("john doe".to_string()),
-            surname: query_result.customer_surname.unwrap_or("doe".to_string()),
+        Ok(SingleProductResponse {
             status: "success".to_string(),
+            product: product_to_response(query_result),
+        })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn list_products(&self, page: PageRequest) -> Result<ProductListResponse> {
+        let query_result = sqlx::query_as!(
+            ProductModel,
+            "SELECT * FROM product ORDER BY sku LIMIT $1 OFFSET $2",
+            page.limit,
+            page.offset(),
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        let total_count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM product"#)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(ProductListResponse {
+            status: "success".to_string(),
+            data: query_result.into_iter().map(product_to_response).collect(),
+            pagination: Pagination::new(page, total_count as u64),
+        })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn get_product(&self, id: &str) -> Result<SingleProductResponse> {
+        let product_id = Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+
+        let query_result = sqlx::query_as!(
+            ProductModel,
+            "SELECT * FROM product WHERE product_id=$1",
+            product_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?
+        .ok_or_else(|| Error::not_found("product", id))?;
+
+        Ok(SingleProductResponse {
+            status: "success".to_string(),
+            product: product_to_response(query_result),
+        })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn get_product_by_sku(&self, sku: &str) -> Result<ProductResponse> {
+        let query_result = sqlx::query_as!(ProductModel, "SELECT * FROM product WHERE sku=$1", sku)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?
+            .ok_or_else(|| Error::not_found("product", sku))?;
+
+        Ok(product_to_response(query_result))
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn update_product(
+        &self,
+        id: &str,
+        body: &CreateProductSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        let product_id = Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+
+        let query_result = sqlx::query_as!(
+            ProductModel,
+            "UPDATE product SET sku=$1,product_name=$2,description=$3,price=$4,stock=$5,version=version+1,updated_at=now() WHERE product_id=$6 AND ($7::int[] IS NULL OR version = ANY($7)) RETURNING *",
+            body.sku,
+            body.product_name,
+            body.description,
+            body.price,
+            body.stock,
+            product_id,
+            if_match,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| product_write_error(e, &body.sku))?;
+
+        let query_result = match query_result {
+            Some(query_result) => query_result,
+            None => return Err(self.product_write_failed(id).await),
         };
 
-        Ok(customer_response)
+        Ok(SingleProductResponse {
+            status: "success".to_string(),
+            product: product_to_response(query_result),
+        })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn patch_product(
+        &self,
+        id: &str,
+        body: &UpdateProductSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        let product_id = Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+
+        // the other columns cannot be null, so an omitted field keeps the current value
+        let query_result = sqlx::query_as!(
+            ProductModel,
+            "UPDATE product SET sku = COALESCE($1, sku), product_name = COALESCE($2, product_name), description = CASE WHEN $3 THEN $4 ELSE description END, price = COALESCE($5, price), stock = COALESCE($6, stock), version=version+1, updated_at=now() WHERE product_id=$7 AND ($8::int[] IS NULL OR version = ANY($8)) RETURNING *",
+            body.sku,
+            body.product_name,
+            body.description.is_some(),
+            body.description.to_owned().flatten(),
+            body.price,
+            body.stock,
+            product_id,
+            if_match,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| product_write_error(e, body.sku.as_deref().unwrap_or_default()))?;
+
+        let query_result = match query_result {
+            Some(query_result) => query_result,
+            None => return Err(self.product_write_failed(id).await),
+        };
+
+        Ok(SingleProductResponse {
+            status: "success".to_string(),
+            product: product_to_response(query_result),
+        })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn delete_product(
+        &self,
+        id: &str,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        let product_id = Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+
+        let query_result = sqlx::query_as!(
+            ProductModel,
+            "DELETE FROM product WHERE product_id=$1 AND ($2::int[] IS NULL OR version = ANY($2)) RETURNING *",
+            product_id,
+            if_match,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        let query_result = match query_result {
+            Some(query_result) => query_result,
+            None => return Err(self.product_write_failed(id).await),
+        };
+
+        Ok(SingleProductResponse {
+            status: "deleted".to_string(),
+            product: product_to_response(query_result),
+        })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn reserve_stock(
+        &self,
+        order_id: &str,
+        items: &[OrderItemSchema],
+    ) -> Result<Vec<LineItem>> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        restock(&mut tx, order_id).await?;
+
+        let mut line_items = Vec::new();
+        for (index, item) in items.iter().enumerate() {
+            // the quantity is validated to be at most `MAX_QUANTITY`
+            let quantity = item.quantity as i32;
+
+            // the stock check and the decrement are one statement, so concurrent
+            // orders cannot both take the last items
+            let product = sqlx::query_as!(
+                ProductModel,
+                "UPDATE product SET stock=stock-$1,version=version+1,updated_at=now() WHERE sku=$2 AND stock >= $1 RETURNING *",
+                quantity,
+                item.sku,
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+            // returning drops `tx`, which rolls back the items reserved so far
+            let product = match product {
+                Some(product) => product,
+                None => {
+                    let available =
+                        sqlx::query_scalar!("SELECT stock FROM product WHERE sku=$1", item.sku)
+                            .fetch_optional(&mut *tx)
+                            .await
+                            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+                    return Err(match available {
+                        Some(available) => Error::InsufficientStock {
+                            sku: item.sku.to_owned(),
+                            requested: item.quantity,
+                            available,
+                        },
+                        None => unknown_product(index, &item.sku),
+                    });
+                }
+            };
+
+            sqlx::query!(
+                "INSERT INTO stock_reservation (order_id,product_id,quantity) VALUES ($1, $2, $3)",
+                order_id,
+                product.product_id,
+                quantity,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+            let product = product_to_response(product);
+            line_items.push(LineItem {
+                sku: product.sku,
+                product_name: product.name,
+                quantity: item.quantity,
+                unit_price: product.price,
+            });
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(line_items)
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn release_stock(&self, order_id: &str) -> Result<u64> {
+        let mut conn = self
+            .pool
+            .acquire()
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        restock(&mut conn, order_id).await
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn settle_stock(&self, order_id: &str) -> Result<u64> {
+        let query_result =
+            sqlx::query!("DELETE FROM stock_reservation WHERE order_id=$1", order_id)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(query_result.rows_affected())
+    }
+
+    #[instrument(skip(password_hash))]
+    #[autometrics]
+    pub async fn create_user(
+        &self,
+        tenant: Option<&Tenant>,
+        username: &str,
+        password_hash: &str,
+        scope: &str,
+    ) -> Result<UserModel> {
+        sqlx::query_as!(
+            UserModel,
+            "INSERT INTO users (tenant_id,username,password_hash,scope) VALUES ($1, $2, $3, $4) RETURNING *",
+            tenant.map(Tenant::as_str),
+            username,
+            password_hash,
+            scope,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db_error) if db_error.is_unique_violation() => Error::Conflict {
+                resource: "user".to_string(),
+                key: format!("{{ username: \"{}\" }}", username),
+            },
+            _ => Error::PGError { e: (e.to_string()) },
+        })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn get_user(&self, id: Uuid) -> Result<Option<UserModel>> {
+        sqlx::query_as!(UserModel, "SELECT * FROM users WHERE user_id=$1", id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn get_user_by_username(&self, username: &str) -> Result<Option<UserModel>> {
+        sqlx::query_as!(UserModel, "SELECT * FROM users WHERE username=$1", username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::PGError { e: (e.to_string()) })
+    }
+
+    #[instrument(skip(token))]
+    #[autometrics]
+    pub async fn insert_refresh_token(&self, token: &RefreshTokenModel) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO refresh_token (token_hash,user_id,family_id,expires_at) VALUES ($1, $2, $3, $4)",
+            token.token_hash,
+            token.user_id,
+            token.family_id,
+            token.expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(token_hash))]
+    #[autometrics]
+    pub async fn consume_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshTokenModel>> {
+        // revoking is the check, so two concurrent refreshes cannot both use a token
+        let token = sqlx::query_as!(
+            RefreshTokenModel,
+            "UPDATE refresh_token SET revoked_at=now() WHERE token_hash=$1 AND revoked_at IS NULL AND expires_at > now() RETURNING *",
+            token_hash,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        if token.is_some() {
+            return Ok(token);
+        }
+
+        let query_result = sqlx::query!(
+            "UPDATE refresh_token SET revoked_at=now() WHERE revoked_at IS NULL AND family_id IN (SELECT family_id FROM refresh_token WHERE token_hash=$1 AND revoked_at IS NOT NULL)",
+            token_hash,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        if query_result.rows_affected() > 0 {
+            tracing::warn!("A revoked refresh token was reused, revoked its family");
+        }
+
+        Ok(None)
+    }
+
+    #[instrument(skip(token_hash))]
+    #[autometrics]
+    pub async fn revoke_refresh_family(&self, token_hash: &str) -> Result<u64> {
+        let query_result = sqlx::query!(
+            "UPDATE refresh_token SET revoked_at=now() WHERE revoked_at IS NULL AND family_id IN (SELECT family_id FROM refresh_token WHERE token_hash=$1)",
+            token_hash,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(query_result.rows_affected())
+    }
+
+    #[instrument(skip(key))]
+    #[autometrics]
+    pub async fn insert_api_key(&self, key: &ApiKeyModel) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO api_key (api_key_id,name,owner,key_hash,prefix,scope,tenant_id,expires_at,created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            key.api_key_id,
+            key.name,
+            key.owner,
+            key.key_hash,
+            key.prefix,
+            key.scope,
+            key.tenant_id,
+            key.expires_at,
+            key.created_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+        Ok(())
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn list_api_keys(&self, tenant: &Tenant) -> Result<Vec<ApiKeyModel>> {
+        sqlx::query_as!(
+            ApiKeyModel,
+            "SELECT * FROM api_key WHERE tenant_id=$1 ORDER BY created_at, api_key_id",
+            tenant.as_str(),
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })
+    }
+
+    #[instrument]
+    #[autometrics]
+    pub async fn revoke_api_key(&self, tenant: &Tenant, id: &str) -> Result<ApiKeyModel> {
+        let api_key_id = Uuid::parse_str(id).map_err(|e| Error::SqlxUuid { e: (e.to_string()) })?;
+
+        sqlx::query_as!(
+            ApiKeyModel,
+            "UPDATE api_key SET revoked_at=COALESCE(revoked_at, now()) WHERE api_key_id=$1 AND tenant_id=$2 RETURNING *",
+            api_key_id,
+            tenant.as_str(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?
+        .ok_or_else(|| Error::not_found("api key", id))
+    }
+
+    #[instrument(skip(key_hash))]
+    #[autometrics]
+    pub async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyModel>> {
+        sqlx::query_as!(
+            ApiKeyModel,
+            "UPDATE api_key SET last_used_at=now() WHERE key_hash=$1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > now()) RETURNING *",
+            key_hash,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })
+    }
+
+    /// Like `write_failed`, for products.
+    async fn product_write_failed(&self, id: &str) -> Error {
+        match self.get_product(id).await {
+            Ok(_) => Error::precondition_failed("product", id),
+            Err(e) => e,
+        }
+    }
+
+    /// A conditional write matched no row, either the customer does not exist
+    /// in the tenant or its version did not match `If-Match`.
+    async fn write_failed(&self, tenant: &Tenant, id: &str) -> Error {
+        match self.get_customer(tenant, id).await {
+            Ok(_) => Error::precondition_failed("customer", id),
+            Err(e) => e,
+        }
     }
 
     #[instrument]
@@ -211,16 +934,200 @@ impl PG {
     fn model_to_result(&self, customer: &CustomerModel) -> Result<CustomerResponse> {
         let customer_response = CustomerResponse {
             id: customer.customer_id.to_owned().to_string(),
-            name: customer
-                .customer_name
-                .to_owned()
-                .ok_or(Error::CustomerError)?,
-            surname: customer
-                .customer_surname
-                .to_owned()
-                .ok_or(Error::CustomerError)?,
+            name: customer.customer_name.to_owned(),
+            surname: customer.customer_surname.to_owned(),
+            email: customer.email.to_owned(),
+            phone: customer.phone.to_owned(),
+            version: customer.version,
+            created_at: customer.created_at,
+            updated_at: customer.updated_at,
         };
 
         Ok(customer_response)
     }
 }
+
+fn customer_to_single(
+    customer: CustomerModel,
+    addresses: Vec<AddressModel>,
+    status: &str,
+) -> SingleCustomerResponse {
+    SingleCustomerResponse {
+        id: customer.customer_id.to_string(),
+        name: customer.customer_name,
+        surname: customer.customer_surname,
+        email: customer.email,
+        phone: customer.phone,
+        addresses: addresses.into_iter().map(Into::into).collect(),
+        version: customer.version,
+        created_at: customer.created_at,
+        updated_at: customer.updated_at,
+        status: status.to_string(),
+        orders: None,
+    }
+}
+
+async fn fetch_addresses(conn: &mut PgConnection, customer_id: Uuid) -> Result<Vec<AddressModel>> {
+    sqlx::query_as!(
+        AddressModel,
+        "SELECT * FROM customer_address WHERE customer_id=$1 ORDER BY position",
+        customer_id,
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| Error::PGError { e: (e.to_string()) })
+}
+
+/// Swaps every address of a customer for `addresses`, call it in the
+/// transaction that writes the customer.
+async fn replace_addresses(
+    conn: &mut PgConnection,
+    customer_id: Uuid,
+    addresses: &[AddressSchema],
+) -> Result<Vec<AddressModel>> {
+    sqlx::query!(
+        "DELETE FROM customer_address WHERE customer_id=$1",
+        customer_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+    let mut stored = Vec::new();
+    for (address, position) in AddressSchema::with_defaults(addresses).into_iter().zip(0..) {
+        let address = sqlx::query_as!(
+            AddressModel,
+            "INSERT INTO customer_address (customer_id,position,kind,line1,line2,city,postal_code,country,is_default) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *",
+            customer_id,
+            position,
+            address.kind.as_ref(),
+            address.line1,
+            address.line2,
+            address.city,
+            address.postal_code,
+            address.country,
+            address.is_default,
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+        stored.push(address);
+    }
+
+    Ok(stored)
+}
+
+/// An email that is already taken is a conflict rather than a database error.
+fn customer_write_error(e: sqlx::Error, email: Option<&str>) -> Error {
+    match (&e, email) {
+        (sqlx::Error::Database(db_error), Some(email)) if db_error.is_unique_violation() => {
+            Error::Conflict {
+                resource: "customer".to_string(),
+                key: format!("{{ email: \"{}\" }}", email),
+            }
+        }
+        _ => Error::PGError { e: (e.to_string()) },
+    }
+}
+
+fn product_to_response(product: ProductModel) -> ProductResponse {
+    // NUMERIC is decoded in base 10000 digits, `0.35` comes back as `0.3500`
+    let mut price = product.price;
+    price.rescale(PRICE_SCALE);
+
+    ProductResponse {
+        id: product.product_id.to_string(),
+        sku: product.sku,
+        name: product.product_name,
+        description: product.description,
+        price,
+        stock: product.stock,
+        version: product.version,
+        created_at: product.created_at,
+        updated_at: product.updated_at,
+    }
+}
+
+/// Puts the reservation of an order back in stock and drops it, returns how
+/// many products it held.
+async fn restock(conn: &mut PgConnection, order_id: &str) -> Result<u64> {
+    let query_result = sqlx::query!(
+        "WITH released AS (DELETE FROM stock_reservation WHERE order_id=$1 RETURNING product_id, quantity) UPDATE product SET stock=product.stock+released.quantity,version=version+1,updated_at=now() FROM released WHERE product.product_id=released.product_id",
+        order_id,
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| Error::PGError { e: (e.to_string()) })?;
+
+    Ok(query_result.rows_affected())
+}
+
+/// A SKU that is already taken is a conflict rather than a database error.
+fn product_write_error(e: sqlx::Error, sku: &str) -> Error {
+    match &e {
+        sqlx::Error::Database(db_error) if db_error.is_unique_violation() => Error::Conflict {
+            resource: "product".to_string(),
+            key: format!("{{ sku: \"{}\" }}", sku),
+        },
+        _ => Error::PGError { e: (e.to_string()) },
+    }
+}
+
+/// Appends the conditions of `filter` to a query that already has a `WHERE`.
+/// The columns come from the filter options and every value is bound.
+fn push_customer_filter(query: &mut QueryBuilder<'_, Postgres>, filter: &CustomerFilter) {
+    for range in &filter.time {
+        if let Some(after) = range.after {
+            query
+                .push(" AND ")
+                .push(range.field)
+                .push(" >= ")
+                .push_bind(after);
+        }
+        if let Some(before) = range.before {
+            query
+                .push(" AND ")
+                .push(range.field)
+                .push(" < ")
+                .push_bind(before);
+        }
+    }
+    for condition in &filter.text {
+        query.push(" AND ").push(condition.field);
+        match condition.op {
+            TextMatch::Exact => query.push(" = ").push_bind(condition.value.to_owned()),
+            TextMatch::Prefix => query
+                .push(" LIKE ")
+                .push_bind(format!("{}%", escape_like(&condition.value))),
+            TextMatch::Contains => query
+                .push(" ILIKE ")
+                .push_bind(format!("%{}%", escape_like(&condition.value))),
+        };
+    }
+}
+
+/// Appends `ORDER BY` for `sort` with the id breaking ties. The columns come
+/// from `CUSTOMER_SORT_FIELDS`, Postgres sorts NULLs as the largest values.
+fn push_customer_order_by(query: &mut QueryBuilder<'_, Postgres>, sort: &Sort) {
+    query.push(" ORDER BY ");
+    for key in &sort.keys {
+        query.push(match key.field {
+            "id" => "customer_id",
+            field => field,
+        });
+        query.push(if key.descending { " DESC, " } else { ", " });
+    }
+    query.push("customer_id");
+}
+
+/// Escapes the LIKE wildcards with the default escape character.
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -1,3 +1,7 @@
+use crate::model::{AddressModel, ApiKeyModel, LineItem, OrderStatus};
+use crate::schema::PageRequest;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -10,8 +14,16 @@ pub struct GenericResponse {
 #[derive(Serialize, Debug)]
 pub struct OrderResponse {
     pub id: String,
+    pub customer_id: String,
     pub customer_name: String,
     pub product_name: String,
+    pub items: Vec<LineItem>,
+    pub currency: Option<String>,
+    pub total: Decimal,
+    pub status: OrderStatus,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Serialize, Debug)]
@@ -29,6 +41,35 @@ pub struct SingleOrderResponse {
 pub struct DeleteOrderResponse {
     pub status: String,
     pub id: String,
+    pub data: OrderData,
+}
+
+/// Position of a list response, serialized next to the items.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum Pagination {
+    Page {
+        page: i64,
+        limit: i64,
+        total_count: u64,
+        total_pages: u64,
+    },
+    /// `next_cursor` is `null` on the last page.
+    Cursor {
+        limit: i64,
+        next_cursor: Option<String>,
+    },
+}
+
+impl Pagination {
+    pub fn new(request: PageRequest, total_count: u64) -> Self {
+        Self::Page {
+            page: request.page,
+            limit: request.limit,
+            total_count,
+            total_pages: (total_count + request.limit as u64 - 1) / request.limit as u64,
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -36,25 +77,189 @@ pub struct OrderListResponse {
     pub status: String,
     pub results: usize,
     pub orders: Vec<OrderResponse>,
+    #[serde(flatten)]
+    pub pagination: Pagination,
 }
 
 #[derive(Serialize, Debug)]
 pub struct CustomerResponse {
     pub id: String,
-    pub name: String,
-    pub surname: String,
+    pub name: Option<String>,
+    pub surname: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Serialize, Debug)]
 pub struct CustomerListResponse {
     pub status: String,
     pub data: Vec<CustomerResponse>,
+    #[serde(flatten)]
+    pub pagination: Pagination,
 }
 
 #[derive(Serialize, Debug)]
 pub struct SingleCustomerResponse {
     pub status: String,
+    pub id: String,
+    pub name: Option<String>,
+    pub surname: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub addresses: Vec<AddressResponse>,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Only set when the orders are requested with `?expand=orders`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orders: Option<Vec<OrderResponse>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AddressResponse {
+    pub id: String,
+    pub kind: String,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub postal_code: String,
+    pub country: String,
+    pub is_default: bool,
+}
+
+impl From<AddressModel> for AddressResponse {
+    fn from(address: AddressModel) -> Self {
+        Self {
+            id: address.address_id.to_string(),
+            kind: address.kind,
+            line1: address.line1,
+            line2: address.line2,
+            city: address.city,
+            postal_code: address.postal_code,
+            country: address.country,
+            is_default: address.is_default,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProductResponse {
+    pub id: String,
+    pub sku: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub price: Decimal,
+    pub stock: i32,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SingleProductResponse {
+    pub status: String,
+    #[serde(flatten)]
+    pub product: ProductResponse,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProductListResponse {
+    pub status: String,
+    pub data: Vec<ProductResponse>,
+    #[serde(flatten)]
+    pub pagination: Pagination,
+}
+
+/// A search hit, serialized as `{"type": "customer", "data": {...}, "score": ...}`.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum SearchResource {
+    Customer(CustomerResponse),
+    Order(OrderResponse),
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub resource: SearchResource,
+    /// Higher is more relevant, only comparable within one search.
+    pub score: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResponse {
+    pub status: String,
+    pub results: Vec<SearchHit>,
+    #[serde(flatten)]
+    pub pagination: Pagination,
+}
+
+/// Tokens handed out by `/api/auth/login` and `/api/auth/refresh`, shaped like
+/// an OAuth 2.0 token response. Lifetimes are in seconds.
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub status: String,
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    pub refresh_token: String,
+    pub refresh_expires_in: u64,
+}
+
+/// An API key as it is listed, the key itself is only returned once when it
+/// is minted.
+#[derive(Serialize, Debug)]
+pub struct ApiKeyResponse {
     pub id: String,
     pub name: String,
-    pub surname: String,
+    pub owner: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub tenant: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<ApiKeyModel> for ApiKeyResponse {
+    fn from(key: ApiKeyModel) -> Self {
+        Self {
+            id: key.api_key_id.to_string(),
+            name: key.name,
+            owner: key.owner,
+            prefix: key.prefix,
+            scopes: key.scope.split_whitespace().map(str::to_string).collect(),
+            tenant: key.tenant_id,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+            revoked_at: key.revoked_at,
+            created_at: key.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct SingleApiKeyResponse {
+    pub status: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKeyResponse {
+    pub status: String,
+    /// The plaintext key, it cannot be retrieved again.
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ApiKeyListResponse {
+    pub status: String,
+    pub data: Vec<ApiKeyResponse>,
 }
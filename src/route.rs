@@ -1,30 +1,146 @@
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 
+use crate::auth::{require_auth, Ctx, Tokens, API_KEY_HEADER};
+use crate::cursor::CursorSigner;
+use crate::error::ProblemDetails;
+use crate::handler::*;
+use crate::permission::{require_permission, Action, Permission, PermissionMap};
+use crate::store::{
+    CustomerDeletePolicy, CustomerStore, DynCustomerStore, DynOrderStore, DynProductStore,
+    DynUserStore, OrderStore, ProductStore, UserStore,
+};
+use crate::tenant::{resolve_tenant, TenantResolver, TENANT_HEADER};
 use crate::Error;
-use crate::{handler::*, mongo::MONGO, pg::PG};
 
+use axum::body::Body;
 use axum::http::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-    HeaderValue, Method,
+    header::{
+        ACCEPT, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, WWW_AUTHENTICATE,
+    },
+    HeaderName, HeaderValue, Method, Request, StatusCode, Uri,
 };
 use axum::response::{IntoResponse, Response};
-use axum::{middleware, Json};
-use serde_json::json;
+use axum::{middleware, Extension, Json};
+use axum_macros::FromRef;
+use std::sync::Arc;
 use tower_http::cors::CorsLayer;
+use tower_http::request_id::{
+    MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer,
+};
 use tower_http::trace::TraceLayer;
 
-pub fn create_router(pg: PG, mongo: MONGO) -> Router {
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const CUSTOMER_PERMISSIONS: PermissionMap = &[
+    Permission::on(
+        "/api/pg/:name/orders",
+        Action::Read,
+        &["customers:read", "orders:read"],
+    ),
+    Permission::any(Action::Read, &["customers:read"]),
+    Permission::any(Action::Write, &["customers:write"]),
+    Permission::any(Action::Delete, &["customers:delete"]),
+];
+
+const ORDER_PERMISSIONS: PermissionMap = &[
+    Permission::any(Action::Read, &["orders:read"]),
+    Permission::any(Action::Write, &["orders:write"]),
+    Permission::any(Action::Delete, &["orders:delete"]),
+];
+
+const PRODUCT_PERMISSIONS: PermissionMap = &[
+    Permission::any(Action::Read, &["products:read"]),
+    Permission::any(Action::Write, &["products:write"]),
+    Permission::any(Action::Delete, &["products:delete"]),
+];
+
+const ADMIN_PERMISSIONS: PermissionMap = &[
+    Permission::any(Action::Read, &["admin"]),
+    Permission::any(Action::Write, &["admin"]),
+    Permission::any(Action::Delete, &["admin"]),
+];
+
+/// Search returns both customers and orders.
+const SEARCH_PERMISSIONS: PermissionMap = &[Permission::any(
+    Action::Read,
+    &["customers:read", "orders:read"],
+)];
+
+/// State shared by the handlers, each one extracts only the parts it needs.
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub customers: DynCustomerStore,
+    pub orders: DynOrderStore,
+    pub products: DynProductStore,
+    pub users: DynUserStore,
+    pub on_customer_delete: CustomerDeletePolicy,
+    pub cursors: CursorSigner,
+    pub tokens: Tokens,
+    pub tenants: TenantResolver,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_router<C, O, P, U>(
+    customers: C,
+    orders: O,
+    products: P,
+    users: U,
+    on_customer_delete: CustomerDeletePolicy,
+    cursors: CursorSigner,
+    tokens: Tokens,
+    tenants: TenantResolver,
+) -> Router
+where
+    C: CustomerStore + 'static,
+    O: OrderStore + 'static,
+    P: ProductStore + 'static,
+    U: UserStore + 'static,
+{
+    let state = AppState {
+        customers: Arc::new(customers),
+        orders: Arc::new(orders),
+        products: Arc::new(products),
+        users: Arc::new(users),
+        on_customer_delete,
+        cursors,
+        tokens,
+        tenants,
+    };
+
     let cors = CorsLayer::new()
         .allow_origin("http://localhost:8000".parse::<HeaderValue>().unwrap())
-        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
         .allow_credentials(true)
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
+        .allow_headers([
+            AUTHORIZATION,
+            HeaderName::from_static(API_KEY_HEADER),
+            HeaderName::from_static(TENANT_HEADER),
+            ACCEPT,
+            CONTENT_TYPE,
+            IF_MATCH,
+            IF_NONE_MATCH,
+        ])
+        .expose_headers([HeaderName::from_static(REQUEST_ID_HEADER), ETAG]);
 
     Router::new()
         .route("/api/healthchecker", get(health_checker_handler))
+        .nest(
+            "/api/auth",
+            Router::new()
+                .route("/login", post(login_handler))
+                .route("/refresh", post(refresh_handler))
+                .route("/logout", post(logout_handler))
+                .with_state(state.clone()),
+        )
         .nest(
             "/api/pg",
             Router::new()
@@ -36,9 +152,56 @@ pub fn create_router(pg: PG, mongo: MONGO) -> Router {
                     "/:name",
                     get(get_customer_handler)
                         .delete(delete_customer_handler)
-                        .patch(update_customer_handler),
+                        .patch(patch_customer_handler)
+                        .put(update_customer_handler),
                 )
-                .with_state(pg),
+                .route("/:name/orders", get(list_customer_orders_handler))
+                .route_layer(middleware::from_fn_with_state(
+                    CUSTOMER_PERMISSIONS,
+                    require_permission::<Ctx, _>,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    resolve_tenant,
+                ))
+                // added last so that it runs first, the tenant is resolved and
+                // scopes are checked once the caller is known
+                .route_layer(middleware::from_fn_with_state(state.clone(), require_auth))
+                .with_state(state.clone()),
+        )
+        .nest(
+            "/api/products",
+            Router::new()
+                .route("/", post(create_product_handler).get(list_product_handler))
+                .route(
+                    "/:id",
+                    get(get_product_handler)
+                        .patch(patch_product_handler)
+                        .put(update_product_handler)
+                        .delete(delete_product_handler),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    PRODUCT_PERMISSIONS,
+                    require_permission::<Ctx, _>,
+                ))
+                // the catalog is shared by every tenant, so no tenant is resolved
+                .route_layer(middleware::from_fn_with_state(state.clone(), require_auth))
+                .with_state(state.clone()),
+        )
+        .nest(
+            "/api/search",
+            Router::new()
+                .route("/", get(search_handler))
+                .route_layer(middleware::from_fn_with_state(
+                    SEARCH_PERMISSIONS,
+                    require_permission::<Ctx, _>,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    resolve_tenant,
+                ))
+                .route_layer(middleware::from_fn_with_state(state.clone(), require_auth))
+                .with_state(state.clone()),
         )
         .nest(
             "/api/mongo",
@@ -47,19 +210,75 @@ pub fn create_router(pg: PG, mongo: MONGO) -> Router {
                 .route(
                     "/:id",
                     get(get_order_handler)
-                        .patch(update_order_handler)
+                        .patch(patch_order_handler)
+                        .put(update_order_handler)
                         .delete(delete_order_handler),
                 )
-                .with_state(mongo),
+                .route("/:id/transition", post(transition_order_handler))
+                .route_layer(middleware::from_fn_with_state(
+                    ORDER_PERMISSIONS,
+                    require_permission::<Ctx, _>,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    resolve_tenant,
+                ))
+                .route_layer(middleware::from_fn_with_state(state.clone(), require_auth))
+                .with_state(state.clone()),
+        )
+        .nest(
+            "/api/admin",
+            Router::new()
+                .route(
+                    "/api-keys",
+                    post(create_api_key_handler).get(list_api_key_handler),
+                )
+                .route("/api-keys/:id", delete(revoke_api_key_handler))
+                .route_layer(middleware::from_fn_with_state(
+                    ADMIN_PERMISSIONS,
+                    require_permission::<Ctx, _>,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    resolve_tenant,
+                ))
+                .route_layer(middleware::from_fn_with_state(state.clone(), require_auth))
+                .with_state(state),
         )
         .layer(cors)
         .layer(middleware::map_response(main_response_mapper))
-        .layer(TraceLayer::new_for_http())
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
+                let request_id = request
+                    .extensions()
+                    .get::<RequestId>()
+                    .and_then(|id| id.header_value().to_str().ok())
+                    .unwrap_or_default();
+                tracing::info_span!(
+                    "request",
+                    request_id,
+                    method = %request.method(),
+                    uri = %request.uri(),
+                )
+            }),
+        )
+        // the request id is set outside of the trace layer so that it is part of the request span
+        .layer(PropagateRequestIdLayer::new(HeaderName::from_static(
+            REQUEST_ID_HEADER,
+        )))
+        .layer(SetRequestIdLayer::new(
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            MakeRequestUuid,
+        ))
         .fallback(handler_404)
 }
 
-#[allow(unused_variables)]
-async fn main_response_mapper(req_method: Method, res: Response) -> Response {
+async fn main_response_mapper(
+    uri: Uri,
+    req_method: Method,
+    request_id: Option<Extension<RequestId>>,
+    res: Response,
+) -> Response {
     tracing::info!("->> {:<12} - main_response_mapper", "RES_MAPPER");
 
     // -- Get the eventual response error.
@@ -70,22 +289,41 @@ async fn main_response_mapper(req_method: Method, res: Response) -> Response {
     let error_response = client_status_error
         .as_ref()
         .map(|(status_code, client_error)| {
-            let client_error_body = json!({
-                "error": {
-                    "type": client_error.as_ref(),
-                }
-            });
+            let request_id = request_id
+                .as_ref()
+                .and_then(|Extension(id)| id.header_value().to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let problem = ProblemDetails::new(
+                *status_code,
+                client_error,
+                uri.path().to_string(),
+                request_id,
+            );
 
-            tracing::error!("    ->> client_error_body: {client_error_body}");
+            tracing::error!(
+                "    ->> {} {} - request_id: {} - service_error: {:?} - client_error: {:?}",
+                req_method,
+                uri,
+                problem.request_id,
+                service_error,
+                client_error
+            );
 
-            // Build the new response from the client_error_body
-            (*status_code, Json(client_error_body)).into_response()
+            // Build the new response from the problem details
+            let mut response = (
+                *status_code,
+                [(CONTENT_TYPE, "application/problem+json")],
+                Json(problem),
+            )
+                .into_response();
+            if *status_code == StatusCode::UNAUTHORIZED {
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            response
         });
 
-    // Build and log the server log line.
-    // let client_error = client_status_error.unzip().1;
-    // tracing::error!("Method: {:?}, client error: {:?}", req_method, client_error);
-    // TODO: Need to handler if log_request fail (but should not fail request)
-
     error_response.unwrap_or(res)
 }
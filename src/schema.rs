@@ -1,24 +1,1173 @@
-use serde::{Deserialize, Serialize};
+use crate::cursor::CursorKey;
+use crate::model::{AddressKind, OrderStatus, PRICE_SCALE};
+use crate::validation::{validate_field, Charset, FieldError, FieldRules, Rule, Validate};
+use crate::Error;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::convert::TryFrom;
 
+const ID_RULES: &[Rule] = &[Rule::Required, Rule::NotBlank];
+
+const NAME_RULES: &[Rule] = &[
+    Rule::Required,
+    Rule::NotBlank,
+    Rule::MaxLength(100),
+    Rule::Charset(Charset::Name),
+];
+
+const PRODUCT_RULES: &[Rule] = &[
+    Rule::Required,
+    Rule::NotBlank,
+    Rule::MaxLength(200),
+    Rule::Charset(Charset::Text),
+];
+
+const CURRENCY_RULES: &[Rule] = &[Rule::Required, Rule::NotBlank];
+
+const SKU_RULES: &[Rule] = &[
+    Rule::Required,
+    Rule::NotBlank,
+    Rule::MaxLength(64),
+    Rule::Charset(Charset::Sku),
+];
+
+// descriptions are free text, they are only limited in length
+const DESCRIPTION_RULES: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(2000)];
+
+// contact details are optional and `null` clears them in a PATCH, the format
+// of an email is checked by `check_email`
+const EMAIL_RULES: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(254)];
+
+const PHONE_RULES: &[Rule] = &[
+    Rule::NotBlank,
+    Rule::MaxLength(32),
+    Rule::Charset(Charset::Phone),
+];
+
+const ADDRESS_LINE_RULES: &[Rule] = &[
+    Rule::NotBlank,
+    Rule::MaxLength(200),
+    Rule::Charset(Charset::Text),
+];
+
+const CITY_RULES: &[Rule] = &[
+    Rule::NotBlank,
+    Rule::MaxLength(100),
+    Rule::Charset(Charset::Text),
+];
+
+const POSTAL_CODE_RULES: &[Rule] = &[
+    Rule::NotBlank,
+    Rule::MaxLength(16),
+    Rule::Charset(Charset::Text),
+];
+
+// PATCH bodies follow JSON Merge Patch (RFC 7396): omitted fields are left
+// untouched and `null` clears a nullable field.
+const NULLABLE_NAME_PATCH_RULES: &[Rule] = &[
+    Rule::NotBlank,
+    Rule::MaxLength(100),
+    Rule::Charset(Charset::Name),
+];
+
+const ID_PATCH_RULES: &[Rule] = &[Rule::NotNull, Rule::NotBlank];
+
+const PRODUCT_PATCH_RULES: &[Rule] = &[
+    Rule::NotNull,
+    Rule::NotBlank,
+    Rule::MaxLength(200),
+    Rule::Charset(Charset::Text),
+];
+
+const SKU_PATCH_RULES: &[Rule] = &[
+    Rule::NotNull,
+    Rule::NotBlank,
+    Rule::MaxLength(64),
+    Rule::Charset(Charset::Sku),
+];
+
+/// Distinguishes an omitted field (`None`) from an explicit `null` (`Some(None)`).
+fn deserialize_patch_field<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 10;
+pub const MAX_PAGE_LIMIT: i64 = 100;
+
+/// `?page=` starts at 1, `?limit=` defaults to `DEFAULT_PAGE_LIMIT` and may not
+/// exceed `MAX_PAGE_LIMIT`. Passing `?cursor=` (empty for the first page)
+/// switches a list to keyset pagination instead of pages.
 #[derive(Debug, Deserialize, Default)]
 pub struct FilterOptions {
-    pub page: Option<usize>,
-    pub limit: Option<usize>,
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ParamOptions {
+impl FilterOptions {
+    pub fn limit(&self) -> crate::Result<i64> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+            return Err(Error::invalid_param(
+                "limit",
+                format!("must be between 1 and {}", MAX_PAGE_LIMIT),
+            ));
+        }
+
+        Ok(limit)
+    }
+
+    pub fn page_request(&self) -> crate::Result<PageRequest> {
+        let page = self.page.unwrap_or(1);
+        if page < 1 {
+            return Err(Error::invalid_param("page", "must be at least 1"));
+        }
+
+        Ok(PageRequest {
+            page,
+            limit: self.limit()?,
+        })
+    }
+
+    /// The cursor and limit of a keyset page, `None` in page mode.
+    pub fn cursor_request(&self) -> crate::Result<Option<(&str, i64)>> {
+        let cursor = match &self.cursor {
+            Some(cursor) => cursor,
+            None => return Ok(None),
+        };
+        if self.page.is_some() {
+            return Err(Error::invalid_param(
+                "page",
+                "cannot be combined with cursor",
+            ));
+        }
+
+        Ok(Some((cursor, self.limit()?)))
+    }
+}
+
+/// Sort key of the last customer of a keyset page, customers are sorted by
+/// name with the id breaking ties.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CustomerCursor {
+    pub name: Option<String>,
+    pub id: String,
+}
+
+impl CursorKey for CustomerCursor {
+    const LIST: &'static str = "customers";
+}
+
+/// Sort key of the last order of a keyset page, orders are sorted by id.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OrderCursor {
     pub id: String,
 }
 
+impl CursorKey for OrderCursor {
+    const LIST: &'static str = "orders";
+}
+
+/// A validated page of a list, see `FilterOptions::page_request`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageRequest {
+    pub page: i64,
+    pub limit: i64,
+}
+
+impl PageRequest {
+    /// Number of items on the pages before this one.
+    pub fn offset(&self) -> i64 {
+        (self.page - 1).saturating_mul(self.limit)
+    }
+}
+
+/// Longest value a list filter accepts, the same as the fields it filters.
+const MAX_FILTER_LENGTH: usize = 100;
+
+/// How a list filter compares a field with the requested value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextMatch {
+    /// `?field=value`, case-sensitive equality.
+    Exact,
+    /// `?field_prefix=value`, case-sensitive.
+    Prefix,
+    /// `?field_contains=value`, case-insensitive.
+    Contains,
+}
+
+/// One condition of a list filter. `field` is taken from the filter options,
+/// never from the request, so the stores can use it as a column name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextFilter {
+    pub field: &'static str,
+    pub op: TextMatch,
+    pub value: String,
+}
+
+impl TextFilter {
+    /// Whether a field value passes the filter, a missing value never does.
+    pub fn matches(&self, candidate: Option<&str>) -> bool {
+        let candidate = match candidate {
+            Some(candidate) => candidate,
+            None => return false,
+        };
+
+        match self.op {
+            TextMatch::Exact => candidate == self.value,
+            TextMatch::Prefix => candidate.starts_with(&self.value),
+            TextMatch::Contains => candidate
+                .to_lowercase()
+                .contains(&self.value.to_lowercase()),
+        }
+    }
+}
+
+/// Builds the text filters of a list from its query parameters, empty values
+/// are ignored.
+fn text_filters(
+    params: &[(&'static str, &'static str, TextMatch, &Option<String>)],
+) -> crate::Result<Vec<TextFilter>> {
+    let mut filters = Vec::new();
+
+    for (param, field, op, value) in params {
+        let value = match value.as_deref() {
+            Some(value) if !value.is_empty() => value,
+            _ => continue,
+        };
+        if value.chars().count() > MAX_FILTER_LENGTH {
+            return Err(Error::invalid_param(
+                param,
+                format!("must be at most {} characters long", MAX_FILTER_LENGTH),
+            ));
+        }
+
+        filters.push(TextFilter {
+            field,
+            op: *op,
+            value: value.to_string(),
+        });
+    }
+
+    Ok(filters)
+}
+
+/// Bounds on a timestamp field, `after` is inclusive and `before` exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeRange {
+    pub field: &'static str,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    /// The range of `?{field}_after=` and `?{field}_before=`, if either is set.
+    fn of(
+        field: &'static str,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+    ) -> Option<Self> {
+        (after.is_some() || before.is_some()).then_some(Self {
+            field,
+            after,
+            before,
+        })
+    }
+
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.after.map_or(true, |after| time >= after)
+            && self.before.map_or(true, |before| time < before)
+    }
+}
+
+/// Filters of `GET /api/pg`, see `TextMatch` for the text parameters and
+/// `TimeRange` for the `_after` and `_before` RFC 3339 timestamps.
+#[derive(Debug, Deserialize, Default)]
+pub struct CustomerFilterOptions {
+    /// Compared with the lowercased email, so the lookup is case-insensitive.
+    pub email: Option<String>,
+    pub customer_name: Option<String>,
+    pub customer_name_prefix: Option<String>,
+    pub customer_name_contains: Option<String>,
+    pub customer_surname: Option<String>,
+    pub customer_surname_prefix: Option<String>,
+    pub customer_surname_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+}
+
+impl CustomerFilterOptions {
+    pub fn filter(&self) -> crate::Result<CustomerFilter> {
+        let email = self.email.as_deref().map(str::to_lowercase);
+
+        Ok(CustomerFilter {
+            text: text_filters(&[
+                ("email", "email", TextMatch::Exact, &email),
+                (
+                    "customer_name",
+                    "customer_name",
+                    TextMatch::Exact,
+                    &self.customer_name,
+                ),
+                (
+                    "customer_name_prefix",
+                    "customer_name",
+                    TextMatch::Prefix,
+                    &self.customer_name_prefix,
+                ),
+                (
+                    "customer_name_contains",
+                    "customer_name",
+                    TextMatch::Contains,
+                    &self.customer_name_contains,
+                ),
+                (
+                    "customer_surname",
+                    "customer_surname",
+                    TextMatch::Exact,
+                    &self.customer_surname,
+                ),
+                (
+                    "customer_surname_prefix",
+                    "customer_surname",
+                    TextMatch::Prefix,
+                    &self.customer_surname_prefix,
+                ),
+                (
+                    "customer_surname_contains",
+                    "customer_surname",
+                    TextMatch::Contains,
+                    &self.customer_surname_contains,
+                ),
+            ])?,
+            time: vec![
+                TimeRange::of("created_at", self.created_after, self.created_before),
+                TimeRange::of("updated_at", self.updated_after, self.updated_before),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        })
+    }
+}
+
+/// A validated customer filter, every condition has to match.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CustomerFilter {
+    pub text: Vec<TextFilter>,
+    pub time: Vec<TimeRange>,
+}
+
+/// Filters of `GET /api/mongo`, see `TextMatch` for the text parameters and
+/// `TimeRange` for the `_after` and `_before` RFC 3339 timestamps. The creation
+/// time is compared to the second with the one embedded in the order id, which
+/// older orders without `created_at` have as well.
+#[derive(Debug, Deserialize, Default)]
+pub struct OrderFilterOptions {
+    pub customer_name: Option<String>,
+    pub customer_name_prefix: Option<String>,
+    pub customer_name_contains: Option<String>,
+    pub product_name: Option<String>,
+    pub product_name_prefix: Option<String>,
+    pub product_name_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+}
+
+impl OrderFilterOptions {
+    pub fn filter(&self) -> crate::Result<OrderFilter> {
+        Ok(OrderFilter {
+            text: text_filters(&[
+                (
+                    "customer_name",
+                    "customer_name",
+                    TextMatch::Exact,
+                    &self.customer_name,
+                ),
+                (
+                    "customer_name_prefix",
+                    "customer_name",
+                    TextMatch::Prefix,
+                    &self.customer_name_prefix,
+                ),
+                (
+                    "customer_name_contains",
+                    "customer_name",
+                    TextMatch::Contains,
+                    &self.customer_name_contains,
+                ),
+                (
+                    "product_name",
+                    "product_name",
+                    TextMatch::Exact,
+                    &self.product_name,
+                ),
+                (
+                    "product_name_prefix",
+                    "product_name",
+                    TextMatch::Prefix,
+                    &self.product_name_prefix,
+                ),
+                (
+                    "product_name_contains",
+                    "product_name",
+                    TextMatch::Contains,
+                    &self.product_name_contains,
+                ),
+            ])?,
+            created_after: self.created_after.map(time_to_object_id),
+            created_before: self.created_before.map(time_to_object_id),
+            time: TimeRange::of("updated_at", self.updated_after, self.updated_before)
+                .into_iter()
+                .collect(),
+        })
+    }
+}
+
+/// The smallest ObjectId generated at `time`, ObjectIds start with their
+/// creation time in seconds so ids created earlier sort before it.
+fn time_to_object_id(time: DateTime<Utc>) -> ObjectId {
+    let seconds = u32::try_from(time.timestamp().max(0)).unwrap_or(u32::MAX);
+    let mut bytes = [0; 12];
+    bytes[..4].copy_from_slice(&seconds.to_be_bytes());
+    ObjectId::from_bytes(bytes)
+}
+
+/// A validated order filter, every condition has to match. The creation
+/// range is expressed as bounds on the order id.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrderFilter {
+    pub text: Vec<TextFilter>,
+    /// Inclusive lower bound of the order id.
+    pub created_after: Option<ObjectId>,
+    /// Exclusive upper bound of the order id.
+    pub created_before: Option<ObjectId>,
+    pub time: Vec<TimeRange>,
+}
+
+/// Searches stop ranking after this many hits of each resource.
+pub const MAX_SEARCH_DEPTH: i64 = 1000;
+
+/// `?q=` of `GET /api/search`.
+#[derive(Debug, Deserialize, Default)]
+pub struct SearchOptions {
+    pub q: Option<String>,
+}
+
+impl SearchOptions {
+    /// The trimmed search text, which is required.
+    pub fn query(&self) -> crate::Result<&str> {
+        let query = match self.q.as_deref().map(str::trim) {
+            Some(query) if !query.is_empty() => query,
+            _ => return Err(Error::invalid_param("q", "is required")),
+        };
+        if query.chars().count() > MAX_FILTER_LENGTH {
+            return Err(Error::invalid_param(
+                "q",
+                format!("must be at most {} characters long", MAX_FILTER_LENGTH),
+            ));
+        }
+
+        Ok(query)
+    }
+}
+
+/// `?sort=field,-other` sorts by `field` ascending, then by `other` descending.
+#[derive(Debug, Deserialize, Default)]
+pub struct SortOptions {
+    pub sort: Option<String>,
+}
+
+/// Fields customers can be sorted by, `id` is the primary key.
+pub const CUSTOMER_SORT_FIELDS: &[&str] = &[
+    "id",
+    "customer_name",
+    "customer_surname",
+    "created_at",
+    "updated_at",
+];
+
+/// Fields orders can be sorted by, `id` follows the creation order.
+pub const ORDER_SORT_FIELDS: &[&str] = &[
+    "id",
+    "customer_name",
+    "product_name",
+    "created_at",
+    "updated_at",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: &'static str,
+    pub descending: bool,
+}
+
+/// A validated `?sort=`. The stores break the remaining ties on the primary
+/// key ascending, so an empty sort lists by primary key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sort {
+    pub keys: Vec<SortKey>,
+}
+
+impl Sort {
+    /// Ascending by a single field.
+    pub fn by(field: &'static str) -> Self {
+        Self {
+            keys: vec![SortKey {
+                field,
+                descending: false,
+            }],
+        }
+    }
+
+    /// Parses `?sort=`, fields outside of `allowed` or listed twice are rejected.
+    pub fn parse(
+        value: Option<&str>,
+        allowed: &[&'static str],
+        default: Self,
+    ) -> crate::Result<Self> {
+        let value = match value.map(str::trim) {
+            Some(value) if !value.is_empty() => value,
+            _ => return Ok(default),
+        };
+
+        let mut keys: Vec<SortKey> = Vec::new();
+        for part in value.split(',').map(str::trim) {
+            let (name, descending) = match part.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (part, false),
+            };
+            let field = allowed
+                .iter()
+                .find(|field| **field == name)
+                .ok_or_else(|| {
+                    Error::invalid_param(
+                        "sort",
+                        format!("'{}' is not one of {}", name, allowed.join(", ")),
+                    )
+                })?;
+            if keys.iter().any(|key| key.field == *field) {
+                return Err(Error::invalid_param(
+                    "sort",
+                    format!("'{}' is listed more than once", name),
+                ));
+            }
+
+            keys.push(SortKey { field, descending });
+        }
+
+        Ok(Self { keys })
+    }
+}
+
+/// Relations a customer can embed with `?expand=`.
+pub const CUSTOMER_EXPANSIONS: &[&str] = &["orders"];
+
+/// `?expand=relation,...` embeds related resources into the response.
+#[derive(Debug, Deserialize, Default)]
+pub struct ExpandOptions {
+    pub expand: Option<String>,
+}
+
+impl ExpandOptions {
+    /// Whether `relation` is requested, relations outside of `allowed` are rejected.
+    pub fn includes(&self, relation: &str, allowed: &[&str]) -> crate::Result<bool> {
+        let mut included = false;
+        for name in self.expand.iter().flat_map(|expand| expand.split(',')) {
+            let name = name.trim();
+            if !allowed.contains(&name) {
+                return Err(Error::invalid_param(
+                    "expand",
+                    format!("'{}' is not one of {}", name, allowed.join(", ")),
+                ));
+            }
+            included |= name == relation;
+        }
+
+        Ok(included)
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct DeleteOptions {
+    pub soft: Option<bool>,
+}
+
+pub const MAX_LINE_ITEMS: usize = 100;
+pub const MAX_QUANTITY: u32 = 1_000_000;
+pub const MAX_PRICE: u32 = 1_000_000_000;
+
+/// A product of an order, its name and price come from the catalog.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderItemSchema {
+    pub sku: String,
+    pub quantity: u32,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CreateOrderSchema {
-    pub customer_name: String,
+    /// Id of the ordering customer in Postgres.
+    pub customer_id: String,
+    /// At least one catalog product, the `product_name` of the order is
+    /// made of their names.
+    #[serde(default)]
+    pub items: Vec<OrderItemSchema>,
+    /// ISO 4217 code of the order.
+    pub currency: String,
+}
+
+impl Validate for CreateOrderSchema {
+    const RULES: &'static [FieldRules] = &[
+        FieldRules {
+            field: "customer_id",
+            rules: ID_RULES,
+        },
+        FieldRules {
+            field: "currency",
+            rules: CURRENCY_RULES,
+        },
+    ];
+
+    fn check(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        if self.currency.len() != 3 || !self.currency.chars().all(|c| c.is_ascii_uppercase()) {
+            errors.push(FieldError::new(
+                "currency",
+                "format",
+                "currency must be an ISO 4217 code such as EUR".to_string(),
+            ))
+        }
+
+        if self.items.is_empty() {
+            errors.push(FieldError::new(
+                "items",
+                "min_items",
+                "items must hold at least one line item".to_string(),
+            ));
+        } else if self.items.len() > MAX_LINE_ITEMS {
+            errors.push(FieldError::new(
+                "items",
+                "max_items",
+                format!("items must hold at most {} line items", MAX_LINE_ITEMS),
+            ));
+            return errors;
+        }
+
+        for (index, item) in self.items.iter().enumerate() {
+            let field = |name: &str| format!("items[{}].{}", index, name);
+
+            let sku = serde_json::Value::from(item.sku.as_str());
+            if let Some(error) = validate_field(&field("sku"), SKU_RULES, Some(&sku)) {
+                errors.push(error);
+            } else if self.items[..index]
+                .iter()
+                .any(|other| other.sku == item.sku)
+            {
+                let field = field("sku");
+                errors.push(FieldError::new(
+                    &field,
+                    "duplicate",
+                    format!("{} is already part of the order", field),
+                ));
+            }
+            if item.quantity == 0 || item.quantity > MAX_QUANTITY {
+                let field = field("quantity");
+                errors.push(FieldError::new(
+                    &field,
+                    "range",
+                    format!("{} must be between 1 and {}", field, MAX_QUANTITY),
+                ));
+            }
+        }
+
+        errors
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct UpdateOrderSchema {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub customer_id: Option<String>,
+}
+
+impl Validate for UpdateOrderSchema {
+    const RULES: &'static [FieldRules] = &[FieldRules {
+        field: "customer_id",
+        rules: ID_PATCH_RULES,
+    }];
+}
+
+fn check_price(price: Option<Decimal>, errors: &mut Vec<FieldError>) {
+    match price {
+        Some(price) if price < Decimal::ZERO || price > Decimal::from(MAX_PRICE) => {
+            errors.push(FieldError::new(
+                "price",
+                "range",
+                format!("price must be between 0 and {}", MAX_PRICE),
+            ))
+        }
+        Some(price) if price.normalize().scale() > PRICE_SCALE => errors.push(FieldError::new(
+            "price",
+            "scale",
+            format!("price must have at most {} decimal places", PRICE_SCALE),
+        )),
+        _ => {}
+    }
+}
+
+fn check_stock(stock: Option<i32>, errors: &mut Vec<FieldError>) {
+    if stock.is_some_and(|stock| stock < 0) {
+        errors.push(FieldError::new(
+            "stock",
+            "range",
+            "stock must not be negative".to_string(),
+        ));
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateProductSchema {
+    pub sku: String,
     pub product_name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Decimal string in the currency of the orders, e.g. `"1.99"`.
+    pub price: Decimal,
+    pub stock: i32,
+}
+
+impl Validate for CreateProductSchema {
+    const RULES: &'static [FieldRules] = &[
+        FieldRules {
+            field: "sku",
+            rules: SKU_RULES,
+        },
+        FieldRules {
+            field: "product_name",
+            rules: PRODUCT_RULES,
+        },
+        FieldRules {
+            field: "description",
+            rules: DESCRIPTION_RULES,
+        },
+        FieldRules {
+            field: "price",
+            rules: &[Rule::Required],
+        },
+        FieldRules {
+            field: "stock",
+            rules: &[Rule::Required],
+        },
+    ];
+
+    fn check(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        check_price(Some(self.price), &mut errors);
+        check_stock(Some(self.stock), &mut errors);
+        errors
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct UpdateProductSchema {
+    pub sku: Option<String>,
+    pub product_name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    pub description: Option<Option<String>>,
+    pub price: Option<Decimal>,
+    pub stock: Option<i32>,
+}
+
+impl Validate for UpdateProductSchema {
+    const RULES: &'static [FieldRules] = &[
+        FieldRules {
+            field: "sku",
+            rules: SKU_PATCH_RULES,
+        },
+        FieldRules {
+            field: "product_name",
+            rules: PRODUCT_PATCH_RULES,
+        },
+        FieldRules {
+            field: "description",
+            rules: DESCRIPTION_RULES,
+        },
+        FieldRules {
+            field: "price",
+            rules: &[Rule::NotNull],
+        },
+        FieldRules {
+            field: "stock",
+            rules: &[Rule::NotNull],
+        },
+    ];
+
+    fn check(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        check_price(self.price, &mut errors);
+        check_stock(self.stock, &mut errors);
+        errors
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransitionOrderSchema {
+    /// The status to move the order to.
+    pub status: OrderStatus,
+}
+
+impl Validate for TransitionOrderSchema {
+    const RULES: &'static [FieldRules] = &[FieldRules {
+        field: "status",
+        rules: &[Rule::Required],
+    }];
+}
+
+pub const MAX_ADDRESSES: usize = 10;
+
+/// A billing or shipping address of a customer, `country` is an ISO 3166-1
+/// alpha-2 code.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AddressSchema {
+    pub kind: AddressKind,
+    pub line1: String,
+    #[serde(default)]
+    pub line2: Option<String>,
+    pub city: String,
+    pub postal_code: String,
+    pub country: String,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+impl AddressSchema {
+    /// The addresses as they are stored: the first address of a kind is its
+    /// default unless another one of that kind is marked as the default.
+    pub fn with_defaults(addresses: &[Self]) -> Vec<Self> {
+        let mut addresses = addresses.to_vec();
+        for kind in [AddressKind::Billing, AddressKind::Shipping] {
+            let mut of_kind = addresses.iter_mut().filter(|address| address.kind == kind);
+            if let Some(first) = of_kind.next() {
+                if !first.is_default && !of_kind.any(|address| address.is_default) {
+                    first.is_default = true;
+                }
+            }
+        }
+        addresses
+    }
+}
+
+/// Emails are unique regardless of case, so they are stored lowercased.
+fn normalize_email(email: &str) -> String {
+    email.to_lowercase()
+}
+
+/// A deliberately loose check, the address is not verified to exist.
+fn check_email(email: Option<&str>, errors: &mut Vec<FieldError>) {
+    let email = match email {
+        Some(email) => email,
+        None => return,
+    };
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.split('.').count() > 1
+                && domain.split('.').all(|label| !label.is_empty())
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    };
+
+    if !valid {
+        errors.push(FieldError::new(
+            "email",
+            "format",
+            "email must be an address such as jane@example.com".to_string(),
+        ));
+    }
+}
+
+fn check_phone(phone: Option<&str>, errors: &mut Vec<FieldError>) {
+    let digits = phone.map(|phone| phone.chars().filter(char::is_ascii_digit).count());
+    if digits.is_some_and(|digits| !(7..=15).contains(&digits)) {
+        errors.push(FieldError::new(
+            "phone",
+            "format",
+            "phone must hold between 7 and 15 digits".to_string(),
+        ));
+    }
+}
+
+fn check_addresses(addresses: &[AddressSchema], errors: &mut Vec<FieldError>) {
+    if addresses.len() > MAX_ADDRESSES {
+        errors.push(FieldError::new(
+            "addresses",
+            "max_items",
+            format!("addresses must hold at most {} addresses", MAX_ADDRESSES),
+        ));
+        return;
+    }
+
+    for (index, address) in addresses.iter().enumerate() {
+        let field = |name: &str| format!("addresses[{}].{}", index, name);
+
+        for (name, rules, value) in [
+            ("line1", ADDRESS_LINE_RULES, Some(&address.line1)),
+            ("line2", ADDRESS_LINE_RULES, address.line2.as_ref()),
+            ("city", CITY_RULES, Some(&address.city)),
+            ("postal_code", POSTAL_CODE_RULES, Some(&address.postal_code)),
+        ] {
+            let value = value.map(|value| serde_json::Value::from(value.as_str()));
+            if let Some(error) = validate_field(&field(name), rules, value.as_ref()) {
+                errors.push(error);
+            }
+        }
+        if address.country.len() != 2 || !address.country.chars().all(|c| c.is_ascii_uppercase()) {
+            let field = field("country");
+            errors.push(FieldError::new(
+                &field,
+                "format",
+                format!("{} must be an ISO 3166-1 alpha-2 code such as GR", field),
+            ));
+        }
+        if address.is_default
+            && addresses[..index]
+                .iter()
+                .any(|other| other.is_default && other.kind == address.kind)
+        {
+            let field = field("is_default");
+            errors.push(FieldError::new(
+                &field,
+                "duplicate",
+                format!(
+                    "{} is set on more than one {} address",
+                    field,
+                    address.kind.as_ref()
+                ),
+            ));
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CreateCustomerSchema {
     pub customer_name: String,
     pub customer_surname: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<AddressSchema>,
+}
+
+impl CreateCustomerSchema {
+    pub fn normalized_email(&self) -> Option<String> {
+        self.email.as_deref().map(normalize_email)
+    }
+}
+
+impl Validate for CreateCustomerSchema {
+    const RULES: &'static [FieldRules] = &[
+        FieldRules {
+            field: "customer_name",
+            rules: NAME_RULES,
+        },
+        FieldRules {
+            field: "customer_surname",
+            rules: NAME_RULES,
+        },
+        FieldRules {
+            field: "email",
+            rules: EMAIL_RULES,
+        },
+        FieldRules {
+            field: "phone",
+            rules: PHONE_RULES,
+        },
+        FieldRules {
+            field: "addresses",
+            rules: &[Rule::NotNull],
+        },
+    ];
+
+    fn check(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        check_email(self.email.as_deref(), &mut errors);
+        check_phone(self.phone.as_deref(), &mut errors);
+        check_addresses(&self.addresses, &mut errors);
+        errors
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct UpdateCustomerSchema {
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    pub customer_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    pub customer_surname: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    pub email: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    pub phone: Option<Option<String>>,
+    /// Replaces every address of the customer when set.
+    pub addresses: Option<Vec<AddressSchema>>,
+}
+
+impl UpdateCustomerSchema {
+    pub fn normalized_email(&self) -> Option<Option<String>> {
+        self.email
+            .as_ref()
+            .map(|email| email.as_deref().map(normalize_email))
+    }
+}
+
+impl Validate for UpdateCustomerSchema {
+    const RULES: &'static [FieldRules] = &[
+        FieldRules {
+            field: "customer_name",
+            rules: NULLABLE_NAME_PATCH_RULES,
+        },
+        FieldRules {
+            field: "customer_surname",
+            rules: NULLABLE_NAME_PATCH_RULES,
+        },
+        FieldRules {
+            field: "email",
+            rules: EMAIL_RULES,
+        },
+        FieldRules {
+            field: "phone",
+            rules: PHONE_RULES,
+        },
+        FieldRules {
+            field: "addresses",
+            rules: &[Rule::NotNull],
+        },
+    ];
+
+    fn check(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        check_email(self.email.clone().flatten().as_deref(), &mut errors);
+        check_phone(self.phone.clone().flatten().as_deref(), &mut errors);
+        if let Some(addresses) = &self.addresses {
+            check_addresses(addresses, &mut errors);
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginSchema {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for LoginSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginSchema")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Validate for LoginSchema {
+    const RULES: &'static [FieldRules] = &[
+        FieldRules {
+            field: "username",
+            rules: &[Rule::Required, Rule::NotBlank, Rule::MaxLength(100)],
+        },
+        FieldRules {
+            field: "password",
+            rules: &[Rule::Required, Rule::NotBlank, Rule::MaxLength(1024)],
+        },
+    ];
+}
+
+/// Body of `/api/auth/refresh` and `/api/auth/logout` for clients that do not
+/// keep cookies, the `refresh_token` cookie is used otherwise.
+#[derive(Deserialize, Default)]
+pub struct RefreshSchema {
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateApiKeySchema {
+    pub name: String,
+    /// Who the key acts for, the caller minting it by default.
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// The key never expires when unset.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Validate for CreateApiKeySchema {
+    const RULES: &'static [FieldRules] = &[
+        FieldRules {
+            field: "name",
+            rules: &[
+                Rule::Required,
+                Rule::NotBlank,
+                Rule::MaxLength(100),
+                Rule::Charset(Charset::Text),
+            ],
+        },
+        FieldRules {
+            field: "owner",
+            rules: &[Rule::NotNull, Rule::NotBlank, Rule::MaxLength(100)],
+        },
+        FieldRules {
+            field: "scopes",
+            rules: &[Rule::NotNull],
+        },
+        FieldRules {
+            field: "expires_at",
+            rules: &[Rule::NotNull],
+        },
+    ];
+
+    fn check(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        for (index, scope) in self.scopes.iter().enumerate() {
+            if scope.is_empty()
+                || !scope
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || ":_-".contains(c))
+            {
+                let field = format!("scopes[{}]", index);
+                errors.push(FieldError::new(
+                    &field,
+                    "format",
+                    format!("{} must be a scope such as orders:read", field),
+                ));
+            }
+        }
+        if self
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            errors.push(FieldError::new(
+                "expires_at",
+                "past",
+                "expires_at must be in the future".to_string(),
+            ));
+        }
+        errors
+    }
 }
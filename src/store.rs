@@ -0,0 +1,646 @@
+use crate::model::{ApiKeyModel, LineItem, OrderStatus, RefreshTokenModel, UserModel};
+use crate::response::{
+    CustomerListResponse, CustomerResponse, DeleteOrderResponse, OrderListResponse, OrderResponse,
+    ProductListResponse, ProductResponse, SingleCustomerResponse, SingleOrderResponse,
+    SingleProductResponse,
+};
+use crate::schema::{
+    CreateCustomerSchema, CreateOrderSchema, CreateProductSchema, CustomerCursor, CustomerFilter,
+    OrderCursor, OrderFilter, OrderItemSchema, PageRequest, Sort, UpdateCustomerSchema,
+    UpdateOrderSchema, UpdateProductSchema,
+};
+use crate::tenant::Tenant;
+use crate::validation::FieldError;
+use crate::{mongo::MONGO, pg::PG, Error, Result};
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub type DynCustomerStore = Arc<dyn CustomerStore>;
+pub type DynOrderStore = Arc<dyn OrderStore>;
+pub type DynProductStore = Arc<dyn ProductStore>;
+pub type DynUserStore = Arc<dyn UserStore>;
+
+/// What happens to the orders of a customer when the customer is deleted,
+/// configured with `CUSTOMER_DELETE_POLICY`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CustomerDeletePolicy {
+    /// The delete fails with `409 Conflict` while the customer has orders.
+    #[default]
+    Reject,
+    /// The orders are deleted together with the customer.
+    Cascade,
+    /// The orders are kept and keep pointing at the deleted customer.
+    Orphan,
+}
+
+impl FromStr for CustomerDeletePolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "cascade" => Ok(Self::Cascade),
+            "orphan" => Ok(Self::Orphan),
+            _ => Err(Error::ConfigInvalid {
+                name: "CUSTOMER_DELETE_POLICY".to_string(),
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+/// Checks that the customer an order points at exists in the tenant of the
+/// order and returns the name the order records for it. An unknown or
+/// malformed id is a validation error on `customer_id` rather than a missing
+/// resource.
+pub async fn ensure_customer_exists<C>(
+    customers: &C,
+    tenant: &Tenant,
+    customer_id: &str,
+) -> Result<String>
+where
+    C: CustomerStore + ?Sized,
+{
+    match customers.get_customer(tenant, customer_id).await {
+        Ok(customer) => Ok(vec![customer.name, customer.surname]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ")),
+        Err(Error::NotFound { .. }) | Err(Error::SqlxUuid { .. }) => Err(Error::ValidationError {
+            errors: vec![FieldError::new(
+                "customer_id",
+                "unknown_customer",
+                format!("No customer found with id '{}'", customer_id),
+            )],
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `items` lists the same products and quantities as the line items
+/// of an order, in any order.
+pub fn same_items(line_items: &[LineItem], items: &[OrderItemSchema]) -> bool {
+    let mut stored: Vec<(&str, u32)> = line_items
+        .iter()
+        .map(|item| (item.sku.as_str(), item.quantity))
+        .collect();
+    let mut requested: Vec<(&str, u32)> = items
+        .iter()
+        .map(|item| (item.sku.as_str(), item.quantity))
+        .collect();
+    stored.sort_unstable();
+    requested.sort_unstable();
+    stored == requested
+}
+
+/// An order item that names no product in the catalog.
+pub fn unknown_product(index: usize, sku: &str) -> Error {
+    Error::ValidationError {
+        errors: vec![FieldError::new(
+            &format!("items[{}].sku", index),
+            "unknown_product",
+            format!("No product found with sku '{}'", sku),
+        )],
+    }
+}
+
+/// `if_match` holds the versions a write is conditional on, see `etag::if_match`.
+/// A write whose version does not match fails with `Error::PreconditionFailed`.
+/// Every method only sees the customers of `tenant`, those of another tenant
+/// are reported missing.
+#[async_trait]
+pub trait CustomerStore: Debug + Send + Sync {
+    async fn create_customer(
+        &self,
+        tenant: &Tenant,
+        body: &CreateCustomerSchema,
+    ) -> Result<SingleCustomerResponse>;
+
+    /// One page of the customers matching `filter`, ties of `sort` are broken by id.
+    async fn list_customers(
+        &self,
+        tenant: &Tenant,
+        filter: &CustomerFilter,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<CustomerListResponse>;
+
+    /// Up to `limit` customers sorted after `after`, from the first one without it.
+    async fn list_customers_after(
+        &self,
+        tenant: &Tenant,
+        filter: &CustomerFilter,
+        after: Option<&CustomerCursor>,
+        limit: i64,
+    ) -> Result<Vec<CustomerResponse>>;
+
+    /// Up to `limit` customers whose name or surname match `query`, best match
+    /// first, along with how many match in total.
+    async fn search_customers(
+        &self,
+        tenant: &Tenant,
+        query: &str,
+        limit: i64,
+    ) -> Result<(Vec<CustomerResponse>, u64)>;
+
+    async fn get_customer(&self, tenant: &Tenant, id: &str) -> Result<SingleCustomerResponse>;
+
+    async fn update_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &CreateCustomerSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse>;
+
+    async fn patch_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &UpdateCustomerSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse>;
+
+    async fn delete_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse>;
+}
+
+#[async_trait]
+pub trait ProductStore: Debug + Send + Sync {
+    async fn create_product(&self, body: &CreateProductSchema) -> Result<SingleProductResponse>;
+
+    /// One page of the catalog, sorted by SKU.
+    async fn list_products(&self, page: PageRequest) -> Result<ProductListResponse>;
+
+    async fn get_product(&self, id: &str) -> Result<SingleProductResponse>;
+
+    async fn get_product_by_sku(&self, sku: &str) -> Result<ProductResponse>;
+
+    async fn update_product(
+        &self,
+        id: &str,
+        body: &CreateProductSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse>;
+
+    async fn patch_product(
+        &self,
+        id: &str,
+        body: &UpdateProductSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse>;
+
+    async fn delete_product(
+        &self,
+        id: &str,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse>;
+
+    /// Takes the items of the order `order_id` out of stock and prices them,
+    /// replacing what was reserved for the order before. Either every item is
+    /// reserved or none, one above the stock fails with `Error::InsufficientStock`.
+    async fn reserve_stock(
+        &self,
+        order_id: &str,
+        items: &[OrderItemSchema],
+    ) -> Result<Vec<LineItem>>;
+
+    /// Puts the stock reserved for an order back, returns how many products it held.
+    async fn release_stock(&self, order_id: &str) -> Result<u64>;
+
+    /// Drops the reservation of a shipped order without putting its stock back.
+    async fn settle_stock(&self, order_id: &str) -> Result<u64>;
+}
+
+/// Accounts that sign in with a password, the refresh tokens issued to them and
+/// the API keys of machine clients.
+#[async_trait]
+pub trait UserStore: Debug + Send + Sync {
+    /// Fails with `Error::Conflict` when the username is taken. Users bound to
+    /// a `tenant` only ever act on it.
+    async fn create_user(
+        &self,
+        tenant: Option<&Tenant>,
+        username: &str,
+        password_hash: &str,
+        scope: &str,
+    ) -> Result<UserModel>;
+
+    async fn get_user(&self, id: Uuid) -> Result<Option<UserModel>>;
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<UserModel>>;
+
+    async fn insert_refresh_token(&self, token: &RefreshTokenModel) -> Result<()>;
+
+    /// Revokes a live refresh token and returns it, so that it can be used only
+    /// once. Presenting a token that was already revoked is taken as a sign it
+    /// leaked and revokes every token of its family.
+    async fn consume_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenModel>>;
+
+    /// Revokes every token of the family of `token_hash`, returns how many were live.
+    async fn revoke_refresh_family(&self, token_hash: &str) -> Result<u64>;
+
+    async fn insert_api_key(&self, key: &ApiKeyModel) -> Result<()>;
+
+    /// Every key of `tenant`, revoked and expired ones included, oldest first.
+    async fn list_api_keys(&self, tenant: &Tenant) -> Result<Vec<ApiKeyModel>>;
+
+    /// Revoking a key twice keeps the time of the first revocation.
+    async fn revoke_api_key(&self, tenant: &Tenant, id: &str) -> Result<ApiKeyModel>;
+
+    /// The live key with `key_hash`, its `last_used_at` is set to now.
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyModel>>;
+}
+
+/// Like `CustomerStore`, every method only sees the orders of `tenant`.
+#[async_trait]
+pub trait OrderStore: Debug + Send + Sync {
+    async fn create_order(
+        &self,
+        tenant: &Tenant,
+        body: &CreateOrderSchema,
+    ) -> Result<SingleOrderResponse>;
+
+    /// One page of the live orders matching `filter`, ties of `sort` are broken
+    /// by creation order.
+    async fn fetch_orders(
+        &self,
+        tenant: &Tenant,
+        filter: &OrderFilter,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse>;
+
+    /// Up to `limit` live orders created after `after`, from the first one without it.
+    async fn fetch_orders_after(
+        &self,
+        tenant: &Tenant,
+        filter: &OrderFilter,
+        after: Option<&OrderCursor>,
+        limit: i64,
+    ) -> Result<Vec<OrderResponse>>;
+
+    /// Up to `limit` live orders whose customer or product name match `query`,
+    /// best match first, along with how many match in total.
+    async fn search_orders(
+        &self,
+        tenant: &Tenant,
+        query: &str,
+        limit: i64,
+    ) -> Result<(Vec<OrderResponse>, u64)>;
+
+    async fn get_order(&self, tenant: &Tenant, id: &str) -> Result<SingleOrderResponse>;
+
+    /// Live orders of a customer, ties of `sort` are broken by creation order.
+    async fn fetch_customer_orders(
+        &self,
+        tenant: &Tenant,
+        customer_id: &str,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse>;
+
+    async fn edit_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &CreateOrderSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse>;
+
+    async fn patch_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &UpdateOrderSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse>;
+
+    /// Moves a live order to `status`, fails with `Error::InvalidTransition`
+    /// unless `OrderStatus::sources` allows it from the current status.
+    async fn transition_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        status: OrderStatus,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse>;
+
+    async fn delete_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        soft: bool,
+        if_match: Option<&[i32]>,
+    ) -> Result<DeleteOrderResponse>;
+
+    /// Number of live orders of a customer.
+    async fn count_customer_orders(&self, tenant: &Tenant, customer_id: &str) -> Result<u64>;
+
+    /// Deletes every order of a customer, soft deleted ones included, and
+    /// returns the ids of the removed orders.
+    async fn delete_customer_orders(
+        &self,
+        tenant: &Tenant,
+        customer_id: &str,
+    ) -> Result<Vec<String>>;
+}
+
+// The inherent methods on PG and MONGO carry the tracing and metrics
+// attributes, so the trait impls only forward to them.
+#[async_trait]
+impl CustomerStore for PG {
+    async fn create_customer(
+        &self,
+        tenant: &Tenant,
+        body: &CreateCustomerSchema,
+    ) -> Result<SingleCustomerResponse> {
+        PG::create_customer(self, tenant, body).await
+    }
+
+    async fn list_customers(
+        &self,
+        tenant: &Tenant,
+        filter: &CustomerFilter,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<CustomerListResponse> {
+        PG::list_customers(self, tenant, filter, page, sort).await
+    }
+
+    async fn list_customers_after(
+        &self,
+        tenant: &Tenant,
+        filter: &CustomerFilter,
+        after: Option<&CustomerCursor>,
+        limit: i64,
+    ) -> Result<Vec<CustomerResponse>> {
+        PG::list_customers_after(self, tenant, filter, after, limit).await
+    }
+
+    async fn search_customers(
+        &self,
+        tenant: &Tenant,
+        query: &str,
+        limit: i64,
+    ) -> Result<(Vec<CustomerResponse>, u64)> {
+        PG::search_customers(self, tenant, query, limit).await
+    }
+
+    async fn get_customer(&self, tenant: &Tenant, id: &str) -> Result<SingleCustomerResponse> {
+        PG::get_customer(self, tenant, id).await
+    }
+
+    async fn update_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &CreateCustomerSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse> {
+        PG::update_customer(self, tenant, id, body, if_match).await
+    }
+
+    async fn patch_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &UpdateCustomerSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse> {
+        PG::patch_customer(self, tenant, id, body, if_match).await
+    }
+
+    async fn delete_customer(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleCustomerResponse> {
+        PG::delete_customer(self, tenant, id, if_match).await
+    }
+}
+
+#[async_trait]
+impl ProductStore for PG {
+    async fn create_product(&self, body: &CreateProductSchema) -> Result<SingleProductResponse> {
+        PG::create_product(self, body).await
+    }
+
+    async fn list_products(&self, page: PageRequest) -> Result<ProductListResponse> {
+        PG::list_products(self, page).await
+    }
+
+    async fn get_product(&self, id: &str) -> Result<SingleProductResponse> {
+        PG::get_product(self, id).await
+    }
+
+    async fn get_product_by_sku(&self, sku: &str) -> Result<ProductResponse> {
+        PG::get_product_by_sku(self, sku).await
+    }
+
+    async fn update_product(
+        &self,
+        id: &str,
+        body: &CreateProductSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        PG::update_product(self, id, body, if_match).await
+    }
+
+    async fn patch_product(
+        &self,
+        id: &str,
+        body: &UpdateProductSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        PG::patch_product(self, id, body, if_match).await
+    }
+
+    async fn delete_product(
+        &self,
+        id: &str,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleProductResponse> {
+        PG::delete_product(self, id, if_match).await
+    }
+
+    async fn reserve_stock(
+        &self,
+        order_id: &str,
+        items: &[OrderItemSchema],
+    ) -> Result<Vec<LineItem>> {
+        PG::reserve_stock(self, order_id, items).await
+    }
+
+    async fn release_stock(&self, order_id: &str) -> Result<u64> {
+        PG::release_stock(self, order_id).await
+    }
+
+    async fn settle_stock(&self, order_id: &str) -> Result<u64> {
+        PG::settle_stock(self, order_id).await
+    }
+}
+
+#[async_trait]
+impl UserStore for PG {
+    async fn create_user(
+        &self,
+        tenant: Option<&Tenant>,
+        username: &str,
+        password_hash: &str,
+        scope: &str,
+    ) -> Result<UserModel> {
+        PG::create_user(self, tenant, username, password_hash, scope).await
+    }
+
+    async fn get_user(&self, id: Uuid) -> Result<Option<UserModel>> {
+        PG::get_user(self, id).await
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<UserModel>> {
+        PG::get_user_by_username(self, username).await
+    }
+
+    async fn insert_refresh_token(&self, token: &RefreshTokenModel) -> Result<()> {
+        PG::insert_refresh_token(self, token).await
+    }
+
+    async fn consume_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenModel>> {
+        PG::consume_refresh_token(self, token_hash).await
+    }
+
+    async fn revoke_refresh_family(&self, token_hash: &str) -> Result<u64> {
+        PG::revoke_refresh_family(self, token_hash).await
+    }
+
+    async fn insert_api_key(&self, key: &ApiKeyModel) -> Result<()> {
+        PG::insert_api_key(self, key).await
+    }
+
+    async fn list_api_keys(&self, tenant: &Tenant) -> Result<Vec<ApiKeyModel>> {
+        PG::list_api_keys(self, tenant).await
+    }
+
+    async fn revoke_api_key(&self, tenant: &Tenant, id: &str) -> Result<ApiKeyModel> {
+        PG::revoke_api_key(self, tenant, id).await
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyModel>> {
+        PG::use_api_key(self, key_hash).await
+    }
+}
+
+#[async_trait]
+impl OrderStore for MONGO {
+    async fn create_order(
+        &self,
+        tenant: &Tenant,
+        body: &CreateOrderSchema,
+    ) -> Result<SingleOrderResponse> {
+        MONGO::create_order(self, tenant, body).await
+    }
+
+    async fn fetch_orders(
+        &self,
+        tenant: &Tenant,
+        filter: &OrderFilter,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse> {
+        MONGO::fetch_orders(self, tenant, filter, page, sort).await
+    }
+
+    async fn fetch_orders_after(
+        &self,
+        tenant: &Tenant,
+        filter: &OrderFilter,
+        after: Option<&OrderCursor>,
+        limit: i64,
+    ) -> Result<Vec<OrderResponse>> {
+        MONGO::fetch_orders_after(self, tenant, filter, after, limit).await
+    }
+
+    async fn search_orders(
+        &self,
+        tenant: &Tenant,
+        query: &str,
+        limit: i64,
+    ) -> Result<(Vec<OrderResponse>, u64)> {
+        MONGO::search_orders(self, tenant, query, limit).await
+    }
+
+    async fn get_order(&self, tenant: &Tenant, id: &str) -> Result<SingleOrderResponse> {
+        MONGO::get_order(self, tenant, id).await
+    }
+
+    async fn fetch_customer_orders(
+        &self,
+        tenant: &Tenant,
+        customer_id: &str,
+        page: PageRequest,
+        sort: &Sort,
+    ) -> Result<OrderListResponse> {
+        MONGO::fetch_customer_orders(self, tenant, customer_id, page, sort).await
+    }
+
+    async fn edit_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &CreateOrderSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse> {
+        MONGO::edit_order(self, tenant, id, body, if_match).await
+    }
+
+    async fn patch_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        body: &UpdateOrderSchema,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse> {
+        MONGO::patch_order(self, tenant, id, body, if_match).await
+    }
+
+    async fn transition_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        status: OrderStatus,
+        if_match: Option<&[i32]>,
+    ) -> Result<SingleOrderResponse> {
+        MONGO::transition_order(self, tenant, id, status, if_match).await
+    }
+
+    async fn delete_order(
+        &self,
+        tenant: &Tenant,
+        id: &str,
+        soft: bool,
+        if_match: Option<&[i32]>,
+    ) -> Result<DeleteOrderResponse> {
+        MONGO::delete_order(self, tenant, id, soft, if_match).await
+    }
+
+    async fn count_customer_orders(&self, tenant: &Tenant, customer_id: &str) -> Result<u64> {
+        MONGO::count_customer_orders(self, tenant, customer_id).await
+    }
+
+    async fn delete_customer_orders(
+        &self,
+        tenant: &Tenant,
+        customer_id: &str,
+    ) -> Result<Vec<String>> {
+        MONGO::delete_customer_orders(self, tenant, customer_id).await
+    }
+}
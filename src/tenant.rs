@@ -0,0 +1,166 @@
+use crate::auth::Ctx;
+use crate::helper::Config;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, State};
+use axum::http::header::HOST;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// The tenant of requests that name none, and of the data stored before
+/// tenants were added.
+pub const DEFAULT_TENANT: &str = "default";
+/// Header naming the tenant a request acts on.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+/// Scope of the operators that may act on any tenant, callers without it
+/// that are bound to no tenant act on the default one.
+pub const ANY_TENANT_SCOPE: &str = "tenants:*";
+
+/// The tenant a request acts on, every customer and order belongs to one and
+/// the stores only ever read and write the data of the tenant they are given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tenant(String);
+
+impl Tenant {
+    /// Tenant ids are DNS labels so that they can double as subdomains.
+    pub fn parse(id: &str) -> Result<Self> {
+        let valid = (1..=63).contains(&id.len())
+            && id
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && !id.starts_with('-')
+            && !id.ends_with('-');
+        if !valid {
+            return Err(Error::invalid_param(
+                "tenant",
+                "must be a lowercase DNS label such as acme",
+            ));
+        }
+
+        Ok(Self(id.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.0 == DEFAULT_TENANT
+    }
+}
+
+impl Default for Tenant {
+    fn default() -> Self {
+        Self(DEFAULT_TENANT.to_string())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tenant
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        parts
+            .extensions
+            .get::<Tenant>()
+            .cloned()
+            .ok_or(Error::TenantNotInRequestExt)
+    }
+}
+
+/// Works out the tenant of a request, see `resolve`.
+#[derive(Clone, Debug, Default)]
+pub struct TenantResolver {
+    /// Requests to `<tenant>.<domain>` act on `<tenant>`.
+    domain: Option<String>,
+}
+
+impl TenantResolver {
+    pub fn with_domain(domain: &str) -> Self {
+        Self {
+            domain: Some(domain.trim_matches('.').to_lowercase()),
+        }
+    }
+
+    /// Reads the optional `TENANT_DOMAIN`, e.g. `api.example.com`.
+    pub fn from_config(config: &Config) -> Self {
+        match config.get_optional_config("TENANT_DOMAIN") {
+            Some(domain) => Self::with_domain(domain.trim()),
+            None => Self::default(),
+        }
+    }
+
+    /// The tenant in the `Host` header, `None` when it is not a subdomain of `domain`.
+    fn subdomain<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        let domain = self.domain.as_deref()?;
+        let host = headers.get(HOST)?.to_str().ok()?;
+        let host = host.split(':').next()?;
+
+        host.strip_suffix(domain)?
+            .strip_suffix('.')
+            .filter(|subdomain| !subdomain.contains('.'))
+    }
+
+    /// Credentials bound to a tenant, a `tenant` token claim or an API key
+    /// minted for one, only ever act on it. Unbound callers act on the
+    /// default tenant, unless they hold `tenants:*` and name another one in
+    /// the `X-Tenant-Id` header or the subdomain.
+    pub fn resolve(&self, ctx: &Ctx, headers: &HeaderMap) -> Result<Tenant> {
+        let requested = match headers.get(HeaderName::from_static(TENANT_HEADER)) {
+            Some(header) => Some(
+                header
+                    .to_str()
+                    .map_err(|_| Error::invalid_param("tenant", "must be a DNS label"))
+                    .and_then(|id| Tenant::parse(id.trim()))?,
+            ),
+            None => self.subdomain(headers).map(Tenant::parse).transpose()?,
+        };
+
+        let bound = ctx
+            .tenant()
+            .map(|id| {
+                Tenant::parse(id).map_err(|_| {
+                    tracing::info!("Rejected credentials bound to the invalid tenant {}", id);
+                    Error::AuthFailTokenWrongFormat
+                })
+            })
+            .transpose()?;
+
+        match (bound, requested) {
+            (Some(bound), Some(requested)) if bound != requested => {
+                Err(Error::AuthFailWrongTenant {
+                    tenant: requested.as_str().to_string(),
+                })
+            }
+            (None, Some(requested))
+                if !requested.is_default() && !ctx.has_scope(ANY_TENANT_SCOPE) =>
+            {
+                Err(Error::AuthFailWrongTenant {
+                    tenant: requested.as_str().to_string(),
+                })
+            }
+            (Some(tenant), _) | (None, Some(tenant)) => Ok(tenant),
+            (None, None) => Ok(Tenant::default()),
+        }
+    }
+}
+
+/// Puts the tenant of the request into its extensions, it runs once the
+/// caller is authenticated.
+pub async fn resolve_tenant<B>(
+    State(resolver): State<TenantResolver>,
+    ctx: Ctx,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response> {
+    let tenant = resolver.resolve(&ctx, request.headers())?;
+    tracing::info!("Acting on tenant {}", tenant.as_str());
+
+    request.extensions_mut().insert(tenant);
+    Ok(next.run(request).await)
+}
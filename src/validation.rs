@@ -0,0 +1,243 @@
+use crate::Error;
+use async_trait::async_trait;
+use axum::body::HttpBody;
+use axum::extract::{FromRequest, FromRequestParts, Query};
+use axum::http::request::Parts;
+use axum::http::Request;
+use axum::{BoxError, Json};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+/// A single rule applied to a field of a JSON request body.
+#[derive(Clone, Copy, Debug)]
+pub enum Rule {
+    /// The field must be present and not `null`.
+    Required,
+    /// The field may be omitted but must not be set to `null`.
+    NotNull,
+    /// The field must be a string that is not empty once trimmed.
+    NotBlank,
+    /// The field must be a string of at most this many characters.
+    MaxLength(usize),
+    /// Every character of the string must belong to the charset.
+    Charset(Charset),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Charset {
+    /// Letters, spaces, hyphens, apostrophes and dots, e.g. `Mary-Jane O'Neil`.
+    Name,
+    /// Letters, digits, spaces and common punctuation, e.g. `Apple (Granny Smith), 1kg`.
+    Text,
+    /// ASCII letters, digits, hyphens, underscores and dots, e.g. `APL-GS_1KG`.
+    Sku,
+    /// Digits, spaces and the characters `+-()`, e.g. `+30 (210) 123-4567`.
+    Phone,
+}
+
+impl Charset {
+    fn allows(&self, c: char) -> bool {
+        match self {
+            Self::Name => c.is_alphabetic() || " -'.".contains(c),
+            Self::Text => c.is_alphanumeric() || " -'.,&()/+#".contains(c),
+            Self::Sku => c.is_ascii_alphanumeric() || "-_.".contains(c),
+            Self::Phone => c.is_ascii_digit() || " +-()".contains(c),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::Name => "letters, spaces, hyphens, apostrophes and dots",
+            Self::Text => "letters, digits, spaces and the characters -'.,&()/+#",
+            Self::Sku => "ASCII letters, digits, hyphens, underscores and dots",
+            Self::Phone => "digits, spaces and the characters +-()",
+        }
+    }
+}
+
+/// The rules declared for one field of a request body.
+pub struct FieldRules {
+    pub field: &'static str,
+    pub rules: &'static [Rule],
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &str, message: String) -> Self {
+        Self {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        }
+    }
+}
+
+/// Request bodies declare their validation rules per field, they are checked
+/// by the `ValidJson` extractor before the body is deserialized.
+pub trait Validate {
+    const RULES: &'static [FieldRules];
+
+    /// Checks spanning several fields or nested values, run by `ValidJson` once
+    /// the body is deserialized.
+    fn check(&self) -> Vec<FieldError> {
+        Vec::new()
+    }
+}
+
+/// Checks `body` against `rules` and returns every failing field, at most one
+/// error per field.
+pub fn validate(rules: &[FieldRules], body: &Value) -> Vec<FieldError> {
+    rules
+        .iter()
+        .filter_map(|field_rules| {
+            validate_field(
+                field_rules.field,
+                field_rules.rules,
+                body.get(field_rules.field),
+            )
+        })
+        .collect()
+}
+
+/// Checks a single value against `rules`, `raw_value` is `None` when the field
+/// is omitted. `field` names the value in the error, e.g. `items[0].product_name`.
+pub fn validate_field(
+    field: &str,
+    rules: &[Rule],
+    raw_value: Option<&Value>,
+) -> Option<FieldError> {
+    let value = raw_value.filter(|value| !value.is_null());
+
+    for rule in rules {
+        let error = match (rule, value) {
+            (Rule::Required, None) => Some(FieldError::new(
+                field,
+                "required",
+                format!("{} is required", field),
+            )),
+            (Rule::NotNull, None) if raw_value.is_some() => Some(FieldError::new(
+                field,
+                "not_null",
+                format!("{} must not be null", field),
+            )),
+            // optional fields are only checked when they are set
+            (_, None) | (Rule::Required | Rule::NotNull, Some(_)) => None,
+            (_, Some(value)) if !value.is_string() => Some(FieldError::new(
+                field,
+                "type",
+                format!("{} must be a string", field),
+            )),
+            (Rule::NotBlank, Some(value)) => value
+                .as_str()
+                .filter(|value| value.trim().is_empty())
+                .map(|_| {
+                    FieldError::new(field, "not_blank", format!("{} must not be blank", field))
+                }),
+            (Rule::MaxLength(max), Some(value)) => value
+                .as_str()
+                .filter(|value| value.chars().count() > *max)
+                .map(|_| {
+                    FieldError::new(
+                        field,
+                        "max_length",
+                        format!("{} must be at most {} characters long", field, max),
+                    )
+                }),
+            (Rule::Charset(charset), Some(value)) => value
+                .as_str()
+                .filter(|value| !value.chars().all(|c| charset.allows(c)))
+                .map(|_| {
+                    FieldError::new(
+                        field,
+                        "charset",
+                        format!("{} may only contain {}", field, charset.description()),
+                    )
+                }),
+        };
+
+        if error.is_some() {
+            return error;
+        }
+    }
+
+    None
+}
+
+/// JSON extractor that validates the body against the `Validate` rules of `T`
+/// and reports rejections through `Error` instead of Axum's plain text responses.
+#[derive(Debug)]
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(body) = Json::<Value>::from_request(req, state)
+            .await
+            .map_err(|rejection| Error::JsonRejection {
+                status: rejection.status().as_u16(),
+                e: rejection.body_text(),
+            })?;
+
+        if !body.is_object() {
+            return Err(Error::JsonRejection {
+                status: 422,
+                e: "Request body must be a JSON object".to_string(),
+            });
+        }
+
+        let errors = validate(T::RULES, &body);
+        if !errors.is_empty() {
+            return Err(Error::ValidationError { errors });
+        }
+
+        let body: T = serde_json::from_value(body).map_err(|e| Error::JsonRejection {
+            status: 422,
+            e: e.to_string(),
+        })?;
+
+        let errors = body.check();
+        if !errors.is_empty() {
+            return Err(Error::ValidationError { errors });
+        }
+
+        Ok(ValidJson(body))
+    }
+}
+
+/// Query string extractor that rejects malformed parameters through `Error`
+/// instead of silently falling back to the defaults.
+#[derive(Debug)]
+pub struct ValidQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| Error::invalid_param("query", rejection.body_text()))?;
+
+        Ok(ValidQuery(query))
+    }
+}